@@ -0,0 +1,309 @@
+use edi_streamer::{StreamParser, Segment, EnvelopeState, BatchOptions, collect_directory, collect_glob, execute_batch_parser};
+use edi_streamer::{Redactor, RedactionRules, open_decompressed, execute_streaming_parser};
+use edi_streamer::{DiffCollector, DiffDocument, DiffOptions, diff_documents};
+use edi_streamer::{Query, QueryParser};
+use edi_streamer::StatsCollector;
+use std::fs::File;
+use std::io::{BufWriter, Error, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE : &str = "\
+usage: edi <command> [options]
+
+commands:
+  batch [--workers N] [--recursive] <file|directory|glob>...
+      parse every file and report segment and transaction counts
+  redact [--salt TEXT] [--output FILE] <file|->
+      replace names, addresses, dates of birth and member ids with
+      pseudonyms, keeping the envelopes and segment counts
+  diff [--ignore-delimiters] [--ignore-line-endings] [--ignore-control-numbers] <left> <right>
+      report added, removed and changed segments and elements
+  query <path> <file|->...
+      print the elements a path such as ST[01=834]/INS[01=Y]/NM1[01=IL]/09
+      selects, with their envelope and byte offset
+  stats [--json] [--largest N] <file|->
+      report segment tag counts, element fill rates, transaction sizes,
+      trading partners and versions";
+
+fn main() -> ExitCode {
+  let args : Vec<String> = std::env::args().skip(1).collect();
+  let res = match args.first().map(|a| a.as_str()) {
+    Some("batch") => run_batch(&args[1..]),
+    Some("redact") => run_redact(&args[1..]),
+    Some("diff") => run_diff(&args[1..]),
+    Some("query") => run_query(&args[1..]),
+    Some("stats") => run_stats(&args[1..]),
+    _ => Err(String::from(USAGE))
+  };
+  match res {
+    Ok(code) => code,
+    Err(message) => {
+      eprintln!("{}", message);
+      ExitCode::from(2)
+    }
+  }
+}
+
+type Options = Vec<(String, String)>;
+
+// Splits `--name value` options and bare arguments.  `flags` lists the
+// options that take no value.
+fn parse_options(args: &[String], flags: &[&str]) -> Result<(Options, Vec<String>), String> {
+  let mut options = Vec::new();
+  let mut rest = Vec::new();
+  let mut iter = args.iter();
+  while let Some(arg) = iter.next() {
+    match arg.strip_prefix("--") {
+      Some(name) if flags.contains(&name) => options.push((String::from(name), String::new())),
+      Some(name) => match iter.next() {
+        Some(value) => options.push((String::from(name), value.clone())),
+        None => return Err(format!("--{} needs a value\n\n{}", name, USAGE))
+      },
+      None => rest.push(arg.clone())
+    }
+  }
+  Ok((options, rest))
+}
+
+fn option<'a>(options: &'a [(String, String)], name: &str) -> Option<&'a str> {
+  options.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+}
+
+struct Counts {
+  state: EnvelopeState,
+  interchanges: u64,
+  functional_groups: u64,
+  transactions: u64
+}
+
+impl StreamParser for Counts {
+  fn segment(&mut self, _segment: &Segment) {
+
+  }
+
+  fn interchange_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    self.interchanges += 1;
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+  }
+
+  fn functional_group_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    self.functional_groups += 1;
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn transaction_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    self.transactions += 1;
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, _error: Error) {
+
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+fn expand_inputs(inputs: &[String], recursive: bool) -> Result<Vec<PathBuf>, String> {
+  let mut paths = Vec::new();
+  for input in inputs {
+    let path = Path::new(input);
+    let found = if path.is_dir() {
+      collect_directory(path, recursive)
+    } else if input.contains(['*', '?']) {
+      collect_glob(input)
+    } else {
+      Ok(vec![path.to_path_buf()])
+    };
+    paths.extend(found.map_err(|e| format!("{}: {}", input, e))?);
+  }
+  Ok(paths)
+}
+
+fn run_batch(args: &[String]) -> Result<ExitCode, String> {
+  let (options, inputs) = parse_options(args, &["recursive"])?;
+  if inputs.is_empty() {
+    return Err(String::from(USAGE));
+  }
+  let workers = match option(&options, "workers") {
+    None => 1,
+    Some(w) => w.parse().map_err(|_| format!("--workers must be a number, not {}", w))?
+  };
+  let paths = expand_inputs(&inputs, option(&options, "recursive").is_some())?;
+  let report = execute_batch_parser(&paths, &BatchOptions { workers }, |_path| Counts {
+    state: EnvelopeState::Nothing,
+    interchanges: 0,
+    functional_groups: 0,
+    transactions: 0
+  }).map_err(|e| e.to_string())?;
+  for file in report.files.iter() {
+    let counts = file.parser.as_ref().map(|c| (c.interchanges, c.functional_groups, c.transactions)).unwrap_or_default();
+    match &file.error {
+      None => println!("{}\tok\t{} segments\t{} interchanges\t{} groups\t{} transactions", file.path.display(), file.segments, counts.0, counts.1, counts.2),
+      Some(e) => println!("{}\terror\t{}", file.path.display(), e)
+    }
+  }
+  let failed = report.failed().count();
+  eprintln!("{} files, {} failed", report.files.len(), failed);
+  Ok(if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+// A file, or standard input for `-`.
+fn open_input(input: &str) -> Result<Box<dyn Read>, String> {
+  Ok(match input {
+    "-" => Box::new(std::io::stdin().lock()),
+    path => Box::new(File::open(path).map_err(|e| format!("{}: {}", path, e))?)
+  })
+}
+
+fn run_redact(args: &[String]) -> Result<ExitCode, String> {
+  let (options, inputs) = parse_options(args, &[])?;
+  let input = match inputs.as_slice() {
+    [input] => input,
+    _ => return Err(String::from(USAGE))
+  };
+  let reader = open_input(input)?;
+  let out : Box<dyn Write> = match option(&options, "output") {
+    None => Box::new(std::io::stdout().lock()),
+    Some(path) => Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?)
+  };
+  let rules = RedactionRules::healthcare(option(&options, "salt").unwrap_or("").as_bytes());
+  let mut source = open_decompressed(reader).map_err(|e| format!("{}: {}", input, e))?;
+  let mut pi = source.streamer();
+  let mut redactor = Redactor::new(BufWriter::new(out), rules);
+  execute_streaming_parser(&mut pi, &mut redactor);
+  let redacted = redactor.redacted();
+  match redactor.finish() {
+    Ok(_) => {
+      eprintln!("{} segments redacted", redacted);
+      Ok(ExitCode::SUCCESS)
+    },
+    Err(e) => {
+      eprintln!("{}: {}", input, e);
+      Ok(ExitCode::FAILURE)
+    }
+  }
+}
+
+fn diff_document(input: &str) -> Result<DiffDocument, String> {
+  let mut source = open_decompressed(open_input(input)?).map_err(|e| format!("{}: {}", input, e))?;
+  let mut pi = source.streamer();
+  let mut collector = DiffCollector::new();
+  execute_streaming_parser(&mut pi, &mut collector);
+  collector.into_document().map_err(|e| format!("{}: {}", input, e))
+}
+
+fn run_diff(args: &[String]) -> Result<ExitCode, String> {
+  let (options, inputs) = parse_options(args, &["ignore-delimiters", "ignore-line-endings", "ignore-control-numbers"])?;
+  let (left, right) = match inputs.as_slice() {
+    [left, right] => (left, right),
+    _ => return Err(String::from(USAGE))
+  };
+  let options = DiffOptions {
+    ignore_delimiters: option(&options, "ignore-delimiters").is_some(),
+    ignore_line_endings: option(&options, "ignore-line-endings").is_some(),
+    ignore_control_numbers: option(&options, "ignore-control-numbers").is_some()
+  };
+  let documents = diff_document(left).and_then(|l| diff_document(right).map(|r| (l, r)));
+  let (left, right) = match documents {
+    Ok(d) => d,
+    Err(message) => {
+      eprintln!("{}", message);
+      return Ok(ExitCode::from(2));
+    }
+  };
+  let differences = diff_documents(&left, &right, &options);
+  for difference in differences.iter() {
+    println!("{}", difference);
+  }
+  Ok(if differences.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn run_query(args: &[String]) -> Result<ExitCode, String> {
+  let (_options, rest) = parse_options(args, &[])?;
+  let (path, inputs) = match rest.split_first() {
+    Some((path, inputs)) if !inputs.is_empty() => (path, inputs),
+    _ => return Err(String::from(USAGE))
+  };
+  let query = Query::parse(path).map_err(|e| e.to_string())?;
+  let mut matched = false;
+  let mut failed = false;
+  for input in inputs {
+    let found = open_input(input)
+      .and_then(|r| open_decompressed(r).map_err(|e| format!("{}: {}", input, e)))
+      .and_then(|mut source| {
+        let mut pi = source.streamer();
+        let mut parser = QueryParser::new(query.clone(), Vec::new());
+        execute_streaming_parser(&mut pi, &mut parser);
+        parser.finish().map_err(|e| format!("{}: {}", input, e))
+      });
+    match found {
+      Ok(found) => for m in found {
+        matched = true;
+        let text = |v: &Option<String>| v.clone().unwrap_or_default();
+        let element = m.element.map(|e| format!("{:02}", e)).unwrap_or_default();
+        println!("{}:{}:{}\t{}\t{}/{}/{}/{}\t{}{}\t{}", input, m.line, m.column, m.start_offset,
+          text(&m.interchange_control_number), text(&m.group_control_number),
+          text(&m.transaction_set_id), text(&m.transaction_control_number), m.tag, element, m.value);
+      },
+      Err(message) => {
+        eprintln!("{}", message);
+        failed = true;
+      }
+    }
+  }
+  Ok(if failed { ExitCode::from(2) } else if matched { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn run_stats(args: &[String]) -> Result<ExitCode, String> {
+  let (options, inputs) = parse_options(args, &["json"])?;
+  let input = match inputs.as_slice() {
+    [input] => input,
+    _ => return Err(String::from(USAGE))
+  };
+  let largest = match option(&options, "largest") {
+    None => 10,
+    Some(n) => n.parse().map_err(|_| format!("--largest must be a number, not {}", n))?
+  };
+  let mut source = open_decompressed(open_input(input)?).map_err(|e| format!("{}: {}", input, e))?;
+  let mut pi = source.streamer();
+  let mut collector = StatsCollector::new(largest);
+  execute_streaming_parser(&mut pi, &mut collector);
+  let report = match collector.finish() {
+    Ok(r) => r,
+    Err(e) => {
+      eprintln!("{}: {}", input, e);
+      return Ok(ExitCode::FAILURE);
+    }
+  };
+  let mut out = std::io::stdout().lock();
+  let written = if option(&options, "json").is_some() { report.write_json(&mut out) } else { report.write_text(&mut out) };
+  written.map_err(|e| e.to_string())?;
+  Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,299 @@
+use crate::edi_segments::{Segment, ParserConfig, ParserState, PState, new_parser_config, new_parser_state, step_byte, step_eof, check_limits};
+use crate::edi_limits::{ParserLimits, LimitState};
+use crate::edi_tag_rules::SegmentTagRule;
+use crate::edi_delimiters::{DelimiterResult, detect_delimiters_in_prefix, DELIMITER_PREFIX_LENGTH};
+use crate::edi_parsers::{EnvelopeEvent, segment_events, closing_events};
+use futures_core::Stream;
+use futures_io::AsyncRead;
+use std::future::poll_fn;
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const READ_BUFFER_LENGTH : usize = 8192;
+
+pub struct AsyncSegmentStream<R: AsyncRead + Unpin> {
+  source: R,
+  buffer: Vec<u8>,
+  position: usize,
+  filled: usize,
+  parser_config: ParserConfig,
+  parser_state: ParserState,
+  limits: Option<LimitState>
+}
+
+// Delimiters are detected from a buffered prefix of the source rather than by
+// seeking, and that prefix is then fed to the tokenizer before the rest of the
+// source is read.
+pub async fn create_async_edi_streamer<R: AsyncRead + Unpin>(source: R) -> Result<AsyncSegmentStream<R>, Error> {
+  create_async_edi_streamer_with_tag_rule(source, SegmentTagRule::default()).await
+}
+
+// The tag rule is also used to find where the ISA's terminator ends.
+pub async fn create_async_edi_streamer_with_tag_rule<R: AsyncRead + Unpin>(mut source: R, tag_rule: SegmentTagRule) -> Result<AsyncSegmentStream<R>, Error> {
+  let mut buffer = vec![0; READ_BUFFER_LENGTH.max(DELIMITER_PREFIX_LENGTH)];
+  let mut filled = 0;
+  while filled < DELIMITER_PREFIX_LENGTH {
+    let size = poll_fn(|cx| Pin::new(&mut source).poll_read(cx, &mut buffer[filled..])).await?;
+    if size == 0 {
+      break;
+    }
+    filled += size;
+  }
+  let delimiters = match detect_delimiters_in_prefix(&buffer[0..filled], &tag_rule) {
+    DelimiterResult::DelimiterReadError(e) => return Err(e),
+    DelimiterResult::DelimitersFound(d) => d
+  };
+  let stream = AsyncSegmentStream {
+    source,
+    buffer,
+    position: 0,
+    filled,
+    parser_config: new_parser_config(delimiters.element_delimiter, delimiters.segment_delimiter),
+    parser_state: new_parser_state(),
+    limits: None
+  };
+  Ok(stream.with_tag_rule(tag_rule))
+}
+
+impl<R: AsyncRead + Unpin> AsyncSegmentStream<R> {
+  pub fn with_limits(mut self, limits: ParserLimits) -> Self {
+    self.limits = Some(LimitState::new(limits));
+    self
+  }
+
+  pub fn with_tag_rule(mut self, tag_rule: SegmentTagRule) -> Self {
+    self.parser_config.tag_rule = tag_rule;
+    self
+  }
+
+  pub async fn next_segment(&mut self) -> Option<Result<Segment, Error>> {
+    poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+  }
+}
+
+impl<R: AsyncRead + Unpin> Stream for AsyncSegmentStream<R> {
+  type Item = Result<Segment, Error>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    let this = self.get_mut();
+    loop {
+      match this.parser_state.state {
+        PState::Errored | PState::EOF => return Poll::Ready(None),
+        _ => ()
+      }
+      while this.position < this.filled {
+        let byte = this.buffer[this.position];
+        this.position += 1;
+        let output = step_byte(&this.parser_config, &mut this.parser_state, byte);
+        match check_limits(&mut this.limits, &this.parser_state, output) {
+          Ok(None) => (),
+          Ok(Some(seg)) => return Poll::Ready(Some(Ok(seg))),
+          Err(e) => {
+            this.parser_state.state = PState::Errored;
+            return Poll::Ready(Some(Err(e)));
+          }
+        }
+      }
+      match Pin::new(&mut this.source).poll_read(cx, &mut this.buffer) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Ok(0)) => {
+          let output = step_eof(&this.parser_config, &mut this.parser_state);
+          return match check_limits(&mut this.limits, &this.parser_state, output) {
+            Ok(output) => Poll::Ready(output.map(Ok)),
+            Err(e) => {
+              this.parser_state.state = PState::Errored;
+              Poll::Ready(Some(Err(e)))
+            }
+          };
+        },
+        Poll::Ready(Ok(size)) => {
+          this.position = 0;
+          this.filled = size;
+        },
+        Poll::Ready(Err(e)) => {
+          this.parser_state.state = PState::Errored;
+          return Poll::Ready(Some(Err(e)));
+        }
+      }
+    }
+  }
+}
+
+#[allow(async_fn_in_trait)]
+pub trait AsyncStreamParser {
+  async fn segment(&mut self, segment: &Segment);
+
+  async fn interchange_start(&mut self, segment: &Segment);
+  async fn interchange_end(&mut self, segment: Option<&Segment>);
+
+  async fn functional_group_start(&mut self, segment: &Segment);
+  async fn functional_group_end(&mut self, segment: Option<&Segment>);
+
+  async fn transaction_start(&mut self, segment: &Segment);
+  async fn transaction_end(&mut self, segment: Option<&Segment>);
+
+  async fn stream_end(&mut self);
+  async fn error(&mut self, error: Error);
+
+  fn in_interchange(&self) -> bool;
+  fn in_functional_group(&self) -> bool;
+  fn in_transaction(&self) -> bool;
+}
+
+pub async fn execute_async_streaming_parser<R: AsyncRead + Unpin, U: AsyncStreamParser>(stream: &mut AsyncSegmentStream<R>, stream_parser: &mut U) {
+  loop {
+    match stream.next_segment().await {
+      None => break,
+      Some(Err(e)) => {
+        stream_parser.error(e).await;
+        return;
+      },
+      Some(Ok(segment)) => {
+        let events = segment_events(stream_parser.in_transaction(), stream_parser.in_functional_group(), stream_parser.in_interchange(), segment.tag.as_slice());
+        for event in events {
+          apply_event(stream_parser, *event, Some(&segment)).await;
+        }
+      }
+    }
+  }
+  let events = closing_events(stream_parser.in_transaction(), stream_parser.in_functional_group(), stream_parser.in_interchange());
+  for event in events {
+    apply_event(stream_parser, *event, None).await;
+  }
+  stream_parser.stream_end().await;
+}
+
+async fn apply_event<U: AsyncStreamParser>(stream_parser: &mut U, event: EnvelopeEvent, segment: Option<&Segment>) {
+  match (event, segment) {
+    (EnvelopeEvent::Segment, Some(s)) => stream_parser.segment(s).await,
+    (EnvelopeEvent::InterchangeStart, Some(s)) => stream_parser.interchange_start(s).await,
+    (EnvelopeEvent::FunctionalGroupStart, Some(s)) => stream_parser.functional_group_start(s).await,
+    (EnvelopeEvent::TransactionStart, Some(s)) => stream_parser.transaction_start(s).await,
+    (EnvelopeEvent::InterchangeEnd(closing), s) => stream_parser.interchange_end(s.filter(|_| closing)).await,
+    (EnvelopeEvent::FunctionalGroupEnd(closing), s) => stream_parser.functional_group_end(s.filter(|_| closing)).await,
+    (EnvelopeEvent::TransactionEnd(closing), s) => stream_parser.transaction_end(s.filter(|_| closing)).await,
+    (_, None) => ()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{create_async_edi_streamer, create_async_edi_streamer_with_tag_rule, execute_async_streaming_parser, AsyncStreamParser};
+  use crate::edi_tag_rules::SegmentTagRule;
+  use crate::edi_segments::Segment;
+  use crate::edi_envelope::EnvelopeState;
+  use futures::executor::block_on;
+  use futures::io::Cursor;
+  use std::io::Error;
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SENDER*RECEIVER*20230101*1200*1*X*005010X220A1~
+ST*834*0001~
+BGN*00*1*20230101~
+SE*3*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+
+  struct EventLog {
+    state: EnvelopeState,
+    events: Vec<String>
+  }
+
+  impl AsyncStreamParser for EventLog {
+    async fn segment(&mut self, segment: &Segment) {
+      self.events.push(String::from_utf8_lossy(&segment.tag).to_string());
+    }
+
+    async fn interchange_start(&mut self, _segment: &Segment) {
+      self.state = EnvelopeState::InInterchange;
+      self.events.push(String::from("<interchange>"));
+    }
+
+    async fn interchange_end(&mut self, _segment: Option<&Segment>) {
+      self.state = EnvelopeState::Nothing;
+      self.events.push(String::from("</interchange>"));
+    }
+
+    async fn functional_group_start(&mut self, _segment: &Segment) {
+      self.state = EnvelopeState::InFunctionalGroup;
+    }
+
+    async fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+      self.state = EnvelopeState::InInterchange;
+    }
+
+    async fn transaction_start(&mut self, _segment: &Segment) {
+      self.state = EnvelopeState::InTransaction;
+      self.events.push(String::from("<transaction>"));
+    }
+
+    async fn transaction_end(&mut self, segment: Option<&Segment>) {
+      self.state = EnvelopeState::InFunctionalGroup;
+      self.events.push(format!("</transaction {}>", segment.is_some()));
+    }
+
+    async fn stream_end(&mut self) {
+
+    }
+
+    async fn error(&mut self, _error: Error) {
+
+    }
+
+    fn in_interchange(&self) -> bool {
+      self.state.in_interchange()
+    }
+
+    fn in_functional_group(&self) -> bool {
+      self.state.in_functional_group()
+    }
+
+    fn in_transaction(&self) -> bool {
+      self.state.in_transaction()
+    }
+  }
+
+  #[test]
+  fn streams_segments() {
+    let segments = block_on(async {
+      let mut stream = create_async_edi_streamer(Cursor::new(RAW.as_bytes())).await.unwrap();
+      let mut tags = Vec::new();
+      while let Some(res) = stream.next_segment().await {
+        tags.push(res.unwrap().tag);
+      }
+      tags
+    });
+    assert_eq!(segments.len(), 7);
+    assert_eq!(segments[6], b"IEA".to_vec());
+  }
+
+  #[test]
+  fn uses_the_tag_rule() {
+    let raw = "ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~\n1A*0123456789012345678901234567890123456789012345678901234567890123456789~\n2B*X~\nIEA*1*000000001~\n";
+    let tag_rule = SegmentTagRule::Custom(|c| c.iter().position(|b| b.is_ascii_alphanumeric()));
+    let tags = block_on(async {
+      let mut stream = create_async_edi_streamer_with_tag_rule(Cursor::new(raw.as_bytes()), tag_rule).await.unwrap();
+      let mut tags = Vec::new();
+      while let Some(res) = stream.next_segment().await {
+        tags.push(res.unwrap().tag);
+      }
+      tags
+    });
+    assert_eq!(tags, vec![b"ISA".to_vec(), b"1A".to_vec(), b"2B".to_vec(), b"IEA".to_vec()]);
+  }
+
+  #[test]
+  fn drives_async_handlers() {
+    let mut log = EventLog { state: EnvelopeState::Nothing, events: Vec::new() };
+    block_on(async {
+      let mut stream = create_async_edi_streamer(Cursor::new(RAW.as_bytes())).await.unwrap();
+      execute_async_streaming_parser(&mut stream, &mut log).await;
+    });
+    assert_eq!(log.events, vec![
+      "<interchange>", "ISA", "GS", "<transaction>", "ST", "BGN", "SE", "</transaction true>", "GE", "IEA", "</interchange>"
+    ]);
+  }
+}
@@ -0,0 +1,273 @@
+use crate::edi_parsers::{StreamParser, create_edi_streamer, consume_segment, complete_parsing};
+use crate::edi_compression::{Compression, detect_compression, open_decompressed};
+use crate::edi_segments::ParserIterator;
+use crate::edi_source::read_prefix;
+use std::fs::{self, File};
+use std::collections::HashSet;
+use std::io::{BufReader, Read, Seek, SeekFrom, Error};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+pub struct BatchOptions {
+  pub workers: usize
+}
+
+impl Default for BatchOptions {
+  fn default() -> Self {
+    BatchOptions {
+      workers: 1
+    }
+  }
+}
+
+// `parser` is `None` when the file could not be opened or its delimiters
+// could not be found.  Otherwise it holds the stream parser as it was when
+// the file ended or the first error was hit.
+pub struct FileReport<U> {
+  pub path: PathBuf,
+  pub parser: Option<U>,
+  pub error: Option<Error>,
+  pub segments: u64
+}
+
+impl<U> FileReport<U> {
+  pub fn is_ok(&self) -> bool {
+    self.error.is_none()
+  }
+}
+
+pub struct BatchReport<U> {
+  pub files: Vec<FileReport<U>>
+}
+
+impl<U> BatchReport<U> {
+  pub fn succeeded(&self) -> impl Iterator<Item = &FileReport<U>> {
+    self.files.iter().filter(|f| f.is_ok())
+  }
+
+  pub fn failed(&self) -> impl Iterator<Item = &FileReport<U>> {
+    self.files.iter().filter(|f| !f.is_ok())
+  }
+}
+
+// Files directly in `dir`, or in it and all of its subdirectories, sorted by
+// path.
+pub fn collect_directory(dir: &Path, recursive: bool) -> Result<Vec<PathBuf>, Error> {
+  let mut paths = Vec::new();
+  walk(dir, recursive, &mut HashSet::new(), &mut |p| paths.push(p.to_path_buf()))?;
+  paths.sort();
+  Ok(paths)
+}
+
+// Only the last part of the pattern may hold wildcards (`*` and `?`).  A
+// `**` directory just before it matches any number of subdirectories, so
+// `drop/**/*.edi` finds every `.edi` file under `drop`.
+pub fn collect_glob(pattern: &str) -> Result<Vec<PathBuf>, Error> {
+  let (dir, name) = match pattern.rfind('/') {
+    None => (".", pattern),
+    Some(i) => (&pattern[..i], &pattern[i + 1..])
+  };
+  let (dir, recursive) = match dir.strip_suffix("**") {
+    Some(d) => (d.trim_end_matches('/'), true),
+    None => (dir, false)
+  };
+  let dir = if dir.is_empty() { "." } else { dir };
+  let mut paths = Vec::new();
+  walk(Path::new(dir), recursive, &mut HashSet::new(), &mut |p| {
+    if p.file_name().map(|n| wildcard_match(name.as_bytes(), n.as_encoded_bytes())).unwrap_or(false) {
+      paths.push(p.to_path_buf());
+    }
+  })?;
+  paths.sort();
+  Ok(paths)
+}
+
+// Symbolic links to directories are followed, but each directory is only
+// walked once, so a link back up the tree does not loop.
+fn walk(dir: &Path, recursive: bool, visited: &mut HashSet<PathBuf>, found: &mut dyn FnMut(&Path)) -> Result<(), Error> {
+  if !visited.insert(fs::canonicalize(dir)?) {
+    return Ok(());
+  }
+  for entry in fs::read_dir(dir)? {
+    let path = entry?.path();
+    if path.is_dir() {
+      if recursive {
+        walk(&path, recursive, visited, found)?;
+      }
+    } else {
+      found(&path);
+    }
+  }
+  Ok(())
+}
+
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+  match (pattern.split_first(), name.split_first()) {
+    (None, None) => true,
+    (Some((b'*', rest)), _) => wildcard_match(rest, name) || (!name.is_empty() && wildcard_match(pattern, &name[1..])),
+    (Some((b'?', rest)), Some((_, name_rest))) => wildcard_match(rest, name_rest),
+    (Some((p, rest)), Some((n, name_rest))) if p == n => wildcard_match(rest, name_rest),
+    _ => false
+  }
+}
+
+// Parses each file with a stream parser made by `factory`.  Files are
+// shared between `options.workers` threads, and the report lists them in the
+// order given.  Errors are collected in the report rather than passed to the
+// stream parser's `error`.  Gzip and bzip2 files are decompressed when those
+// features are enabled.  A worker that panics fails the whole batch, since
+// the files it held would otherwise be missing from the report.
+pub fn execute_batch_parser<U, F>(paths: &[PathBuf], options: &BatchOptions, factory: F) -> Result<BatchReport<U>, Error>
+  where U: StreamParser + Send,
+        F: Fn(&Path) -> U + Sync {
+  let next = AtomicUsize::new(0);
+  let reports : Mutex<Vec<Option<FileReport<U>>>> = Mutex::new(paths.iter().map(|_| None).collect());
+  let (next_ref, reports_ref, factory_ref) = (&next, &reports, &factory);
+  let panicked = thread::scope(|scope| {
+    let workers : Vec<_> = (0..options.workers.max(1).min(paths.len().max(1))).map(|_| {
+      scope.spawn(move || loop {
+        let position = next_ref.fetch_add(1, Ordering::SeqCst);
+        let path = match paths.get(position) {
+          None => return,
+          Some(p) => p
+        };
+        let report = parse_file(path, factory_ref);
+        if let Ok(mut r) = reports_ref.lock() {
+          r[position] = Some(report);
+        }
+      })
+    }).collect();
+    workers.into_iter().filter_map(|w| w.join().err()).count()
+  });
+  if panicked > 0 {
+    return Err(Error::other(format!("{} batch workers panicked", panicked)));
+  }
+  let reports = reports.into_inner().map_err(|_| Error::other("the batch report was poisoned by a panicking worker"))?;
+  Ok(BatchReport { files: reports.into_iter().flatten().collect() })
+}
+
+fn parse_file<U: StreamParser, F: Fn(&Path) -> U>(path: &Path, factory: &F) -> FileReport<U> {
+  let mut report = FileReport {
+    path: path.to_path_buf(),
+    parser: None,
+    error: None,
+    segments: 0
+  };
+  if let Err(e) = open_and_parse(path, factory, &mut report) {
+    report.error = Some(e);
+  }
+  report
+}
+
+fn open_and_parse<U: StreamParser, F: Fn(&Path) -> U>(path: &Path, factory: &F, report: &mut FileReport<U>) -> Result<(), Error> {
+  let mut file = BufReader::new(File::open(path)?);
+  let magic = read_prefix(&mut file, 4)?;
+  file.seek(SeekFrom::Start(0))?;
+  match detect_compression(&magic) {
+    Compression::None => {
+      let mut pi = create_edi_streamer(&mut file)?;
+      run(&mut pi, factory(path), report)
+    },
+    _ => {
+      let mut source = open_decompressed(file)?;
+      run(&mut source.streamer(), factory(path), report)
+    }
+  }
+}
+
+fn run<T: Read, U: StreamParser>(pi: &mut ParserIterator<T>, mut stream_parser: U, report: &mut FileReport<U>) -> Result<(), Error> {
+  for pr in pi.by_ref() {
+    match pr {
+      Ok(segment) => {
+        report.segments += 1;
+        consume_segment(&mut stream_parser, &segment);
+      },
+      Err(e) => {
+        report.parser = Some(stream_parser);
+        return Err(e);
+      }
+    }
+  }
+  complete_parsing(&mut stream_parser);
+  report.parser = Some(stream_parser);
+  Ok(())
+}
+
+#[cfg(test)]
+mod test {
+  use super::{BatchOptions, collect_directory, collect_glob, execute_batch_parser, wildcard_match};
+  use crate::parser_impls::DefaultParser;
+  use std::fs;
+  use std::path::PathBuf;
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001~
+SE*2*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+
+  fn drop_folder(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("edi_batch_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    fs::write(dir.join("a.edi"), RAW).unwrap();
+    fs::write(dir.join("b.edi"), "not edi").unwrap();
+    fs::write(dir.join("notes.txt"), "ignored").unwrap();
+    fs::write(dir.join("nested").join("c.edi"), RAW).unwrap();
+    dir
+  }
+
+  #[test]
+  fn matches_wildcards() {
+    assert!(wildcard_match(b"*.edi", b"claims.edi"));
+    assert!(wildcard_match(b"a?c*", b"abc"));
+    assert!(!wildcard_match(b"*.edi", b"claims.txt"));
+  }
+
+  #[test]
+  fn reports_each_file() {
+    let dir = drop_folder("report");
+    assert_eq!(collect_directory(&dir, false).unwrap().len(), 3);
+    let pattern = format!("{}/**/*.edi", dir.display());
+    let paths = collect_glob(&pattern).unwrap();
+    assert_eq!(paths.len(), 3);
+    let report = execute_batch_parser(&paths, &BatchOptions { workers: 2 }, |_path| DefaultParser::new()).unwrap();
+    assert_eq!(report.files.len(), 3);
+    assert_eq!(report.files[0].path, dir.join("a.edi"));
+    assert_eq!(report.files[0].segments, 6);
+    assert_eq!(report.files[0].parser.as_ref().map(|p| p.transactions().count()), Some(1));
+    let failed : Vec<&PathBuf> = report.failed().map(|f| &f.path).collect();
+    assert_eq!(failed, vec![&dir.join("b.edi")]);
+    assert_eq!(report.succeeded().count(), 2);
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn fails_when_a_worker_panics() {
+    let dir = drop_folder("panic");
+    let paths = collect_directory(&dir, true).unwrap();
+    let result = execute_batch_parser(&paths, &BatchOptions { workers: 2 }, |path| {
+      if path.ends_with("a.edi") {
+        panic!("factory failed");
+      }
+      DefaultParser::new()
+    });
+    assert!(result.is_err());
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn walks_symlink_cycles_once() {
+    let dir = drop_folder("cycle");
+    std::os::unix::fs::symlink(&dir, dir.join("nested").join("loop")).unwrap();
+    let paths = collect_directory(&dir, true).unwrap();
+    assert_eq!(paths.len(), 4);
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,709 @@
+use crate::edi_parsers::{StreamParser, consume_segment, complete_parsing};
+use crate::edi_segments::{Segment, ParserIterator, PState, new_parser_config, new_parser_state, restore_segment_iterator};
+use crate::edi_envelope::EnvelopeState;
+use crate::edi_index::{write_u64, write_bytes, read_u64, read_bytes};
+use crate::edi_tag_rules::SegmentTagRule;
+use crate::edi_limits::{ParserLimits, LimitCounts, LimitState};
+use crate::edi_recovery::{RecoveryCheckpoint, RecoveryDiagnostic, RecoveryKind, RecoveryState};
+use std::io::{Read, Write, Seek, SeekFrom, Error, ErrorKind};
+
+const CHECKPOINT_MAGIC : [u8; 4] = [b'E', b'D', b'I', b'C'];
+const CHECKPOINT_VERSION : u64 = 4;
+
+// Read error kinds are written as their place in this list.  Others are
+// written as `Other`.
+const ERROR_KINDS : [ErrorKind; 20] = [
+  ErrorKind::Other,
+  ErrorKind::NotFound,
+  ErrorKind::PermissionDenied,
+  ErrorKind::ConnectionRefused,
+  ErrorKind::ConnectionReset,
+  ErrorKind::ConnectionAborted,
+  ErrorKind::NotConnected,
+  ErrorKind::AddrInUse,
+  ErrorKind::AddrNotAvailable,
+  ErrorKind::BrokenPipe,
+  ErrorKind::AlreadyExists,
+  ErrorKind::WouldBlock,
+  ErrorKind::InvalidInput,
+  ErrorKind::InvalidData,
+  ErrorKind::TimedOut,
+  ErrorKind::WriteZero,
+  ErrorKind::Interrupted,
+  ErrorKind::Unsupported,
+  ErrorKind::UnexpectedEof,
+  ErrorKind::OutOfMemory
+];
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TokenizerCheckpoint {
+  pub element_delimiter: Vec<u8>,
+  pub segment_delimiter: Vec<u8>,
+  pub byte_index: u64,
+  pub start_of_last_segment: u64,
+  pub segment_index: u64,
+  // How much of the segment terminator has been seen, zero inside a segment.
+  pub terminator_matched: u64,
+  pub current_string: Vec<u8>,
+  pub current_field: Vec<u8>,
+  pub current_segment: Vec<Vec<u8>>,
+  pub pending: Vec<u8>,
+  pub stray: Vec<u8>,
+  // (line, column) pairs for the next byte, the current segment and the
+  // first pending byte.
+  pub position: (u64, u64),
+  pub start_position: (u64, u64),
+  pub pending_position: (u64, u64),
+  pub tag_rule: SegmentTagRule,
+  // The limits and how far they had been counted, if limits were set.
+  pub limits: Option<(ParserLimits, LimitCounts)>,
+  // Set in recovery mode.
+  pub recovery: Option<RecoveryCheckpoint>
+}
+
+impl TokenizerCheckpoint {
+  pub fn capture<T: Read>(pi: &ParserIterator<T>) -> Self {
+    let pc = pi.parser_config();
+    let ps = pi.parser_state();
+    TokenizerCheckpoint {
+      element_delimiter: pc.element_delimiter.clone(),
+      segment_delimiter: pc.segment_delimiter.clone(),
+      byte_index: ps.byte_index,
+      start_of_last_segment: ps.start_of_last_segment,
+      segment_index: ps.segment_index,
+      terminator_matched: ps.terminator_matched as u64,
+      current_string: ps.current_string.clone(),
+      current_field: ps.current_field.clone(),
+      current_segment: ps.current_segment.clone(),
+      pending: ps.pending.clone(),
+      stray: ps.stray.clone(),
+      position: (ps.line, ps.column),
+      start_position: (ps.start_line, ps.start_column),
+      pending_position: (ps.pending_line, ps.pending_column),
+      tag_rule: pc.tag_rule,
+      limits: pi.limits().map(|l| (l.limits, l.counts)),
+      recovery: pi.recovery().map(|r| r.checkpoint())
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct EnvelopeCheckpoint {
+  pub state: EnvelopeState,
+  pub interchange_count: u64,
+  pub functional_group_count: u64,
+  pub transaction_count: u64,
+  pub segment_count: u64,
+  pub transaction_segment_count: u64,
+  pub interchange_control_number: Vec<u8>,
+  pub functional_group_control_number: Vec<u8>,
+  pub transaction_control_number: Vec<u8>
+}
+
+impl EnvelopeCheckpoint {
+  pub fn new() -> Self {
+    EnvelopeCheckpoint {
+      state: EnvelopeState::Nothing,
+      interchange_count: 0,
+      functional_group_count: 0,
+      transaction_count: 0,
+      segment_count: 0,
+      transaction_segment_count: 0,
+      interchange_control_number: Vec::new(),
+      functional_group_control_number: Vec::new(),
+      transaction_control_number: Vec::new()
+    }
+  }
+}
+
+impl Default for EnvelopeCheckpoint {
+  fn default() -> Self {
+    EnvelopeCheckpoint::new()
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Checkpoint {
+  pub tokenizer: TokenizerCheckpoint,
+  pub envelope: EnvelopeCheckpoint
+}
+
+impl Checkpoint {
+  pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+    let t = &self.tokenizer;
+    let e = &self.envelope;
+    let tag_rule = match t.tag_rule {
+      SegmentTagRule::X12TagGrammar => 0,
+      SegmentTagRule::AnyByte => 1,
+      SegmentTagRule::Custom(_) => return Err(Error::new(ErrorKind::InvalidInput, "a custom tag rule can not be written"))
+    };
+    w.write_all(&CHECKPOINT_MAGIC)?;
+    write_u64(w, CHECKPOINT_VERSION)?;
+    write_bytes(w, &t.element_delimiter)?;
+    write_bytes(w, &t.segment_delimiter)?;
+    write_u64(w, t.byte_index)?;
+    write_u64(w, t.start_of_last_segment)?;
+    write_u64(w, t.segment_index)?;
+    write_u64(w, t.terminator_matched)?;
+    write_bytes(w, &t.current_string)?;
+    write_bytes(w, &t.current_field)?;
+    write_u64(w, t.current_segment.len() as u64)?;
+    for f in t.current_segment.iter() {
+      write_bytes(w, f)?;
+    }
+    write_bytes(w, &t.pending)?;
+    write_bytes(w, &t.stray)?;
+    for (line, column) in [t.position, t.start_position, t.pending_position] {
+      write_u64(w, line)?;
+      write_u64(w, column)?;
+    }
+    write_u64(w, tag_rule)?;
+    write_limits(w, &t.limits)?;
+    write_recovery(w, &t.recovery)?;
+    let state = match e.state {
+      EnvelopeState::Nothing => 0,
+      EnvelopeState::InInterchange => 1,
+      EnvelopeState::InFunctionalGroup => 2,
+      EnvelopeState::InTransaction => 3
+    };
+    write_u64(w, state)?;
+    write_u64(w, e.interchange_count)?;
+    write_u64(w, e.functional_group_count)?;
+    write_u64(w, e.transaction_count)?;
+    write_u64(w, e.segment_count)?;
+    write_u64(w, e.transaction_segment_count)?;
+    write_bytes(w, &e.interchange_control_number)?;
+    write_bytes(w, &e.functional_group_control_number)?;
+    write_bytes(w, &e.transaction_control_number)
+  }
+
+  pub fn read_from<R: Read>(r: &mut R) -> Result<Checkpoint, Error> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != CHECKPOINT_MAGIC || read_u64(r)? != CHECKPOINT_VERSION {
+      return Err(Error::new(ErrorKind::InvalidData, "not an EDI checkpoint"));
+    }
+    let element_delimiter = read_bytes(r)?;
+    let segment_delimiter = read_bytes(r)?;
+    let byte_index = read_u64(r)?;
+    let start_of_last_segment = read_u64(r)?;
+    let segment_index = read_u64(r)?;
+    let terminator_matched = read_u64(r)?;
+    let current_string = read_bytes(r)?;
+    let current_field = read_bytes(r)?;
+    let mut current_segment = Vec::new();
+    for _ in 0..read_u64(r)? {
+      current_segment.push(read_bytes(r)?);
+    }
+    let pending = read_bytes(r)?;
+    let stray = read_bytes(r)?;
+    let position = (read_u64(r)?, read_u64(r)?);
+    let start_position = (read_u64(r)?, read_u64(r)?);
+    let pending_position = (read_u64(r)?, read_u64(r)?);
+    let tag_rule = match read_u64(r)? {
+      0 => SegmentTagRule::X12TagGrammar,
+      1 => SegmentTagRule::AnyByte,
+      _ => return Err(Error::new(ErrorKind::InvalidData, "unknown tag rule"))
+    };
+    let limits = read_limits(r)?;
+    let recovery = read_recovery(r)?;
+    let state = match read_u64(r)? {
+      0 => EnvelopeState::Nothing,
+      1 => EnvelopeState::InInterchange,
+      2 => EnvelopeState::InFunctionalGroup,
+      3 => EnvelopeState::InTransaction,
+      _ => return Err(Error::new(ErrorKind::InvalidData, "unknown envelope state"))
+    };
+    Ok(Checkpoint {
+      tokenizer: TokenizerCheckpoint {
+        element_delimiter,
+        segment_delimiter,
+        byte_index,
+        start_of_last_segment,
+        segment_index,
+        terminator_matched,
+        current_string,
+        current_field,
+        current_segment,
+        pending,
+        stray,
+        position,
+        start_position,
+        pending_position,
+        tag_rule,
+        limits,
+        recovery
+      },
+      envelope: EnvelopeCheckpoint {
+        state,
+        interchange_count: read_u64(r)?,
+        functional_group_count: read_u64(r)?,
+        transaction_count: read_u64(r)?,
+        segment_count: read_u64(r)?,
+        transaction_segment_count: read_u64(r)?,
+        interchange_control_number: read_bytes(r)?,
+        functional_group_control_number: read_bytes(r)?,
+        transaction_control_number: read_bytes(r)?
+      }
+    })
+  }
+}
+
+fn write_option<W: Write>(w: &mut W, value: Option<u64>) -> Result<(), Error> {
+  match value {
+    None => write_u64(w, 0),
+    Some(v) => {
+      write_u64(w, 1)?;
+      write_u64(w, v)
+    }
+  }
+}
+
+fn read_option<R: Read>(r: &mut R) -> Result<Option<u64>, Error> {
+  match read_u64(r)? {
+    0 => Ok(None),
+    _ => read_u64(r).map(Some)
+  }
+}
+
+// Zero for none, otherwise one more than the place in `ERROR_KINDS`.
+fn error_kind_code(kind: Option<ErrorKind>) -> u64 {
+  kind.map_or(0, |k| ERROR_KINDS.iter().position(|e| *e == k).unwrap_or(0) as u64 + 1)
+}
+
+fn error_kind(code: u64) -> Option<ErrorKind> {
+  code.checked_sub(1).map(|c| ERROR_KINDS.get(c as usize).copied().unwrap_or(ErrorKind::Other))
+}
+
+fn write_limits<W: Write>(w: &mut W, limits: &Option<(ParserLimits, LimitCounts)>) -> Result<(), Error> {
+  let (limits, counts) = match limits {
+    None => return write_u64(w, 0),
+    Some(l) => l
+  };
+  write_u64(w, 1)?;
+  write_option(w, limits.max_segment_bytes)?;
+  write_option(w, limits.max_elements_per_segment)?;
+  write_option(w, limits.max_segments_per_transaction)?;
+  write_option(w, limits.max_transactions_per_group)?;
+  write_option(w, limits.max_total_bytes)?;
+  write_u64(w, counts.in_transaction as u64)?;
+  write_u64(w, counts.transaction_segments)?;
+  write_u64(w, counts.group_transactions)
+}
+
+fn read_limits<R: Read>(r: &mut R) -> Result<Option<(ParserLimits, LimitCounts)>, Error> {
+  if read_u64(r)? == 0 {
+    return Ok(None);
+  }
+  let limits = ParserLimits {
+    max_segment_bytes: read_option(r)?,
+    max_elements_per_segment: read_option(r)?,
+    max_segments_per_transaction: read_option(r)?,
+    max_transactions_per_group: read_option(r)?,
+    max_total_bytes: read_option(r)?
+  };
+  let counts = LimitCounts {
+    in_transaction: read_u64(r)? != 0,
+    transaction_segments: read_u64(r)?,
+    group_transactions: read_u64(r)?
+  };
+  Ok(Some((limits, counts)))
+}
+
+fn write_recovery<W: Write>(w: &mut W, recovery: &Option<RecoveryCheckpoint>) -> Result<(), Error> {
+  let recovery = match recovery {
+    None => return write_u64(w, 0),
+    Some(r) => r
+  };
+  write_u64(w, 1)?;
+  match &recovery.region {
+    None => write_u64(w, 0)?,
+    Some(d) => {
+      write_u64(w, 1)?;
+      write_u64(w, match d.kind {
+        RecoveryKind::MalformedSegment => 0,
+        RecoveryKind::ReadError(k) => error_kind_code(Some(k))
+      })?;
+      write_u64(w, d.start_offset)?;
+      write_u64(w, d.end_offset)?;
+      write_u64(w, d.segment_index)?;
+      write_u64(w, d.line)?;
+      write_u64(w, d.column)?;
+      write_bytes(w, &d.skipped)?;
+    }
+  }
+  write_u64(w, error_kind_code(recovery.damaged))?;
+  write_u64(w, recovery.consecutive_errors as u64)
+}
+
+fn read_recovery<R: Read>(r: &mut R) -> Result<Option<RecoveryCheckpoint>, Error> {
+  if read_u64(r)? == 0 {
+    return Ok(None);
+  }
+  let region = match read_u64(r)? {
+    0 => None,
+    _ => Some(RecoveryDiagnostic {
+      kind: error_kind(read_u64(r)?).map_or(RecoveryKind::MalformedSegment, RecoveryKind::ReadError),
+      start_offset: read_u64(r)?,
+      end_offset: read_u64(r)?,
+      segment_index: read_u64(r)?,
+      line: read_u64(r)?,
+      column: read_u64(r)?,
+      skipped: read_bytes(r)?
+    })
+  };
+  Ok(Some(RecoveryCheckpoint {
+    region,
+    damaged: error_kind(read_u64(r)?),
+    consecutive_errors: read_u64(r)? as u32
+  }))
+}
+
+// Wraps a `StreamParser`, keeping the envelope state itself so that it can
+// be captured and restored.  Any state of the wrapped parser is its own
+// business - restore it before resuming.
+pub struct CheckpointingParser<U: StreamParser> {
+  inner: U,
+  envelope: EnvelopeCheckpoint
+}
+
+impl<U: StreamParser> CheckpointingParser<U> {
+  pub fn new(inner: U) -> Self {
+    CheckpointingParser {
+      inner,
+      envelope: EnvelopeCheckpoint::new()
+    }
+  }
+
+  pub fn resume(inner: U, envelope: EnvelopeCheckpoint) -> Self {
+    CheckpointingParser {
+      inner,
+      envelope
+    }
+  }
+
+  pub fn envelope(&self) -> &EnvelopeCheckpoint {
+    &self.envelope
+  }
+
+  pub fn inner(&self) -> &U {
+    &self.inner
+  }
+
+  pub fn inner_mut(&mut self) -> &mut U {
+    &mut self.inner
+  }
+
+  pub fn into_inner(self) -> U {
+    self.inner
+  }
+}
+
+fn element(segment: &Segment, index: usize) -> Vec<u8> {
+  segment.fields.get(index).cloned().unwrap_or_default()
+}
+
+impl<U: StreamParser> StreamParser for CheckpointingParser<U> {
+  fn segment(&mut self, segment: &Segment) {
+    self.envelope.segment_count += 1;
+    if self.envelope.state.in_transaction() {
+      self.envelope.transaction_segment_count += 1;
+    }
+    self.inner.segment(segment);
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.envelope.state = EnvelopeState::InInterchange;
+    self.envelope.interchange_count += 1;
+    self.envelope.interchange_control_number = element(segment, 13);
+    self.inner.interchange_start(segment);
+  }
+
+  fn interchange_end(&mut self, segment: Option<&Segment>) {
+    self.envelope.state = EnvelopeState::Nothing;
+    self.envelope.interchange_control_number.clear();
+    self.inner.interchange_end(segment);
+  }
+
+  fn functional_group_start(&mut self, segment: &Segment) {
+    self.envelope.state = EnvelopeState::InFunctionalGroup;
+    self.envelope.functional_group_count += 1;
+    self.envelope.functional_group_control_number = element(segment, 6);
+    self.inner.functional_group_start(segment);
+  }
+
+  fn functional_group_end(&mut self, segment: Option<&Segment>) {
+    self.envelope.state = EnvelopeState::InInterchange;
+    self.envelope.functional_group_control_number.clear();
+    self.inner.functional_group_end(segment);
+  }
+
+  fn transaction_start(&mut self, segment: &Segment) {
+    self.envelope.state = EnvelopeState::InTransaction;
+    self.envelope.transaction_count += 1;
+    self.envelope.transaction_segment_count = 0;
+    self.envelope.transaction_control_number = element(segment, 2);
+    self.inner.transaction_start(segment);
+  }
+
+  fn transaction_end(&mut self, segment: Option<&Segment>) {
+    self.envelope.state = EnvelopeState::InFunctionalGroup;
+    self.envelope.transaction_control_number.clear();
+    self.inner.transaction_end(segment);
+  }
+
+  fn stream_end(&mut self) {
+    self.inner.stream_end();
+  }
+
+  fn error(&mut self, error: Error) {
+    self.inner.error(error);
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.envelope.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.envelope.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.envelope.state.in_transaction()
+  }
+}
+
+// Behaves like `execute_streaming_parser`, handing a checkpoint to
+// `on_checkpoint` after every `interval` segments.
+pub fn execute_checkpointed_parser<T: Read, U: StreamParser, F: FnMut(Checkpoint)>(parser_iterator: &mut ParserIterator<T>, stream_parser: &mut CheckpointingParser<U>, interval: u64, mut on_checkpoint: F) {
+  let interval = interval.max(1);
+  let mut since_checkpoint = 0;
+  let mut pr : Option<Result<Segment, Error>> = parser_iterator.next();
+  while let Some(res) = pr {
+    match res {
+      Err(e) => {
+        stream_parser.error(e);
+        return;
+      },
+      Ok(segment) => consume_segment(stream_parser, &segment)
+    }
+    since_checkpoint += 1;
+    if since_checkpoint >= interval {
+      since_checkpoint = 0;
+      on_checkpoint(Checkpoint {
+        tokenizer: TokenizerCheckpoint::capture(parser_iterator),
+        envelope: stream_parser.envelope.clone()
+      });
+    }
+    pr = parser_iterator.next();
+  }
+  complete_parsing(stream_parser);
+}
+
+// Seeks `ioish` back to where the checkpoint was taken and rebuilds the
+// tokenizer from it, with the tag rule, limits and recovery mode it had.
+// Byte offsets are relative to the start of `ioish`.
+#[allow(clippy::needless_lifetimes)]
+pub fn resume_edi_streamer<'a, T: Read + Seek>(ioish: &'a mut T, checkpoint: &TokenizerCheckpoint) -> Result<ParserIterator<'a, T>, Error> {
+  ioish.seek(SeekFrom::Start(checkpoint.byte_index))?;
+  let mut pc = new_parser_config(checkpoint.element_delimiter.clone(), checkpoint.segment_delimiter.clone());
+  pc.tag_rule = checkpoint.tag_rule;
+  let mut ps = new_parser_state();
+  ps.byte_index = checkpoint.byte_index;
+  ps.start_of_last_segment = checkpoint.start_of_last_segment;
+  ps.segment_index = checkpoint.segment_index;
+  ps.terminator_matched = checkpoint.terminator_matched as usize;
+  ps.state = match ps.terminator_matched {
+    0 => PState::InField,
+    n if n < pc.segment_delimiter.len() => PState::InSegTerm,
+    _ => PState::InTagCandidate
+  };
+  ps.current_string = checkpoint.current_string.clone();
+  ps.current_field = checkpoint.current_field.clone();
+  ps.current_segment = checkpoint.current_segment.clone();
+  ps.pending = checkpoint.pending.clone();
+  ps.stray = checkpoint.stray.clone();
+  (ps.line, ps.column) = checkpoint.position;
+  (ps.start_line, ps.start_column) = checkpoint.start_position;
+  (ps.pending_line, ps.pending_column) = checkpoint.pending_position;
+  let recovery = checkpoint.recovery.as_ref().map(RecoveryState::restore);
+  let limits = checkpoint.limits.map(|(limits, counts)| LimitState { limits, counts });
+  Ok(restore_segment_iterator(ioish, pc, ps, recovery, limits))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Checkpoint, TokenizerCheckpoint, EnvelopeCheckpoint, CheckpointingParser, execute_checkpointed_parser, resume_edi_streamer};
+  use crate::edi_tag_rules::SegmentTagRule;
+  use crate::edi_limits::{ParserLimits, LimitExceeded, LimitKind};
+  use crate::edi_recovery::RecoveryCheckpoint;
+  use crate::edi_parsers::{StreamParser, create_edi_streamer, execute_streaming_parser};
+  use crate::edi_segments::Segment;
+  use crate::edi_envelope::EnvelopeState;
+  use std::io::{Cursor, Error};
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SENDER*RECEIVER*20230101*1200*1*X*005010X220A1~
+ST*834*0001~
+BGN*00*1*20230101~
+INS*Y*18~
+SE*4*0001~
+ST*834*0002~
+BGN*00*2*20230101~
+SE*3*0002~
+GE*2*1~
+IEA*1*000000001~
+";
+
+  struct Recorder {
+    state: EnvelopeState,
+    events: Vec<String>
+  }
+
+  impl Recorder {
+    fn new() -> Self {
+      Recorder { state: EnvelopeState::Nothing, events: Vec::new() }
+    }
+  }
+
+  impl StreamParser for Recorder {
+    fn segment(&mut self, segment: &Segment) {
+      self.events.push(format!("{}@{}#{}", String::from_utf8_lossy(&segment.tag), segment.start_offset, segment.segment_index));
+    }
+
+    fn interchange_start(&mut self, _segment: &Segment) {
+      self.state = EnvelopeState::InInterchange;
+      self.events.push(String::from("isa"));
+    }
+
+    fn interchange_end(&mut self, segment: Option<&Segment>) {
+      self.state = EnvelopeState::Nothing;
+      self.events.push(format!("iea {}", segment.is_some()));
+    }
+
+    fn functional_group_start(&mut self, _segment: &Segment) {
+      self.state = EnvelopeState::InFunctionalGroup;
+      self.events.push(String::from("gs"));
+    }
+
+    fn functional_group_end(&mut self, segment: Option<&Segment>) {
+      self.state = EnvelopeState::InInterchange;
+      self.events.push(format!("ge {}", segment.is_some()));
+    }
+
+    fn transaction_start(&mut self, _segment: &Segment) {
+      self.state = EnvelopeState::InTransaction;
+      self.events.push(String::from("st"));
+    }
+
+    fn transaction_end(&mut self, segment: Option<&Segment>) {
+      self.state = EnvelopeState::InFunctionalGroup;
+      self.events.push(format!("se {}", segment.is_some()));
+    }
+
+    fn stream_end(&mut self) {
+      self.events.push(String::from("end"));
+    }
+
+    fn error(&mut self, _error: Error) {
+
+    }
+
+    fn in_interchange(&self) -> bool {
+      self.state.in_interchange()
+    }
+
+    fn in_functional_group(&self) -> bool {
+      self.state.in_functional_group()
+    }
+
+    fn in_transaction(&self) -> bool {
+      self.state.in_transaction()
+    }
+  }
+
+  #[test]
+  fn resumes_with_identical_results() {
+    let mut expected = Recorder::new();
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    execute_streaming_parser(&mut create_edi_streamer(&mut ioish).unwrap(), &mut expected);
+
+    let mut checkpoints = Vec::new();
+    let mut first_run = CheckpointingParser::new(Recorder::new());
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    execute_checkpointed_parser(&mut create_edi_streamer(&mut ioish).unwrap(), &mut first_run, 1, |c| checkpoints.push(c));
+    assert_eq!(first_run.inner().events, expected.events);
+
+    let checkpoint = &checkpoints[4];
+    assert_eq!(checkpoint.envelope.transaction_count, 1);
+    assert_eq!(checkpoint.envelope.transaction_control_number, b"0001".to_vec());
+    let mut written = Vec::new();
+    checkpoint.write_to(&mut written).unwrap();
+    let restored = Checkpoint::read_from(&mut Cursor::new(written)).unwrap();
+    assert!(restored == *checkpoint);
+
+    let mut resumed_inner = Recorder::new();
+    resumed_inner.state = restored.envelope.state;
+    let mut resumed = CheckpointingParser::resume(resumed_inner, restored.envelope.clone());
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    let mut pi = resume_edi_streamer(&mut ioish, &restored.tokenizer).unwrap();
+    execute_streaming_parser(&mut pi, &mut resumed);
+
+    let seen_before = first_run.inner().events.iter().position(|e| e.starts_with("INS")).unwrap() + 1;
+    assert_eq!(resumed.inner().events, expected.events[seen_before..].to_vec());
+    assert_eq!(resumed.envelope().segment_count, 11);
+  }
+
+  #[test]
+  fn resumes_with_tag_rule_and_limits() {
+    let raw = RAW.replace("INS*Y*18~", "INS*Y*18~9X*1~").replace("~\n", "~X\n");
+    let limits = ParserLimits { max_segments_per_transaction: Some(4), ..ParserLimits::default() };
+    let mut checkpoints = Vec::new();
+    let mut first_run = CheckpointingParser::new(Recorder::new());
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let mut pi = create_edi_streamer(&mut ioish).unwrap().with_tag_rule(SegmentTagRule::AnyByte).with_limits(limits);
+    execute_checkpointed_parser(&mut pi, &mut first_run, 1, |c| checkpoints.push(c));
+
+    let checkpoint = &checkpoints[4];
+    assert_eq!(checkpoint.tokenizer.tag_rule, SegmentTagRule::AnyByte);
+    let (restored_limits, counts) = checkpoint.tokenizer.limits.unwrap();
+    assert_eq!(restored_limits, limits);
+    assert_eq!(counts.transaction_segments, 3);
+    let mut written = Vec::new();
+    checkpoint.write_to(&mut written).unwrap();
+    let restored = Checkpoint::read_from(&mut Cursor::new(written)).unwrap();
+    assert!(restored == *checkpoint);
+
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let results : Vec<_> = resume_edi_streamer(&mut ioish, &restored.tokenizer).unwrap().collect();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].as_ref().unwrap().tag, b"9X".to_vec());
+    match &results[1] {
+      Err(e) => assert_eq!(LimitExceeded::from_error(e).map(|l| l.kind), Some(LimitKind::SegmentsPerTransaction)),
+      Ok(_) => panic!("expected a limit error")
+    }
+  }
+
+  #[test]
+  fn keeps_recovery_mode() {
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    let mut pi = create_edi_streamer(&mut ioish).unwrap().with_recovery();
+    pi.next();
+    let checkpoint = Checkpoint {
+      tokenizer: TokenizerCheckpoint::capture(&pi),
+      envelope: EnvelopeCheckpoint::new()
+    };
+    assert_eq!(checkpoint.tokenizer.recovery, Some(RecoveryCheckpoint::default()));
+    assert_eq!(checkpoint.tokenizer.limits, None);
+    let mut written = Vec::new();
+    checkpoint.write_to(&mut written).unwrap();
+    assert!(Checkpoint::read_from(&mut Cursor::new(written)).unwrap() == checkpoint);
+
+    let custom = Checkpoint {
+      tokenizer: TokenizerCheckpoint { tag_rule: SegmentTagRule::Custom(|_| Some(0)), ..checkpoint.tokenizer.clone() },
+      envelope: EnvelopeCheckpoint::new()
+    };
+    assert!(custom.write_to(&mut Vec::new()).is_err());
+  }
+}
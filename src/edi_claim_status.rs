@@ -0,0 +1,632 @@
+use crate::edi_common::{TransactionHandler, TransactionParser, Separators, Reference, DateReference, Amount, Entity, Name, Demographics};
+use crate::edi_claims::Procedure;
+use crate::edi_elements::{Decimal, EdiDate};
+use crate::edi_segments::Segment;
+use crate::edi_writer::X12Writer;
+use std::io::{Write, Error};
+
+// One C043 composite: STC01, STC10 or STC11.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct StatusCode {
+  pub category: String,
+  pub status: Option<String>,
+  pub entity: Option<String>,
+  pub code_list: Option<String>
+}
+
+impl StatusCode {
+  fn from_components(components: Vec<String>) -> Option<Self> {
+    let mut iter = components.into_iter().map(|c| Some(c).filter(|c| !c.is_empty()));
+    Some(StatusCode {
+      category: iter.next().flatten()?,
+      status: iter.next().flatten(),
+      entity: iter.next().flatten(),
+      code_list: iter.next().flatten()
+    })
+  }
+}
+
+// STC.  The position is that of the STC segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ClaimStatus {
+  pub codes: Vec<StatusCode>,
+  pub effective_date: Option<EdiDate>,
+  pub action_code: Option<String>,
+  pub charge: Option<Decimal>,
+  pub paid: Option<Decimal>,
+  pub adjudication_date: Option<EdiDate>,
+  pub payment_method: Option<String>,
+  pub check_date: Option<EdiDate>,
+  pub check_number: Option<String>,
+  pub message: Option<String>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl ClaimStatus {
+  fn from_stc(segment: &Segment, separators: &Separators) -> Self {
+    ClaimStatus {
+      codes: [1, 10, 11].iter().filter_map(|n| StatusCode::from_components(segment.components(*n, separators))).collect(),
+      effective_date: segment.element_date(2).value(),
+      action_code: segment.text(3),
+      charge: segment.element_decimal(4).value(),
+      paid: segment.element_decimal(5).value(),
+      adjudication_date: segment.element_date(6).value(),
+      payment_method: segment.text(7),
+      check_date: segment.element_date(8).value(),
+      check_number: segment.text(9),
+      message: segment.text(12),
+      segment_index: segment.segment_index,
+      start_offset: segment.start_offset,
+      line: segment.line,
+      column: segment.column
+    }
+  }
+}
+
+// 2210 or 2220.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ServiceStatus {
+  pub procedure: Option<Procedure>,
+  pub charge: Option<Decimal>,
+  pub paid: Option<Decimal>,
+  pub revenue_code: Option<String>,
+  pub units: Option<Decimal>,
+  pub statuses: Vec<ClaimStatus>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>
+}
+
+// A 2200D or 2200E loop, started by TRN.  The position is that of the TRN
+// segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct StatusClaim {
+  pub trace_number: String,
+  pub statuses: Vec<ClaimStatus>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub amounts: Vec<Amount>,
+  pub services: Vec<ServiceStatus>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl StatusClaim {
+  // REF*1K
+  pub fn payer_claim_number(&self) -> Option<&str> {
+    self.references.iter().find(|r| r.qualifier == "1K").map(|r| r.value.as_str())
+  }
+}
+
+// The HL levels above the claim being handed over.  `statuses` holds the STC
+// segments found at those levels, such as the 277CA acceptance or rejection
+// of a whole batch.  A level's statuses are dropped when the next level at the
+// same or a higher depth starts.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct StatusParties {
+  pub set_id: String,
+  pub control_number: String,
+  pub source: Option<Entity>,
+  pub receiver: Option<Entity>,
+  pub provider: Option<Entity>,
+  pub subscriber: Option<Entity>,
+  pub patient: Option<Entity>,
+  pub statuses: Vec<ClaimStatus>
+}
+
+pub trait ClaimStatusHandler {
+  fn claim(&mut self, parties: &StatusParties, claim: StatusClaim);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Loop {
+  Header,
+  Source,
+  Receiver,
+  Provider,
+  Subscriber,
+  Patient,
+  Claim,
+  Service,
+  Skipped
+}
+
+impl Loop {
+  fn depth(&self) -> Option<usize> {
+    match self {
+      Loop::Source => Some(0),
+      Loop::Receiver => Some(1),
+      Loop::Provider => Some(2),
+      Loop::Subscriber => Some(3),
+      Loop::Patient => Some(4),
+      _ => None
+    }
+  }
+}
+
+// Reads 276, 277 and 277CA transaction sets and hands over each claim as soon
+// as the next TRN, HL or the SE is seen.  The 277CA patient level (HL03 PT)
+// and the 277 dependent level (HL03 23) both fill `patient`.  Other
+// transaction sets are skipped.
+pub struct ClaimStatusReader<T: ClaimStatusHandler> {
+  parties: StatusParties,
+  // The depth of the level each of `parties.statuses` was found at.
+  status_depths: Vec<usize>,
+  claim: Option<StatusClaim>,
+  current: Loop,
+  handler: T
+}
+
+pub type ClaimStatusParser<T> = TransactionParser<ClaimStatusReader<T>>;
+
+impl<T: ClaimStatusHandler> ClaimStatusReader<T> {
+  pub fn new(handler: T) -> Self {
+    ClaimStatusReader {
+      parties: StatusParties::default(),
+      status_depths: Vec::new(),
+      claim: None,
+      current: Loop::Skipped,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+
+  fn flush(&mut self) {
+    if let Some(claim) = self.claim.take() {
+      self.handler.claim(&self.parties, claim);
+    }
+  }
+
+  fn hierarchical_level(&mut self, segment: &Segment) {
+    self.flush();
+    let parties = &mut self.parties;
+    self.current = match segment.element(3).value() {
+      Some(b"20") => {
+        parties.source = None;
+        Loop::Source
+      },
+      Some(b"21") => Loop::Receiver,
+      Some(b"19") => Loop::Provider,
+      Some(b"22") => {
+        parties.subscriber = None;
+        parties.patient = None;
+        Loop::Subscriber
+      },
+      Some(b"23") | Some(b"PT") => {
+        parties.patient = None;
+        Loop::Patient
+      },
+      _ => Loop::Header
+    };
+    match self.current {
+      Loop::Receiver => parties.receiver = None,
+      Loop::Provider => parties.provider = None,
+      _ => ()
+    }
+    if let Some(depth) = self.current.depth() {
+      let kept = self.status_depths.iter().take_while(|d| **d < depth).count();
+      self.status_depths.truncate(kept);
+      parties.statuses.truncate(kept);
+    }
+  }
+
+  fn level_entity(&mut self) -> Option<&mut Option<Entity>> {
+    let parties = &mut self.parties;
+    match self.current {
+      Loop::Source => Some(&mut parties.source),
+      Loop::Receiver => Some(&mut parties.receiver),
+      Loop::Provider => Some(&mut parties.provider),
+      Loop::Subscriber => Some(&mut parties.subscriber),
+      Loop::Patient => Some(&mut parties.patient),
+      _ => None
+    }
+  }
+
+  fn level_segment(&mut self, segment: &Segment, separators: &Separators) {
+    match segment.tag.as_slice() {
+      // A 276 puts the DMG of a subscriber or dependent before its NM1.
+      b"NM1" => if let Some(entity) = self.level_entity() {
+        let demographics = entity.take().and_then(|e| e.demographics);
+        *entity = Some(Entity { demographics, ..Entity::from_nm1(segment) });
+      },
+      b"DMG" => if let Some(entity) = self.level_entity() {
+        entity.get_or_insert_with(Entity::default).demographics = Some(Demographics::from_dmg(segment));
+      },
+      b"STC" => if let Some(depth) = self.current.depth() {
+        self.parties.statuses.push(ClaimStatus::from_stc(segment, separators));
+        self.status_depths.push(depth);
+      },
+      b"TRN" | b"DTP" | b"QTY" | b"AMT" => (),
+      _ => if let Some(Some(entity)) = self.level_entity() {
+        entity.apply(segment);
+      }
+    }
+  }
+
+  fn claim_segment(&mut self, segment: &Segment, separators: &Separators) {
+    let claim = match self.claim.as_mut() {
+      None => return,
+      Some(c) => c
+    };
+    let tag = segment.tag.as_slice();
+    if tag == b"SVC" {
+      claim.services.push(ServiceStatus {
+        procedure: Procedure::from_components(segment.components(1, separators)),
+        charge: segment.element_decimal(2).value(),
+        paid: segment.element_decimal(3).value(),
+        revenue_code: segment.text(4),
+        units: segment.element_decimal(7).value(),
+        ..ServiceStatus::default()
+      });
+      self.current = Loop::Service;
+      return;
+    }
+    match (tag, claim.services.last_mut().filter(|_| self.current == Loop::Service)) {
+      (b"STC", Some(s)) => s.statuses.push(ClaimStatus::from_stc(segment, separators)),
+      (b"REF", Some(s)) => s.references.push(Reference::from_ref(segment)),
+      (b"DTP", Some(s)) => s.dates.push(DateReference::from_dtp(segment)),
+      (b"STC", None) => claim.statuses.push(ClaimStatus::from_stc(segment, separators)),
+      (b"REF", None) => claim.references.push(Reference::from_ref(segment)),
+      (b"DTP", None) => claim.dates.push(DateReference::from_dtp(segment)),
+      (b"AMT", None) => claim.amounts.push(Amount::from_amt(segment)),
+      _ => ()
+    }
+  }
+}
+
+impl<T: ClaimStatusHandler> TransactionHandler for ClaimStatusReader<T> {
+  fn transaction_start(&mut self, header: &Segment, _separators: &Separators) {
+    let set_id = header.text(1).unwrap_or_default();
+    if set_id != "276" && set_id != "277" {
+      self.current = Loop::Skipped;
+      return;
+    }
+    self.parties = StatusParties {
+      set_id,
+      control_number: header.text(2).unwrap_or_default(),
+      ..StatusParties::default()
+    };
+    self.status_depths.clear();
+    self.current = Loop::Header;
+  }
+
+  fn segment(&mut self, segment: &Segment, separators: &Separators) {
+    if self.current == Loop::Skipped {
+      return;
+    }
+    match (segment.tag.as_slice(), self.current) {
+      (b"HL", _) => self.hierarchical_level(segment),
+      (_, Loop::Header) => (),
+      (b"TRN", Loop::Subscriber) | (b"TRN", Loop::Patient) | (b"TRN", Loop::Claim) | (b"TRN", Loop::Service) => {
+        self.flush();
+        self.claim = Some(StatusClaim {
+          trace_number: segment.text(2).unwrap_or_default(),
+          segment_index: segment.segment_index,
+          start_offset: segment.start_offset,
+          line: segment.line,
+          column: segment.column,
+          ..StatusClaim::default()
+        });
+        self.current = Loop::Claim;
+      },
+      (_, Loop::Claim) | (_, Loop::Service) => self.claim_segment(segment, separators),
+      _ => self.level_segment(segment, separators)
+    }
+  }
+
+  fn transaction_end(&mut self, _trailer: Option<&Segment>) {
+    self.flush();
+    self.current = Loop::Skipped;
+  }
+}
+
+// One 2200 loop of a 276.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct StatusInquiry {
+  pub trace_number: String,
+  pub payer_claim_number: Option<String>,
+  pub bill_type: Option<String>,
+  pub patient_account: Option<String>,
+  pub charge: Option<Decimal>,
+  pub service_date: Option<DateReference>
+}
+
+pub struct StatusMember {
+  pub name: Name,
+  pub demographics: Option<Demographics>,
+  pub inquiries: Vec<StatusInquiry>
+}
+
+// When `dependent` is set the inquiries of `subscriber` are not written, as
+// a 276 only asks about claims at the lowest level.
+pub struct ClaimStatusRequest {
+  pub source: Name,
+  pub receiver: Name,
+  pub provider: Name,
+  pub subscriber: StatusMember,
+  pub dependent: Option<StatusMember>
+}
+
+fn write_status_member<W: Write>(writer: &mut X12Writer<W>, member: &StatusMember, inquiries: bool) -> Result<(), Error> {
+  if let Some(demographics) = &member.demographics {
+    writer.dmg(demographics)?;
+  }
+  writer.nm1(&member.name)?;
+  if !inquiries {
+    return Ok(());
+  }
+  for inquiry in member.inquiries.iter() {
+    writer.segment("TRN", &["1", &inquiry.trace_number])?;
+    for (qualifier, value) in [("1K", &inquiry.payer_claim_number), ("BLT", &inquiry.bill_type), ("EJ", &inquiry.patient_account)] {
+      if let Some(value) = value {
+        writer.segment("REF", &[qualifier, value])?;
+      }
+    }
+    if let Some(charge) = &inquiry.charge {
+      writer.segment("AMT", &["T3", &charge.to_string()])?;
+    }
+    if let Some(date) = &inquiry.service_date {
+      writer.segment("DTP", &[&date.qualifier, &date.format, &date.value])?;
+    }
+  }
+  Ok(())
+}
+
+// Writes a 276 holding one HL chain for each request.  The writer has to be
+// inside a functional group.
+pub fn write_claim_status_request<W: Write>(writer: &mut X12Writer<W>, control_number: &str, reference: &str, date: &str, time: &str, requests: &[ClaimStatusRequest]) -> Result<(), Error> {
+  writer.begin_transaction("276", control_number, Some("005010X212"))?;
+  writer.segment("BHT", &["0010", "13", reference, date, time])?;
+  let mut hl = 0;
+  for request in requests.iter() {
+    let source = hl + 1;
+    writer.segment("HL", &[&source.to_string(), "", "20", "1"])?;
+    writer.nm1(&request.source)?;
+    let receiver = source + 1;
+    writer.segment("HL", &[&receiver.to_string(), &source.to_string(), "21", "1"])?;
+    writer.nm1(&request.receiver)?;
+    let provider = receiver + 1;
+    writer.segment("HL", &[&provider.to_string(), &receiver.to_string(), "19", "1"])?;
+    writer.nm1(&request.provider)?;
+    let subscriber = provider + 1;
+    let child = if request.dependent.is_some() { "1" } else { "0" };
+    writer.segment("HL", &[&subscriber.to_string(), &provider.to_string(), "22", child])?;
+    write_status_member(writer, &request.subscriber, request.dependent.is_none())?;
+    hl = subscriber;
+    if let Some(dependent) = &request.dependent {
+      hl += 1;
+      writer.segment("HL", &[&hl.to_string(), &subscriber.to_string(), "23"])?;
+      write_status_member(writer, dependent, true)?;
+    }
+  }
+  writer.end_transaction()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ClaimStatusHandler, StatusParties, StatusClaim, ClaimStatusReader, StatusCode, ClaimStatusRequest, StatusMember, StatusInquiry, write_claim_status_request};
+  use crate::edi_common::{Name, Entity, DateReference};
+  use crate::edi_common::testing::{Collector, read_transactions};
+  use crate::edi_writer::{X12Writer, InterchangeHeader, GroupHeader};
+  use crate::edi_elements::{Decimal, EdiDate, EdiTime};
+
+  impl ClaimStatusHandler for Collector<(StatusParties, StatusClaim)> {
+    fn claim(&mut self, parties: &StatusParties, claim: StatusClaim) {
+      self.items.push((parties.clone(), claim));
+    }
+  }
+
+  fn read(raw: &[u8]) -> Vec<(StatusParties, StatusClaim)> {
+    read_transactions(raw, ClaimStatusReader::new(Collector::new())).into_handler().items
+  }
+
+  #[test]
+  fn reads_claim_acknowledgments() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*PAYER          *ZZ*PROVIDER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HN*PAYER*PROVIDER*20230101*1200*1*X*005010X214~
+ST*277*0001*005010X214~
+BHT*0085*08*0123*20230101*1200*TH~
+HL*1**20*1~
+NM1*PR*2*PAYER NAME*****PI*12345~
+TRN*1*0123~
+DTP*050*D8*20230101~
+HL*2*1*21*1~
+NM1*41*2*SUBMITTER*****46*S1~
+TRN*2*BATCH1~
+STC*A1:19:PR*20230101*WQ*1000~
+QTY*90*2~
+HL*3*2*19*1~
+NM1*85*2*CLINIC*****XX*1234567890~
+HL*4*3*PT~
+NM1*QC*1*DOE*JOHN****MI*M1~
+TRN*2*CLAIM1~
+STC*A2:20:PR*20230101*WQ*600~
+REF*1K*PAYERCLAIM1~
+DTP*472*RD8*20221201-20221202~
+HL*5*3*PT~
+NM1*QC*1*DOE*JANE****MI*M2~
+TRN*2*CLAIM2~
+STC*A7:21:85**U*400******A7:562:85*A7:453~
+SVC*HC:99213*400****1~
+STC*A7:145:85*20230101~
+REF*FJ*11~
+SE*27*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let claims = read(raw.as_bytes());
+    assert_eq!(claims.len(), 2);
+    let (parties, accepted) = &claims[0];
+    assert_eq!(parties.set_id, "277");
+    assert_eq!(parties.statuses.len(), 1);
+    assert_eq!(parties.statuses[0].codes[0], StatusCode { category: String::from("A1"), status: Some(String::from("19")), entity: Some(String::from("PR")), code_list: None });
+    assert_eq!(parties.provider.as_ref().and_then(|p| p.name.id.clone()), Some(String::from("1234567890")));
+    assert_eq!(parties.patient.as_ref().and_then(|p| p.name.first_name.clone()), Some(String::from("JOHN")));
+    assert_eq!(accepted.trace_number, "CLAIM1");
+    assert_eq!(accepted.payer_claim_number(), Some("PAYERCLAIM1"));
+    assert_eq!(accepted.statuses[0].charge, Some(Decimal::new(600, 0)));
+    let (parties, rejected) = &claims[1];
+    assert_eq!(parties.patient.as_ref().and_then(|p| p.name.first_name.clone()), Some(String::from("JANE")));
+    let codes : Vec<&str> = rejected.statuses[0].codes.iter().filter_map(|c| c.status.as_deref()).collect();
+    assert_eq!(codes, vec!["21", "562", "453"]);
+    assert_eq!(rejected.statuses[0].line, 25);
+    assert_eq!(rejected.statuses[0].start_offset, raw.find("STC*A7:21").unwrap() as u64);
+    assert_eq!(rejected.statuses[0].column, 1);
+    assert_eq!(rejected.services[0].statuses[0].codes[0].status.as_deref(), Some("145"));
+    assert_eq!(rejected.services[0].references.len(), 1);
+  }
+
+  #[test]
+  fn keeps_statuses_per_level() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*PAYER          *ZZ*PROVIDER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HN*PAYER*PROVIDER*20230101*1200*1*X*005010X214~
+ST*277*0001*005010X214~
+BHT*0085*08*0123*20230101*1200*TH~
+HL*1**20*1~
+NM1*PR*2*PAYER NAME*****PI*12345~
+HL*2*1*21*1~
+NM1*41*2*SUBMITTER*****46*S1~
+STC*A1:19:PR*20230101*WQ*1000~
+HL*3*2*19*1~
+NM1*85*2*FIRST CLINIC*****XX*1111111111~
+STC*A3:24:85*20230101*U*600~
+HL*4*3*PT~
+NM1*QC*1*DOE*JOHN****MI*M1~
+TRN*2*CLAIM1~
+STC*A3:24:85*20230101*U*600~
+HL*5*2*19*1~
+NM1*85*2*SECOND CLINIC*****XX*2222222222~
+STC*A1:20:85*20230101*WQ*400~
+HL*6*5*PT~
+NM1*QC*1*DOE*JANE****MI*M2~
+TRN*2*CLAIM2~
+STC*A2:20:PR*20230101*WQ*400~
+SE*21*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let claims = read(raw.as_bytes());
+    assert_eq!(claims.len(), 2);
+    let categories = |parties: &StatusParties| -> Vec<String> {
+      parties.statuses.iter().map(|s| s.codes[0].category.clone()).collect()
+    };
+    let (parties, first) = &claims[0];
+    assert_eq!(first.trace_number, "CLAIM1");
+    assert_eq!(categories(parties), vec!["A1", "A3"]);
+    let (parties, second) = &claims[1];
+    assert_eq!(second.trace_number, "CLAIM2");
+    assert_eq!(parties.provider.as_ref().and_then(|p| p.name.id.clone()), Some(String::from("2222222222")));
+    assert_eq!(categories(parties), vec!["A1", "A1"]);
+    assert_eq!(parties.statuses[1].charge, Some(Decimal::new(400, 0)));
+  }
+
+  #[test]
+  fn reads_request_demographics() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*PROVIDER       *ZZ*PAYER          *230101*1200*^*00501*000000001*0*P*:~
+GS*HR*PROVIDER*PAYER*20230101*1200*1*X*005010X212~
+ST*276*0001*005010X212~
+BHT*0010*13*REF1*20230101*1200~
+HL*1**20*1~
+NM1*PR*2*PAYER~
+HL*2*1*21*1~
+NM1*41*2*CLINIC~
+HL*3*2*19*1~
+NM1*1P*2*CLINIC~
+HL*4*3*22*1~
+DMG*D8*19700101*F~
+NM1*IL*1*DOE*JANE~
+HL*5*4*23~
+DMG*D8*20100101*M~
+NM1*QC*1*DOE*JOHN~
+TRN*1*T1~
+DTP*472*D8*20221215~
+SE*17*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let claims = read(raw.as_bytes());
+    assert_eq!(claims.len(), 1);
+    let (parties, claim) = &claims[0];
+    let birth_date = |entity: &Option<Entity>| entity.as_ref().and_then(|e| e.demographics.as_ref()).and_then(|d| d.birth_date);
+    assert_eq!(birth_date(&parties.subscriber), Some(EdiDate { year: 1970, month: 1, day: 1 }));
+    assert_eq!(birth_date(&parties.patient), Some(EdiDate { year: 2010, month: 1, day: 1 }));
+    assert_eq!(parties.patient.as_ref().map(|p| p.name.last_name.as_str()), Some("DOE"));
+    assert_eq!(parties.provider.as_ref().and_then(|p| p.demographics.clone()), None);
+    assert_eq!(claim.dates[0].qualifier, "472");
+  }
+
+  #[test]
+  fn writes_requests() {
+    let mut writer = X12Writer::new(Vec::new());
+    let date = EdiDate { year: 2023, month: 1, day: 2 };
+    let time = EdiTime { hour: 9, minute: 5, second: 0, hundredths: 0 };
+    writer.begin_interchange(&InterchangeHeader {
+      sender_qualifier: String::from("ZZ"),
+      sender_id: String::from("PROVIDER"),
+      receiver_qualifier: String::from("ZZ"),
+      receiver_id: String::from("PAYER"),
+      date,
+      time,
+      control_number: 1,
+      version: String::from("00501"),
+      acknowledgment_requested: false,
+      test: false
+    }).unwrap();
+    writer.begin_group(&GroupHeader {
+      functional_id: String::from("HR"),
+      sender: String::from("PROVIDER"),
+      receiver: String::from("PAYER"),
+      date,
+      time,
+      control_number: 1,
+      version: String::from("005010X212")
+    }).unwrap();
+    let name = |code: &str, last: &str| Name {
+      entity_code: String::from(code),
+      entity_type: String::from("2"),
+      last_name: String::from(last),
+      ..Name::default()
+    };
+    let request = ClaimStatusRequest {
+      source: name("PR", "PAYER"),
+      receiver: name("41", "CLINIC"),
+      provider: name("1P", "CLINIC"),
+      subscriber: StatusMember {
+        name: name("IL", "DOE"),
+        demographics: None,
+        inquiries: vec![StatusInquiry {
+          trace_number: String::from("T1"),
+          patient_account: Some(String::from("ACCT1")),
+          charge: Some(Decimal::new(12550, 2)),
+          service_date: Some(DateReference { qualifier: String::from("232"), format: String::from("D8"), value: String::from("20221215") }),
+          ..StatusInquiry::default()
+        }]
+      },
+      dependent: None
+    };
+    write_claim_status_request(&mut writer, "0001", "REF1", "20230102", "0905", &[request]).unwrap();
+    writer.end_group().unwrap();
+    writer.end_interchange().unwrap();
+    let raw = writer.into_inner();
+    assert!(String::from_utf8_lossy(&raw).contains("HL*4*3*22*0~\nNM1*IL*2*DOE~\nTRN*1*T1~\nREF*EJ*ACCT1~\nAMT*T3*125.50~\nDTP*232*D8*20221215~\nSE*15*0001~"));
+    let claims = read(&raw);
+    assert_eq!(claims.len(), 1);
+    assert_eq!(claims[0].0.set_id, "276");
+    assert_eq!(claims[0].1.trace_number, "T1");
+    assert_eq!(claims[0].1.amounts[0].amount, Some(Decimal::new(12550, 2)));
+  }
+}
@@ -0,0 +1,781 @@
+use crate::edi_common::{TransactionHandler, TransactionParser, Separators, Reference, DateReference, Amount, Entity, Demographics};
+use crate::edi_elements::{Decimal, EdiDate};
+use crate::edi_hierarchy::HierarchyBuilder;
+use crate::edi_segments::Segment;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum ClaimKind {
+  #[default]
+  Professional,
+  Institutional
+}
+
+fn find_entity<'a>(entities: &'a [Entity], code: &str) -> Option<&'a Entity> {
+  entities.iter().find(|e| e.name.entity_code == code)
+}
+
+// 2000A with its 2010A loops.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct BillingProvider {
+  pub hl_id: String,
+  pub specialty: Option<String>,
+  pub entities: Vec<Entity>
+}
+
+impl BillingProvider {
+  // NM1*85
+  pub fn provider(&self) -> Option<&Entity> {
+    find_entity(&self.entities, "85")
+  }
+}
+
+// 2000B with its 2010B loops.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Subscriber {
+  pub hl_id: String,
+  pub payer_responsibility: Option<String>,
+  pub relationship_code: Option<String>,
+  pub group_number: Option<String>,
+  pub claim_filing_code: Option<String>,
+  pub entities: Vec<Entity>,
+  pub demographics: Option<Demographics>
+}
+
+impl Subscriber {
+  // NM1*IL
+  pub fn name(&self) -> Option<&Entity> {
+    find_entity(&self.entities, "IL")
+  }
+
+  // NM1*PR
+  pub fn payer(&self) -> Option<&Entity> {
+    find_entity(&self.entities, "PR")
+  }
+}
+
+// 2000C with its 2010CA loop.  Only present when the patient is not the
+// subscriber.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Patient {
+  pub hl_id: String,
+  pub relationship_code: Option<String>,
+  pub entities: Vec<Entity>,
+  pub demographics: Option<Demographics>
+}
+
+impl Patient {
+  // NM1*QC
+  pub fn name(&self) -> Option<&Entity> {
+    find_entity(&self.entities, "QC")
+  }
+}
+
+// The submitter (1000A), the receiver (1000B) and the HL levels above the
+// claim that is being handed over.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ClaimParties {
+  pub control_number: String,
+  pub submitter: Option<Entity>,
+  pub receiver: Option<Entity>,
+  pub billing_provider: Option<BillingProvider>,
+  pub subscriber: Option<Subscriber>,
+  pub patient: Option<Patient>
+}
+
+// One HI composite.  `present_on_admission` is its ninth component.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnosis {
+  pub qualifier: String,
+  pub code: String,
+  pub present_on_admission: Option<String>
+}
+
+// An HI composite that is not a diagnosis.  Value codes carry an amount, and
+// occurrence codes and procedures a D8 date.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ClaimCode {
+  pub qualifier: String,
+  pub code: String,
+  pub date: Option<EdiDate>,
+  pub amount: Option<Decimal>
+}
+
+// A procedure composite from SV101 or SV202.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Procedure {
+  pub qualifier: String,
+  pub code: String,
+  pub modifiers: Vec<String>
+}
+
+impl Procedure {
+  pub(crate) fn from_components(components: Vec<String>) -> Option<Self> {
+    let mut iter = components.into_iter();
+    let qualifier = iter.next().filter(|q| !q.is_empty())?;
+    let code = iter.next().unwrap_or_default();
+    let modifiers = iter.take(4).filter(|m| !m.is_empty()).collect();
+    Some(Procedure { qualifier, code, modifiers })
+  }
+}
+
+// CL1 on institutional claims.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Admission {
+  pub type_code: Option<String>,
+  pub source_code: Option<String>,
+  pub patient_status: Option<String>
+}
+
+// 2320 with its 2330 entities.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct OtherSubscriber {
+  pub payer_responsibility: Option<String>,
+  pub relationship_code: Option<String>,
+  pub group_number: Option<String>,
+  pub claim_filing_code: Option<String>,
+  pub amounts: Vec<Amount>,
+  pub entities: Vec<Entity>
+}
+
+// 2400.  SV1 fills `procedure`, `charge`, `unit_basis`, `units` and
+// `diagnosis_pointers`; SV2 fills `revenue_code` as well.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ServiceLine {
+  pub line_number: Option<String>,
+  pub revenue_code: Option<String>,
+  pub procedure: Option<Procedure>,
+  pub charge: Option<Decimal>,
+  pub unit_basis: Option<String>,
+  pub units: Option<Decimal>,
+  pub diagnosis_pointers: Vec<String>,
+  pub dates: Vec<DateReference>,
+  pub references: Vec<Reference>,
+  pub amounts: Vec<Amount>,
+  pub providers: Vec<Entity>
+}
+
+// One 2300 loop with its service lines.  The position is that of its CLM
+// segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Claim {
+  pub kind: ClaimKind,
+  pub claim_id: String,
+  pub total_charge: Option<Decimal>,
+  pub facility_code: Option<String>,
+  pub facility_qualifier: Option<String>,
+  pub frequency_code: Option<String>,
+  pub admission: Option<Admission>,
+  pub dates: Vec<DateReference>,
+  pub references: Vec<Reference>,
+  pub amounts: Vec<Amount>,
+  pub notes: Vec<String>,
+  pub diagnoses: Vec<Diagnosis>,
+  // HI*BE
+  pub value_codes: Vec<ClaimCode>,
+  // HI*BH
+  pub occurrence_codes: Vec<ClaimCode>,
+  // HI*BG
+  pub condition_codes: Vec<ClaimCode>,
+  // HI*BBR, BBQ, BP and BO
+  pub procedures: Vec<ClaimCode>,
+  pub providers: Vec<Entity>,
+  pub other_subscribers: Vec<OtherSubscriber>,
+  pub lines: Vec<ServiceLine>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl Claim {
+  // HI*ABK (ICD-10) or HI*BK (ICD-9).
+  pub fn principal_diagnosis(&self) -> Option<&Diagnosis> {
+    self.diagnoses.iter().find(|d| d.qualifier == "ABK" || d.qualifier == "BK")
+  }
+
+  pub fn provider(&self, code: &str) -> Option<&Entity> {
+    find_entity(&self.providers, code)
+  }
+}
+
+pub trait ClaimHandler {
+  fn claim(&mut self, parties: &ClaimParties, claim: Claim);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Loop {
+  Header,
+  BillingProvider,
+  Subscriber,
+  Patient,
+  Claim,
+  ClaimProvider,
+  OtherSubscriber,
+  OtherSubscriberEntity,
+  ServiceLine,
+  LineProvider,
+  LineAdjudication,
+  Skipped
+}
+
+// The data of one HL level, kept to the end of the transaction so that later
+// levels can name it as their parent.
+enum PartyLevel {
+  BillingProvider(BillingProvider),
+  Subscriber(Subscriber),
+  Patient(Patient),
+  Other
+}
+
+// Rebuilds the billing provider, subscriber and patient levels of an 837 from
+// its HL segments and hands over each claim as soon as the next CLM, HL or the
+// SE is seen.  The parties of a claim are the levels its HL02 links lead up
+// to.  The kind of claim comes from ST03 (005010X222 or 005010X223),
+// or from the presence of CL1 and SV2 when ST03 is missing.  Other transaction
+// sets are skipped.
+pub struct ClaimReader<T: ClaimHandler> {
+  parties: ClaimParties,
+  hierarchy: HierarchyBuilder,
+  levels: Vec<PartyLevel>,
+  kind: Option<ClaimKind>,
+  claim: Option<Claim>,
+  active: bool,
+  current: Loop,
+  handler: T
+}
+
+pub type ClaimParser<T> = TransactionParser<ClaimReader<T>>;
+
+impl<T: ClaimHandler> ClaimReader<T> {
+  pub fn new(handler: T) -> Self {
+    ClaimReader {
+      parties: ClaimParties::default(),
+      hierarchy: HierarchyBuilder::new(),
+      levels: Vec::new(),
+      kind: None,
+      claim: None,
+      active: false,
+      current: Loop::Skipped,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+
+  fn flush(&mut self) {
+    if let Some(claim) = self.claim.take() {
+      self.fill_parties();
+      self.handler.claim(&self.parties, claim);
+    }
+  }
+
+  // Follows the parent links up from the latest HL level.
+  fn fill_parties(&mut self) {
+    let parties = &mut self.parties;
+    parties.billing_provider = None;
+    parties.subscriber = None;
+    parties.patient = None;
+    let hierarchy = self.hierarchy.levels();
+    let mut at = hierarchy.len().checked_sub(1);
+    while let Some(idx) = at {
+      match &self.levels[idx] {
+        PartyLevel::BillingProvider(b) => { parties.billing_provider.get_or_insert_with(|| b.clone()); },
+        PartyLevel::Subscriber(s) => { parties.subscriber.get_or_insert_with(|| s.clone()); },
+        PartyLevel::Patient(p) => { parties.patient.get_or_insert_with(|| p.clone()); },
+        PartyLevel::Other => ()
+      }
+      at = hierarchy[idx].parent;
+    }
+  }
+
+  fn hierarchical_level(&mut self, segment: &Segment) {
+    self.flush();
+    self.hierarchy.segment(segment);
+    let hl_id = segment.text(1).unwrap_or_default();
+    let (level, current) = match segment.element(3).value() {
+      Some(b"20") => (PartyLevel::BillingProvider(BillingProvider { hl_id, ..BillingProvider::default() }), Loop::BillingProvider),
+      Some(b"22") => (PartyLevel::Subscriber(Subscriber { hl_id, ..Subscriber::default() }), Loop::Subscriber),
+      Some(b"23") => (PartyLevel::Patient(Patient { hl_id, ..Patient::default() }), Loop::Patient),
+      _ => (PartyLevel::Other, Loop::Skipped)
+    };
+    self.levels.push(level);
+    self.current = current;
+  }
+
+  fn start_claim(&mut self, segment: &Segment, separators: &Separators) {
+    self.flush();
+    let facility = segment.components(5, separators);
+    self.claim = Some(Claim {
+      kind: self.kind.unwrap_or_default(),
+      claim_id: segment.text(1).unwrap_or_default(),
+      total_charge: segment.element_decimal(2).value(),
+      facility_code: component(&facility, 0),
+      facility_qualifier: component(&facility, 1),
+      frequency_code: component(&facility, 2),
+      segment_index: segment.segment_index,
+      start_offset: segment.start_offset,
+      line: segment.line,
+      column: segment.column,
+      ..Claim::default()
+    });
+    self.current = Loop::Claim;
+  }
+
+  // Segments ahead of the first HL and inside the HL levels.
+  fn party_segment(&mut self, segment: &Segment) {
+    let parties = &mut self.parties;
+    let level = match self.current {
+      Loop::Header => None,
+      _ => self.levels.last_mut()
+    };
+    let tag = segment.tag.as_slice();
+    if tag == b"NM1" {
+      let entity = Entity::from_nm1(segment);
+      match level {
+        None => match entity.name.entity_code.as_str() {
+          "41" => parties.submitter = Some(entity),
+          "40" => parties.receiver = Some(entity),
+          _ => ()
+        },
+        Some(PartyLevel::BillingProvider(b)) => b.entities.push(entity),
+        Some(PartyLevel::Subscriber(s)) => s.entities.push(entity),
+        Some(PartyLevel::Patient(p)) => p.entities.push(entity),
+        Some(PartyLevel::Other) => ()
+      }
+      return;
+    }
+    let (entity, demographics) = match level {
+      None => (parties.receiver.as_mut().or(parties.submitter.as_mut()), None),
+      Some(PartyLevel::BillingProvider(b)) => {
+        if tag == b"PRV" {
+          b.specialty = segment.text(3);
+        }
+        (b.entities.last_mut(), None)
+      },
+      Some(PartyLevel::Subscriber(s)) => {
+        if tag == b"SBR" {
+          s.payer_responsibility = segment.text(1);
+          s.relationship_code = segment.text(2);
+          s.group_number = segment.text(3);
+          s.claim_filing_code = segment.text(9);
+        }
+        (s.entities.last_mut(), Some(&mut s.demographics))
+      },
+      Some(PartyLevel::Patient(p)) => {
+        if tag == b"PAT" {
+          p.relationship_code = segment.text(1);
+        }
+        (p.entities.last_mut(), Some(&mut p.demographics))
+      },
+      Some(PartyLevel::Other) => (None, None)
+    };
+    match (tag, demographics) {
+      (b"DMG", Some(d)) => *d = Some(Demographics::from_dmg(segment)),
+      _ => {
+        if let Some(e) = entity {
+          e.apply(segment);
+        }
+      }
+    }
+  }
+
+  fn claim_segment(&mut self, segment: &Segment, separators: &Separators) {
+    let claim = match self.claim.as_mut() {
+      None => return,
+      Some(c) => c
+    };
+    let tag = segment.tag.as_slice();
+    self.current = match (tag, self.current) {
+      (b"LX", _) => {
+        claim.lines.push(ServiceLine {
+          line_number: segment.text(1),
+          ..ServiceLine::default()
+        });
+        Loop::ServiceLine
+      },
+      (b"SBR", _) => {
+        claim.other_subscribers.push(OtherSubscriber {
+          payer_responsibility: segment.text(1),
+          relationship_code: segment.text(2),
+          group_number: segment.text(3),
+          claim_filing_code: segment.text(9),
+          ..OtherSubscriber::default()
+        });
+        Loop::OtherSubscriber
+      },
+      (b"NM1", Loop::Claim) | (b"NM1", Loop::ClaimProvider) => {
+        claim.providers.push(Entity::from_nm1(segment));
+        Loop::ClaimProvider
+      },
+      (b"NM1", Loop::OtherSubscriber) | (b"NM1", Loop::OtherSubscriberEntity) => {
+        if let Some(other) = claim.other_subscribers.last_mut() {
+          other.entities.push(Entity::from_nm1(segment));
+        }
+        Loop::OtherSubscriberEntity
+      },
+      (b"NM1", Loop::ServiceLine) | (b"NM1", Loop::LineProvider) => {
+        if let Some(line) = claim.lines.last_mut() {
+          line.providers.push(Entity::from_nm1(segment));
+        }
+        Loop::LineProvider
+      },
+      (b"SVD", _) => Loop::LineAdjudication,
+      (_, Loop::Claim) => {
+        apply_claim_segment(claim, segment, separators);
+        if tag == b"CL1" || tag == b"SV2" {
+          claim.kind = self.kind.unwrap_or(ClaimKind::Institutional);
+        }
+        Loop::Claim
+      },
+      (_, Loop::ClaimProvider) => {
+        if let Some(provider) = claim.providers.last_mut() {
+          provider.apply(segment);
+        }
+        Loop::ClaimProvider
+      },
+      (_, Loop::OtherSubscriber) => {
+        if let (Some(other), b"AMT") = (claim.other_subscribers.last_mut(), tag) {
+          other.amounts.push(Amount::from_amt(segment));
+        }
+        Loop::OtherSubscriber
+      },
+      (_, Loop::OtherSubscriberEntity) => {
+        if let Some(entity) = claim.other_subscribers.last_mut().and_then(|o| o.entities.last_mut()) {
+          entity.apply(segment);
+        }
+        Loop::OtherSubscriberEntity
+      },
+      (_, Loop::ServiceLine) => {
+        if let Some(line) = claim.lines.last_mut() {
+          apply_line_segment(line, segment, separators);
+        }
+        if tag == b"SV2" {
+          claim.kind = self.kind.unwrap_or(ClaimKind::Institutional);
+        }
+        Loop::ServiceLine
+      },
+      (_, Loop::LineProvider) => {
+        if let Some(provider) = claim.lines.last_mut().and_then(|l| l.providers.last_mut()) {
+          provider.apply(segment);
+        }
+        Loop::LineProvider
+      },
+      (_, current) => current
+    };
+  }
+}
+
+fn component(components: &[String], n: usize) -> Option<String> {
+  components.get(n).filter(|c| !c.is_empty()).cloned()
+}
+
+fn apply_claim_segment(claim: &mut Claim, segment: &Segment, separators: &Separators) {
+  match segment.tag.as_slice() {
+    b"DTP" => claim.dates.push(DateReference::from_dtp(segment)),
+    b"REF" => claim.references.push(Reference::from_ref(segment)),
+    b"AMT" => claim.amounts.push(Amount::from_amt(segment)),
+    b"NTE" => claim.notes.extend(segment.text(2)),
+    b"CL1" => claim.admission = Some(Admission {
+      type_code: segment.text(1),
+      source_code: segment.text(2),
+      patient_status: segment.text(3)
+    }),
+    b"HI" => for n in 1..segment.fields.len() {
+      let components = segment.components(n, separators);
+      if let (Some(qualifier), Some(code)) = (component(&components, 0), component(&components, 1)) {
+        let codes = match qualifier.as_str() {
+          "BE" => &mut claim.value_codes,
+          "BH" => &mut claim.occurrence_codes,
+          "BG" => &mut claim.condition_codes,
+          "BBR" | "BBQ" | "BP" | "BO" => &mut claim.procedures,
+          _ => {
+            claim.diagnoses.push(Diagnosis {
+              qualifier,
+              code,
+              present_on_admission: component(&components, 8)
+            });
+            continue;
+          }
+        };
+        let date = match component(&components, 2).as_deref() {
+          Some("D8") => component(&components, 3).and_then(|d| EdiDate::parse(d.as_bytes())),
+          _ => None
+        };
+        codes.push(ClaimCode {
+          qualifier,
+          code,
+          date,
+          amount: component(&components, 4).and_then(|a| Decimal::parse(a.as_bytes()))
+        });
+      }
+    },
+    _ => ()
+  }
+}
+
+fn apply_line_segment(line: &mut ServiceLine, segment: &Segment, separators: &Separators) {
+  match segment.tag.as_slice() {
+    b"SV1" => {
+      line.procedure = Procedure::from_components(segment.components(1, separators));
+      line.charge = segment.element_decimal(2).value();
+      line.unit_basis = segment.text(3);
+      line.units = segment.element_decimal(4).value();
+      line.diagnosis_pointers = segment.components(7, separators).into_iter().filter(|p| !p.is_empty()).collect();
+    },
+    b"SV2" => {
+      line.revenue_code = segment.text(1);
+      line.procedure = Procedure::from_components(segment.components(2, separators));
+      line.charge = segment.element_decimal(3).value();
+      line.unit_basis = segment.text(4);
+      line.units = segment.element_decimal(5).value();
+    },
+    b"DTP" => line.dates.push(DateReference::from_dtp(segment)),
+    b"REF" => line.references.push(Reference::from_ref(segment)),
+    b"AMT" => line.amounts.push(Amount::from_amt(segment)),
+    _ => ()
+  }
+}
+
+impl<T: ClaimHandler> TransactionHandler for ClaimReader<T> {
+  fn transaction_start(&mut self, header: &Segment, _separators: &Separators) {
+    self.active = header.element(1).value() == Some(b"837".as_slice());
+    if !self.active {
+      return;
+    }
+    self.parties = ClaimParties {
+      control_number: header.text(2).unwrap_or_default(),
+      ..ClaimParties::default()
+    };
+    self.hierarchy = HierarchyBuilder::new();
+    self.levels.clear();
+    let version = header.text(3).unwrap_or_default();
+    self.kind = if version.contains("X222") {
+      Some(ClaimKind::Professional)
+    } else if version.contains("X223") {
+      Some(ClaimKind::Institutional)
+    } else {
+      None
+    };
+    self.current = Loop::Header;
+  }
+
+  fn segment(&mut self, segment: &Segment, separators: &Separators) {
+    if !self.active {
+      return;
+    }
+    match segment.tag.as_slice() {
+      b"HL" => self.hierarchical_level(segment),
+      b"CLM" => self.start_claim(segment, separators),
+      _ => match self.current {
+        Loop::Header | Loop::BillingProvider | Loop::Subscriber | Loop::Patient => self.party_segment(segment),
+        _ => self.claim_segment(segment, separators)
+      }
+    }
+  }
+
+  fn transaction_end(&mut self, _trailer: Option<&Segment>) {
+    self.flush();
+    self.parties = ClaimParties::default();
+    self.hierarchy = HierarchyBuilder::new();
+    self.levels.clear();
+    self.active = false;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ClaimHandler, ClaimParties, ClaimReader, Claim, ClaimCode, ClaimKind};
+  use crate::edi_common::testing::{Collector, read_transactions};
+  use crate::edi_elements::{Decimal, EdiDate};
+
+  impl ClaimHandler for Collector<(ClaimParties, Claim)> {
+    fn claim(&mut self, parties: &ClaimParties, claim: Claim) {
+      self.items.push((parties.clone(), claim));
+    }
+  }
+
+  fn read(raw: &str) -> Vec<(ClaimParties, Claim)> {
+    read_transactions(raw.as_bytes(), ClaimReader::new(Collector::new())).into_handler().items
+  }
+
+  #[test]
+  fn streams_professional_claims() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SUBMITTER      *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SUBMITTER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001*005010X222A1~
+BHT*0019*00*0123*20230101*1200*CH~
+NM1*41*2*BILLING SERVICE*****46*TGJ23~
+PER*IC*JERRY*TE*3055552222~
+NM1*40*2*KEY INSURANCE*****46*66783JJT~
+HL*1**20*1~
+PRV*BI*PXC*203BF0100Y~
+NM1*85*2*BEN KILDARE SERVICE*****XX*9876543210~
+N3*234 SEAWAY ST~
+N4*MIAMI*FL*33111~
+REF*EI*587654321~
+HL*2*1*22*1~
+SBR*P**2222-SJ******CI~
+NM1*IL*1*SMITH*JANE****MI*JS00111223333~
+DMG*D8*19430501*F~
+NM1*PR*2*KEY INSURANCE*****PI*999996666~
+HL*3*2*23*0~
+PAT*19~
+NM1*QC*1*SMITH*TED~
+N3*236 N MAIN ST~
+N4*MIAMI*FL*33413~
+DMG*D8*19730501*M~
+CLM*26463774*100***11:B:1*Y*A*Y*I~
+REF*D9*17312345600006351~
+HI*ABK:J020*ABF:Z1159~
+NM1*82*1*KILDARE*BEN****XX*1234567804~
+PRV*PE*PXC*204C00000X~
+LX*1~
+SV1*HC:99213:25*40*UN*1***1:2~
+DTP*472*D8*20230110~
+LX*2~
+SV1*HC:87070*15*UN*1***1~
+DTP*472*D8*20230110~
+HL*4*2*23*0~
+PAT*19~
+NM1*QC*1*SMITH*ANN~
+CLM*26463775*55***11:B:7*Y*A*Y*I~
+HI*ABK:R509~
+LX*1~
+SV1*HC:99212*55*UN*1***1~
+SE*42*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let claims = read(raw);
+    assert_eq!(claims.len(), 2);
+    let (parties, claim) = &claims[0];
+    assert_eq!(parties.submitter.as_ref().map(|s| s.contacts.len()), Some(1));
+    let billing = parties.billing_provider.as_ref().unwrap();
+    assert_eq!(billing.specialty.as_deref(), Some("203BF0100Y"));
+    assert_eq!(billing.provider().map(|p| p.references.len()), Some(1));
+    let subscriber = parties.subscriber.as_ref().unwrap();
+    assert_eq!(subscriber.group_number.as_deref(), Some("2222-SJ"));
+    assert_eq!(subscriber.payer().and_then(|p| p.name.id.clone()), Some(String::from("999996666")));
+    assert!(subscriber.demographics.is_some());
+    assert_eq!(parties.patient.as_ref().and_then(|p| p.name()).and_then(|n| n.name.first_name.clone()), Some(String::from("TED")));
+    assert_eq!(claim.kind, ClaimKind::Professional);
+    assert_eq!(claim.claim_id, "26463774");
+    assert_eq!(claim.total_charge, Some(Decimal::new(100, 0)));
+    assert_eq!(claim.facility_code.as_deref(), Some("11"));
+    assert_eq!(claim.frequency_code.as_deref(), Some("1"));
+    assert_eq!(claim.diagnoses.len(), 2);
+    assert_eq!(claim.principal_diagnosis().map(|d| d.code.as_str()), Some("J020"));
+    assert_eq!(claim.provider("82").and_then(|p| p.name.id.clone()), Some(String::from("1234567804")));
+    assert_eq!(claim.lines.len(), 2);
+    let procedure = claim.lines[0].procedure.as_ref().unwrap();
+    assert_eq!(procedure.code, "99213");
+    assert_eq!(procedure.modifiers, vec![String::from("25")]);
+    assert_eq!(claim.lines[0].diagnosis_pointers, vec![String::from("1"), String::from("2")]);
+    assert_eq!(claim.lines[1].charge, Some(Decimal::new(15, 0)));
+    assert_eq!(claim.line, 25);
+    let (parties, claim) = &claims[1];
+    assert_eq!(parties.patient.as_ref().map(|p| p.hl_id.as_str()), Some("4"));
+    assert_eq!(parties.subscriber.as_ref().map(|s| s.hl_id.as_str()), Some("2"));
+    assert_eq!(claim.frequency_code.as_deref(), Some("7"));
+  }
+
+  #[test]
+  fn streams_institutional_claims() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SUBMITTER      *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SUBMITTER*RECEIVER*20230101*1200*1*X*005010X223A2~
+ST*837*0001~
+BHT*0019*00*0123*20230101*1200*CH~
+HL*1**20*1~
+NM1*85*2*GENERAL HOSPITAL*****XX*9876540809~
+HL*2*1*22*0~
+SBR*P*18*******MB~
+NM1*IL*1*DOE*JOHN****MI*030005074A~
+CLM*756048Q*89.93***14:A:1**A*Y*Y~
+DTP*434*RD8*20230101-20230102~
+CL1*3**01~
+HI*ABK:J189:::::::Y~
+HI*BE:80:::3~
+HI*BH:11:D8:20221230~
+HI*BG:39~
+HI*BBR:0JHT3VZ:D8:20230101~
+SBR*S*01*******CI~
+AMT*D*10~
+NM1*IN*2*OTHER PLAN~
+N3*1 PLAN WAY~
+LX*1~
+SV2*0305*HC:85025*13.39*UN*1~
+SE*24*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let claims = read(raw);
+    assert_eq!(claims.len(), 1);
+    let (parties, claim) = &claims[0];
+    assert!(parties.patient.is_none());
+    assert_eq!(parties.subscriber.as_ref().and_then(|s| s.claim_filing_code.clone()), Some(String::from("MB")));
+    assert_eq!(claim.kind, ClaimKind::Institutional);
+    assert_eq!(claim.admission.as_ref().and_then(|a| a.patient_status.clone()), Some(String::from("01")));
+    assert_eq!(claim.diagnoses[0].present_on_admission.as_deref(), Some("Y"));
+    assert_eq!(claim.diagnoses.len(), 1);
+    assert_eq!(claim.value_codes, vec![ClaimCode { qualifier: String::from("BE"), code: String::from("80"), date: None, amount: Some(Decimal::new(3, 0)) }]);
+    assert_eq!(claim.occurrence_codes[0].date, Some(EdiDate { year: 2022, month: 12, day: 30 }));
+    assert_eq!(claim.condition_codes[0].code, "39");
+    assert_eq!(claim.procedures[0].code, "0JHT3VZ");
+    assert_eq!(claim.procedures[0].date, Some(EdiDate { year: 2023, month: 1, day: 1 }));
+    assert_eq!(claim.other_subscribers[0].amounts.len(), 1);
+    assert_eq!(claim.other_subscribers[0].entities[0].address.lines, vec![String::from("1 PLAN WAY")]);
+    assert_eq!(claim.lines[0].revenue_code.as_deref(), Some("0305"));
+    assert_eq!(claim.lines[0].charge, Some(Decimal::new(1339, 2)));
+  }
+
+  #[test]
+  fn follows_parent_links() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SUBMITTER      *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SUBMITTER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001*005010X222A1~
+BHT*0019*00*0123*20230101*1200*CH~
+HL*1**20*1~
+NM1*85*2*FIRST CLINIC*****XX*1111111111~
+HL*2**20*1~
+NM1*85*2*SECOND CLINIC*****XX*2222222222~
+HL*3*1*22*0~
+SBR*P*18*******CI~
+NM1*IL*1*DOE*JOHN****MI*M1~
+CLM*A1*10***11:B:1*Y*A*Y*I~
+HL*4*2*22*1~
+SBR*P**G2******CI~
+NM1*IL*1*ROE*RITA****MI*M2~
+HL*5*4*23*0~
+PAT*01~
+NM1*QC*1*ROE*SAM~
+CLM*A2*20***11:B:1*Y*A*Y*I~
+HL*6*2*22*0~
+SBR*P*18*******CI~
+NM1*IL*1*POE*PAT****MI*M3~
+CLM*A3*30***11:B:1*Y*A*Y*I~
+SE*22*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let claims = read(raw);
+    let hl = |id: Option<&String>| id.map_or("-", |i| i.as_str()).to_string();
+    let summary : Vec<String> = claims.iter().map(|(parties, claim)| format!("{} {} {} {}",
+      claim.claim_id,
+      hl(parties.billing_provider.as_ref().map(|b| &b.hl_id)),
+      hl(parties.subscriber.as_ref().map(|s| &s.hl_id)),
+      hl(parties.patient.as_ref().map(|p| &p.hl_id))
+    )).collect();
+    assert_eq!(summary, vec!["A1 1 3 -", "A2 2 4 5", "A3 2 6 -"]);
+    let provider = claims[0].0.billing_provider.as_ref().and_then(|b| b.provider()).and_then(|p| p.name.id.clone());
+    assert_eq!(provider, Some(String::from("1111111111")));
+  }
+}
@@ -0,0 +1,464 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::Segment;
+use crate::edi_envelope::EnvelopeState;
+use crate::edi_elements::{Decimal, EdiDate};
+use std::io::Error;
+
+// The separators that are only found inside the ISA segment.  ISA11 is the
+// repetition separator from version 00402 on; before that it is a standards
+// identifier and there is no repetition separator.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Separators {
+  pub component: u8,
+  pub repetition: Option<u8>
+}
+
+impl Default for Separators {
+  fn default() -> Self {
+    Separators {
+      component: b':',
+      repetition: Some(b'^')
+    }
+  }
+}
+
+impl Separators {
+  pub fn from_isa(isa: &Segment) -> Self {
+    let component = isa.fields.get(16).and_then(|f| f.first().copied()).unwrap_or(b':');
+    let repetition = match isa.fields.get(11).map(|f| f.as_slice()) {
+      Some([r]) if !r.is_ascii_alphanumeric() => Some(*r),
+      _ => None
+    };
+    Separators { component, repetition }
+  }
+}
+
+fn split(value: &[u8], separator: Option<u8>) -> Vec<&[u8]> {
+  match separator {
+    Some(s) => value.split(move |b| *b == s).collect(),
+    None => vec![value]
+  }
+}
+
+fn text(value: &[u8]) -> String {
+  String::from_utf8_lossy(value).to_string()
+}
+
+impl Segment {
+  // Element `n` as text, or `None` when it is absent or empty.
+  pub fn text(&self, n: usize) -> Option<String> {
+    self.element(n).value().map(text)
+  }
+
+  // The components of composite element `n`.  Empty when the element is
+  // absent.
+  pub fn components(&self, n: usize, separators: &Separators) -> Vec<String> {
+    match self.fields.get(n) {
+      None => Vec::new(),
+      Some(f) => split(f, Some(separators.component)).into_iter().map(text).collect()
+    }
+  }
+
+  // Each repeat of element `n`, split into its components.
+  pub fn repetitions(&self, n: usize, separators: &Separators) -> Vec<Vec<String>> {
+    match self.fields.get(n) {
+      None => Vec::new(),
+      Some(f) if f.is_empty() => Vec::new(),
+      Some(f) => split(f, separators.repetition).into_iter()
+        .map(|r| split(r, Some(separators.component)).into_iter().map(text).collect())
+        .collect()
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Reference {
+  pub qualifier: String,
+  pub value: String,
+  pub description: Option<String>
+}
+
+impl Reference {
+  pub fn from_ref(segment: &Segment) -> Self {
+    Reference {
+      qualifier: segment.text(1).unwrap_or_default(),
+      value: segment.text(2).unwrap_or_default(),
+      description: segment.text(3)
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct DateReference {
+  pub qualifier: String,
+  pub format: String,
+  pub value: String
+}
+
+impl DateReference {
+  pub fn from_dtp(segment: &Segment) -> Self {
+    DateReference {
+      qualifier: segment.text(1).unwrap_or_default(),
+      format: segment.text(2).unwrap_or_default(),
+      value: segment.text(3).unwrap_or_default()
+    }
+  }
+
+  // DTM carries a CCYYMMDD date in DTM02, or a format qualifier and value in
+  // DTM05 and DTM06.
+  pub fn from_dtm(segment: &Segment) -> Self {
+    let (format, value) = match segment.text(5) {
+      Some(format) => (format, segment.text(6).unwrap_or_default()),
+      None => (String::from("D8"), segment.text(2).unwrap_or_default())
+    };
+    DateReference {
+      qualifier: segment.text(1).unwrap_or_default(),
+      format,
+      value
+    }
+  }
+
+  // The date for D8 values, or the first date of RD8 ranges.
+  pub fn date(&self) -> Option<EdiDate> {
+    self.range().map(|(from, _)| from)
+  }
+
+  pub fn range(&self) -> Option<(EdiDate, EdiDate)> {
+    match self.format.as_str() {
+      "D8" => EdiDate::parse(self.value.as_bytes()).map(|d| (d, d)),
+      "RD8" => {
+        let (from, to) = self.value.split_once('-')?;
+        Some((EdiDate::parse(from.as_bytes())?, EdiDate::parse(to.as_bytes())?))
+      },
+      _ => None
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Amount {
+  pub qualifier: String,
+  pub amount: Option<Decimal>
+}
+
+impl Amount {
+  pub fn from_amt(segment: &Segment) -> Self {
+    Amount {
+      qualifier: segment.text(1).unwrap_or_default(),
+      amount: segment.element_decimal(2).value()
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Name {
+  pub entity_code: String,
+  pub entity_type: String,
+  pub last_name: String,
+  pub first_name: Option<String>,
+  pub middle_name: Option<String>,
+  pub prefix: Option<String>,
+  pub suffix: Option<String>,
+  pub id_qualifier: Option<String>,
+  pub id: Option<String>
+}
+
+impl Name {
+  pub fn from_nm1(segment: &Segment) -> Self {
+    Name {
+      entity_code: segment.text(1).unwrap_or_default(),
+      entity_type: segment.text(2).unwrap_or_default(),
+      last_name: segment.text(3).unwrap_or_default(),
+      first_name: segment.text(4),
+      middle_name: segment.text(5),
+      prefix: segment.text(6),
+      suffix: segment.text(7),
+      id_qualifier: segment.text(8),
+      id: segment.text(9)
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Address {
+  pub lines: Vec<String>,
+  pub city: Option<String>,
+  pub state: Option<String>,
+  pub postal_code: Option<String>,
+  pub country: Option<String>
+}
+
+impl Address {
+  pub fn apply_n3(&mut self, segment: &Segment) {
+    self.lines.extend(segment.text(1));
+    self.lines.extend(segment.text(2));
+  }
+
+  pub fn apply_n4(&mut self, segment: &Segment) {
+    self.city = segment.text(1);
+    self.state = segment.text(2);
+    self.postal_code = segment.text(3);
+    self.country = segment.text(4);
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Contact {
+  pub function_code: String,
+  pub name: Option<String>,
+  // (qualifier, number) pairs from PER03 to PER08.
+  pub numbers: Vec<(String, String)>
+}
+
+impl Contact {
+  pub fn from_per(segment: &Segment) -> Self {
+    let numbers = [3, 5, 7].iter()
+      .filter_map(|n| Some((segment.text(*n)?, segment.text(n + 1)?)))
+      .collect();
+    Contact {
+      function_code: segment.text(1).unwrap_or_default(),
+      name: segment.text(2),
+      numbers
+    }
+  }
+}
+
+// N1 loops: N1 with the N2, N3, N4, REF and PER that follow it.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Party {
+  pub entity_code: String,
+  pub name: Option<String>,
+  pub id_qualifier: Option<String>,
+  pub id: Option<String>,
+  pub address: Address,
+  pub references: Vec<Reference>,
+  pub contacts: Vec<Contact>
+}
+
+impl Party {
+  pub fn from_n1(segment: &Segment) -> Self {
+    Party {
+      entity_code: segment.text(1).unwrap_or_default(),
+      name: segment.text(2),
+      id_qualifier: segment.text(3),
+      id: segment.text(4),
+      ..Party::default()
+    }
+  }
+
+  // Returns false for segments that do not belong to the loop.
+  pub fn apply(&mut self, segment: &Segment) -> bool {
+    match segment.tag.as_slice() {
+      b"N2" => {
+        let more = segment.text(1).unwrap_or_default();
+        self.name = Some(match self.name.take() {
+          Some(name) => format!("{} {}", name, more),
+          None => more
+        });
+      },
+      b"N3" => self.address.apply_n3(segment),
+      b"N4" => self.address.apply_n4(segment),
+      b"REF" => self.references.push(Reference::from_ref(segment)),
+      b"PER" => self.contacts.push(Contact::from_per(segment)),
+      _ => return false
+    }
+    true
+  }
+}
+
+// NM1 loops: NM1 with the N3, N4, REF and PER that follow it.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Entity {
+  pub name: Name,
+  pub address: Address,
+  pub references: Vec<Reference>,
+  pub contacts: Vec<Contact>,
+  pub demographics: Option<Demographics>
+}
+
+impl Entity {
+  pub fn from_nm1(segment: &Segment) -> Self {
+    Entity {
+      name: Name::from_nm1(segment),
+      ..Entity::default()
+    }
+  }
+
+  // Returns false for segments that do not belong to the loop.
+  pub fn apply(&mut self, segment: &Segment) -> bool {
+    match segment.tag.as_slice() {
+      b"N3" => self.address.apply_n3(segment),
+      b"N4" => self.address.apply_n4(segment),
+      b"REF" => self.references.push(Reference::from_ref(segment)),
+      b"PER" => self.contacts.push(Contact::from_per(segment)),
+      _ => return false
+    }
+    true
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Demographics {
+  pub birth_date: Option<EdiDate>,
+  pub gender: Option<String>,
+  pub marital_status: Option<String>
+}
+
+impl Demographics {
+  pub fn from_dmg(segment: &Segment) -> Self {
+    Demographics {
+      birth_date: segment.element_date(2).value(),
+      gender: segment.text(3),
+      marital_status: segment.text(4)
+    }
+  }
+}
+
+pub trait TransactionHandler {
+  fn transaction_start(&mut self, header: &Segment, separators: &Separators);
+  fn segment(&mut self, segment: &Segment, separators: &Separators);
+  fn transaction_end(&mut self, trailer: Option<&Segment>);
+}
+
+// Tracks the envelope and the separators from the ISA so that typed models
+// only need to deal with the segments of each transaction set.  The ST and SE
+// segments go to `transaction_start` and `transaction_end` and not to
+// `segment`.
+pub struct TransactionParser<T: TransactionHandler> {
+  state: EnvelopeState,
+  separators: Separators,
+  handler: T
+}
+
+impl<T: TransactionHandler> TransactionParser<T> {
+  pub fn new(handler: T) -> Self {
+    TransactionParser {
+      state: EnvelopeState::Nothing,
+      separators: Separators::default(),
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn handler_mut(&mut self) -> &mut T {
+    &mut self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+}
+
+impl<T: TransactionHandler> StreamParser for TransactionParser<T> {
+  fn segment(&mut self, segment: &Segment) {
+    let tag = segment.tag.as_slice();
+    if self.state.in_transaction() && tag != b"ST" && tag != b"SE" {
+      self.handler.segment(segment, &self.separators);
+    }
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    self.separators = Separators::from_isa(segment);
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+  }
+
+  fn functional_group_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn transaction_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    self.handler.transaction_start(segment, &self.separators);
+  }
+
+  fn transaction_end(&mut self, segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    self.handler.transaction_end(segment);
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, _error: Error) {
+
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+// Shared by the tests of the transaction set readers: `Collector` keeps
+// whatever a reader hands over and `read_transactions` runs a reader over raw
+// input and gives it back.
+#[cfg(test)]
+pub(crate) mod testing {
+  use super::{TransactionHandler, TransactionParser};
+  use crate::edi_parsers::{create_edi_streamer, execute_streaming_parser};
+  use std::io::Cursor;
+
+  pub struct Collector<T> {
+    pub items: Vec<T>
+  }
+
+  impl<T> Collector<T> {
+    pub fn new() -> Self {
+      Collector { items: Vec::new() }
+    }
+  }
+
+  pub fn read_transactions<T: TransactionHandler>(raw: &[u8], reader: T) -> T {
+    let mut ioish = Cursor::new(raw);
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let mut parser = TransactionParser::new(reader);
+    execute_streaming_parser(&mut pi, &mut parser);
+    parser.into_handler()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Separators, DateReference, Contact};
+  use crate::edi_segments::create_segment_iterator;
+  use crate::edi_elements::EdiDate;
+  use std::io::Cursor;
+
+  #[test]
+  fn splits_composites_and_repeats() {
+    let raw = "ISA*00*          *00*          *ZZ*S              *ZZ*R              *230101*1200*^*00501*000000001*0*P*>~EB*1*IND*30^1^35~DTP*472*RD8*20230101-20230131~PER*IC*JANE*TE*5551234**EM*A@B.COM~";
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let segments : Vec<_> = create_segment_iterator(&mut ioish, b"*".to_vec(), b"~".to_vec()).map(|r| r.unwrap()).collect();
+    let separators = Separators::from_isa(&segments[0]);
+    assert_eq!(separators, Separators { component: b'>', repetition: Some(b'^') });
+    assert_eq!(segments[1].repetitions(3, &separators), vec![vec!["30"], vec!["1"], vec!["35"]]);
+    let older = Separators { component: b':', repetition: None };
+    assert_eq!(segments[1].repetitions(3, &older), vec![vec!["30^1^35"]]);
+    let dtp = DateReference::from_dtp(&segments[2]);
+    assert_eq!(dtp.range(), Some((EdiDate { year: 2023, month: 1, day: 1 }, EdiDate { year: 2023, month: 1, day: 31 })));
+    let per = Contact::from_per(&segments[3]);
+    assert_eq!(per.numbers, vec![(String::from("TE"), String::from("5551234"))]);
+  }
+}
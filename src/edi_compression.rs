@@ -0,0 +1,172 @@
+use crate::edi_source::{EdiSource, open_edi_source, read_prefix};
+use std::io::{Read, Cursor, Error, ErrorKind};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Compression {
+  None,
+  Gzip,
+  Zip,
+  Bzip2
+}
+
+pub fn detect_compression(prefix: &[u8]) -> Compression {
+  if prefix.starts_with(&[0x1f, 0x8b]) {
+    Compression::Gzip
+  } else if prefix.starts_with(b"PK\x03\x04") {
+    Compression::Zip
+  } else if prefix.starts_with(b"BZh") {
+    Compression::Bzip2
+  } else {
+    Compression::None
+  }
+}
+
+// Opens `source` for parsing, decompressing it first if it is gzip or bzip2.
+// Zip archives can hold more than one file and are read with
+// `for_each_zip_member` instead.  The decompressors buffer their own input and
+// `open_edi_source` buffers their output.
+pub fn open_decompressed<'a, R: Read + 'a>(mut source: R) -> Result<EdiSource<Box<dyn Read + 'a>>, Error> {
+  let magic = read_prefix(&mut source, 4)?;
+  let compression = detect_compression(&magic);
+  let restored = Cursor::new(magic).chain(source);
+  let reader : Box<dyn Read + 'a> = match compression {
+    Compression::None => Box::new(restored),
+    Compression::Gzip => gzip_reader(restored)?,
+    Compression::Bzip2 => bzip2_reader(restored)?,
+    Compression::Zip => return Err(Error::new(ErrorKind::InvalidInput, "zip archives are read with for_each_zip_member"))
+  };
+  open_edi_source(reader)
+}
+
+#[cfg(not(all(feature = "gzip", feature = "bzip2")))]
+fn unsupported(feature: &str) -> Error {
+  Error::new(ErrorKind::Unsupported, format!("built without the {} feature", feature))
+}
+
+#[cfg(feature = "gzip")]
+fn gzip_reader<'a, R: Read + 'a>(source: R) -> Result<Box<dyn Read + 'a>, Error> {
+  Ok(Box::new(flate2::read::MultiGzDecoder::new(source)))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_reader<'a, R: Read + 'a>(_source: R) -> Result<Box<dyn Read + 'a>, Error> {
+  Err(unsupported("gzip"))
+}
+
+#[cfg(feature = "bzip2")]
+fn bzip2_reader<'a, R: Read + 'a>(source: R) -> Result<Box<dyn Read + 'a>, Error> {
+  Ok(Box::new(bzip2::read::MultiBzDecoder::new(source)))
+}
+
+#[cfg(not(feature = "bzip2"))]
+fn bzip2_reader<'a, R: Read + 'a>(_source: R) -> Result<Box<dyn Read + 'a>, Error> {
+  Err(unsupported("bzip2"))
+}
+
+// Each file in the archive is handed to `f` as a separate source, with its
+// name.  Directories are skipped.  The archive is read as a stream, so it does
+// not need to be seekable.
+#[cfg(feature = "zip")]
+pub fn for_each_zip_member<R, F>(source: R, mut f: F) -> Result<(), Error>
+  where R: Read,
+        F: FnMut(&str, &mut EdiSource<&mut dyn Read>) -> Result<(), Error> {
+  let mut source = std::io::BufReader::new(source);
+  loop {
+    let mut member = match zip::read::read_zipfile_from_stream(&mut source) {
+      Ok(Some(m)) => m,
+      Ok(None) => return Ok(()),
+      Err(e) => return Err(Error::new(ErrorKind::InvalidData, e))
+    };
+    if member.is_dir() {
+      continue;
+    }
+    let name = member.name().to_string();
+    let mut edi = open_edi_source(&mut member as &mut dyn Read)?;
+    f(&name, &mut edi)?;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Compression, detect_compression, open_decompressed};
+  use std::io::{Cursor, ErrorKind};
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001~
+SE*2*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+
+  fn tags(source: impl std::io::Read) -> Vec<String> {
+    let mut edi = match open_decompressed(source) {
+      Ok(s) => s,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    edi.streamer().map(|r| String::from_utf8_lossy(&r.unwrap().tag).to_string()).collect()
+  }
+
+  #[test]
+  fn detects_magic_bytes() {
+    assert_eq!(detect_compression(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+    assert_eq!(detect_compression(b"PK\x03\x04"), Compression::Zip);
+    assert_eq!(detect_compression(b"BZh9"), Compression::Bzip2);
+    assert_eq!(detect_compression(b"ISA*"), Compression::None);
+    assert_eq!(tags(Cursor::new(RAW.as_bytes())), vec!["ISA", "GS", "ST", "SE", "GE", "IEA"]);
+  }
+
+  #[cfg(not(feature = "gzip"))]
+  #[test]
+  fn reports_missing_feature() {
+    match open_decompressed(Cursor::new(vec![0x1f, 0x8b, 8, 0])) {
+      Err(e) => assert_eq!(e.kind(), ErrorKind::Unsupported),
+      Ok(_) => panic!("expected an error")
+    }
+  }
+
+  #[cfg(feature = "gzip")]
+  #[test]
+  fn reads_gzip() {
+    use flate2::{write::GzEncoder, Compression as Level};
+    use std::io::Write;
+    let mut encoder = GzEncoder::new(Vec::new(), Level::default());
+    encoder.write_all(RAW.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(tags(Cursor::new(compressed)).len(), 6);
+    assert!(matches!(open_decompressed(Cursor::new(b"PK\x03\x04".to_vec())), Err(e) if e.kind() == ErrorKind::InvalidInput));
+  }
+
+  #[cfg(feature = "bzip2")]
+  #[test]
+  fn reads_bzip2() {
+    use bzip2::{write::BzEncoder, Compression as Level};
+    use std::io::Write;
+    let mut encoder = BzEncoder::new(Vec::new(), Level::default());
+    encoder.write_all(RAW.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(tags(Cursor::new(compressed)).len(), 6);
+  }
+
+  #[cfg(feature = "zip")]
+  #[test]
+  fn reads_each_zip_member() {
+    use super::for_each_zip_member;
+    use std::io::Write;
+    use zip::write::{SimpleFileOptions, ZipWriter};
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for name in ["a.edi", "b.edi"] {
+      writer.start_file(name, SimpleFileOptions::default()).unwrap();
+      writer.write_all(RAW.as_bytes()).unwrap();
+    }
+    let archive = writer.finish().unwrap().into_inner();
+    let mut seen = Vec::new();
+    let res = for_each_zip_member(Cursor::new(archive), |name, edi| {
+      seen.push((name.to_string(), edi.streamer().count()));
+      Ok(())
+    });
+    assert!(res.is_ok());
+    assert_eq!(seen, vec![(String::from("a.edi"), 6), (String::from("b.edi"), 6)]);
+  }
+}
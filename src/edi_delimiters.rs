@@ -1,5 +1,6 @@
 use std::io::{Read, Seek, SeekFrom, Error, ErrorKind};
-use crate::edi_constants::SEGMENT_STARTERS;
+use crate::edi_constants::{GS_TAG, IEA_TAG};
+use crate::edi_tag_rules::SegmentTagRule;
 
 pub struct Delimiters {
   pub element_delimiter: Vec<u8>,
@@ -11,7 +12,9 @@ pub enum DelimiterResult {
   DelimitersFound(Delimiters)
 }
 
-pub fn detect_delimiters<T: Read + Seek>(ioish: &mut T) -> DelimiterResult {
+// `tag_rule` finds the segment after the ISA when it is neither a GS nor an
+// IEA.
+pub fn detect_delimiters<T: Read + Seek>(ioish: &mut T, tag_rule: &SegmentTagRule) -> DelimiterResult {
   let pos = SeekFrom::Start(3);
   match ioish.seek(pos) {
     Ok(_) => (),
@@ -19,7 +22,7 @@ pub fn detect_delimiters<T: Read + Seek>(ioish: &mut T) -> DelimiterResult {
   }
   let mut buff = [0; 1];
   match ioish.read(&mut buff) {
-    Ok(size) if size == 1 => (),
+    Ok(1) => (),
     Ok(_) => {
       let eof_error = Error::from(ErrorKind::UnexpectedEof);
       return DelimiterResult::DelimiterReadError(eof_error)
@@ -37,7 +40,7 @@ pub fn detect_delimiters<T: Read + Seek>(ioish: &mut T) -> DelimiterResult {
       return DelimiterResult::DelimiterReadError(eof_error)
     }
     match ioish.read(&mut sd_buff) {
-      Ok(size) if size == 1 => (),
+      Ok(1) => (),
       Ok(_) => {
         let eof_error = Error::from(ErrorKind::UnexpectedEof);
         return DelimiterResult::DelimiterReadError(eof_error)
@@ -49,63 +52,69 @@ pub fn detect_delimiters<T: Read + Seek>(ioish: &mut T) -> DelimiterResult {
     }
     read_count += 1;
   }
-  let mut seg_delimiter : Vec<u8> = Vec::new();
-  match ioish.read(&mut sd_buff) {
-    Ok(size) if size == 1 => (),
-    Ok(_) => {
-      let eof_error = Error::from(ErrorKind::UnexpectedEof);
-      return DelimiterResult::DelimiterReadError(eof_error)
-    },
-    Err(e) => return DelimiterResult::DelimiterReadError(e)
-  };
-  match ioish.read(&mut sd_buff) {
-    Ok(size) if size == 1 => (),
-    Ok(_) => {
-      let eof_error = Error::from(ErrorKind::UnexpectedEof);
-      return DelimiterResult::DelimiterReadError(eof_error)
-    },
-    Err(e) => return DelimiterResult::DelimiterReadError(e)
-  };
-  let rewind_pos = SeekFrom::Start(0);
-  while !SEGMENT_STARTERS.contains(&sd_buff[0]) {
-    seg_delimiter.push(sd_buff[0]);
-    match ioish.read(&mut sd_buff) {
-      Ok(size) if size == 1 => (),
-      Ok(_) => {
-        match ioish.seek(rewind_pos) {
-          Ok(_) => (),
-          Err(e) => return DelimiterResult::DelimiterReadError(e)
-        }
-        return DelimiterResult::DelimitersFound(
-          Delimiters {
-            element_delimiter,
-            segment_delimiter: seg_delimiter
-          }
-        )
-      },
-      Err(e) => return DelimiterResult::DelimiterReadError(e)
-    };
+  // ISA16, then the terminator and the start of the next segment.
+  let mut tail = Vec::new();
+  if let Err(e) = ioish.by_ref().take(TERMINATOR_WINDOW).read_to_end(&mut tail) {
+    return DelimiterResult::DelimiterReadError(e)
   }
-  if seg_delimiter.is_empty() {
+  let rest = tail.get(1..).unwrap_or_default();
+  let length = next_tag(rest, &element_delimiter)
+    .or_else(|| first_tag(rest, &element_delimiter, tag_rule))
+    .unwrap_or(rest.len());
+  if length == 0 {
     let eof_error = Error::from(ErrorKind::UnexpectedEof);
     return DelimiterResult::DelimiterReadError(eof_error)
   }
-  match ioish.seek(rewind_pos) {
+  match ioish.seek(SeekFrom::Start(0)) {
     Ok(_) => (),
     Err(e) => return DelimiterResult::DelimiterReadError(e)
   }
   DelimiterResult::DelimitersFound(
     Delimiters {
       element_delimiter,
-      segment_delimiter: seg_delimiter
+      segment_delimiter: rest[..length].to_vec()
     }
   )
 }
 
+// How far past ISA16 to look for the segment after the ISA.
+const TERMINATOR_WINDOW : u64 = 64;
+
+// The terminator is whatever lies between ISA16 and the GS tag, or the IEA tag
+// of an interchange without groups, so it may hold letters.  Without either
+// tag it ends where the tag rule finds one.
+fn next_tag(rest: &[u8], element_delimiter: &[u8]) -> Option<usize> {
+  (0..rest.len()).find(|i| {
+    [&GS_TAG[..], &IEA_TAG[..]].iter().any(|tag| {
+      let after = &rest[*i..];
+      after.starts_with(tag) && after[tag.len()..].starts_with(element_delimiter)
+    })
+  })
+}
+
+// Whatever the tag rule leaves as stray in the bytes up to the next element
+// delimiter, or the next terminator, which starts with the same byte as this
+// one.
+fn first_tag(rest: &[u8], element_delimiter: &[u8], tag_rule: &SegmentTagRule) -> Option<usize> {
+  let end = (1..rest.len()).find(|i| element_delimiter.contains(&rest[*i]) || rest[*i] == rest[0]).unwrap_or(rest.len());
+  tag_rule.split(&rest[..end])
+}
+
+// Enough of the start of a stream to hold the ISA segment, its terminator
+// and the start of the next segment, for sources that can not seek.
+pub const DELIMITER_PREFIX_LENGTH : usize = 1024;
+
+pub fn detect_delimiters_in_prefix(prefix: &[u8], tag_rule: &SegmentTagRule) -> DelimiterResult {
+  detect_delimiters(&mut std::io::Cursor::new(prefix), tag_rule)
+}
+
 #[cfg(test)]
+#[allow(clippy::char_lit_as_u8)]
 mod test {
   use super::detect_delimiters;
   use super::DelimiterResult;
+  use crate::edi_parsers::{create_edi_streamer, create_edi_streamer_with_tag_rule};
+  use crate::edi_tag_rules::SegmentTagRule;
   use std::io::ErrorKind;
   use std::io::Cursor;
   use std::io::Seek;
@@ -113,7 +122,7 @@ mod test {
   #[test]
   fn not_long_enough_for_field_delimiter() {
     let mut ioish = Cursor::new("".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(e) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
       _ => panic!("Delimiters found - should have been an error instead")
@@ -123,7 +132,7 @@ mod test {
   #[test]
   fn not_long_enough_for_segment_delimiter() {
     let mut ioish = Cursor::new("ISA*00*TSI       *01*92511930  *01*ME             *12*BRADLEY        *970815*1732*U*00201*000000050*0*T>~".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(e) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
       _ => panic!("Delimiters found - should have been an error instead")
@@ -133,7 +142,7 @@ mod test {
   #[test]
   fn weird_missing_delimiter() {
     let mut ioish = Cursor::new("ISA*00*TSI       *01*92511930  *01*ME             *12*BRADLEY        *970815*1732*U*00201*000000050*0*T*>ISA".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(e) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
       _ => panic!("Delimiters found - should have been an error instead")
@@ -143,7 +152,7 @@ mod test {
   #[test]
   fn simple_delimiter_set() {
     let mut ioish = Cursor::new("ISA*00*TSI       *01*92511930  *01*ME             *12*BRADLEY        *970815*1732*U*00201*000000050*0*T*>~".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(_) => panic!("Delimiters not found"),
       DelimiterResult::DelimitersFound(x) => {
@@ -156,7 +165,7 @@ mod test {
   #[test]
   fn multibyte_delimiter_set_eof() {
     let mut ioish = Cursor::new("ISA*00*TSI       *01*92511930  *01*ME             *12*BRADLEY        *970815*1732*U*00201*000000050*0*T*>~\n".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(_) => panic!("Delimiters not found"),
       DelimiterResult::DelimitersFound(x) => {
@@ -169,7 +178,7 @@ mod test {
   #[test]
   fn multibyte_delimiter_set() {
     let mut ioish = Cursor::new("ISA*00*TSI       *01*92511930  *01*ME             *12*BRADLEY        *970815*1732*U*00201*000000050*0*T*>~\nIEA".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(_) => panic!("Delimiters not found"),
       DelimiterResult::DelimitersFound(x) => {
@@ -182,7 +191,7 @@ mod test {
   #[test]
   fn multibyte_delimiter_only_test() {
     let mut ioish = Cursor::new("ISA*00*TSI       *01*92511930  *01*ME             *12*BRADLEY        *970815*1732*U*00201*000000050*0*T*>~\n".as_bytes());
-    let res = detect_delimiters(&mut ioish);
+    let res = detect_delimiters(&mut ioish, &SegmentTagRule::default());
     match res {
       DelimiterResult::DelimiterReadError(_) => panic!("Delimiters not found"),
       DelimiterResult::DelimitersFound(x) => {
@@ -195,4 +204,44 @@ mod test {
       }
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn terminator_with_letters() {
+    let raw = "ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~X\nGS*HC*S*R*20230101*1200*1*X*005010X222A1~X\nST*837*0001~X\nSE*2*0001~X\nGE*1*1~X\nIEA*1*000000001~X\n";
+    let mut ioish = Cursor::new(raw.as_bytes());
+    match detect_delimiters(&mut ioish, &SegmentTagRule::default()) {
+      DelimiterResult::DelimiterReadError(_) => panic!("Delimiters not found"),
+      DelimiterResult::DelimitersFound(x) => assert_eq!(x.segment_delimiter, b"~X\n".to_vec())
+    }
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let segments : Vec<(Vec<u8>, usize)> = (&mut pi).map(|r| r.unwrap()).map(|s| (s.tag, s.fields.len())).collect();
+    assert_eq!(segments, vec![
+      (b"ISA".to_vec(), 17),
+      (b"GS".to_vec(), 9),
+      (b"ST".to_vec(), 3),
+      (b"SE".to_vec(), 3),
+      (b"GE".to_vec(), 3),
+      (b"IEA".to_vec(), 3)
+    ]);
+  }
+
+  #[test]
+  fn terminator_found_by_tag_rule() {
+    let raw = "ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~\n1A*0123456789012345678901234567890123456789012345678901234567890123456789~\nIEA*1*000000001~\n";
+    let tag_rule = SegmentTagRule::Custom(|c| c.iter().position(|b| b.is_ascii_alphanumeric()));
+    let mut ioish = Cursor::new(raw.as_bytes());
+    match detect_delimiters(&mut ioish, &tag_rule) {
+      DelimiterResult::DelimiterReadError(_) => panic!("Delimiters not found"),
+      DelimiterResult::DelimitersFound(x) => assert_eq!(x.segment_delimiter, b"~\n".to_vec())
+    }
+    let pi = match create_edi_streamer_with_tag_rule(&mut ioish, tag_rule) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let tags : Vec<Vec<u8>> = pi.map(|r| r.unwrap().tag).collect();
+    assert_eq!(tags, vec![b"ISA".to_vec(), b"1A".to_vec(), b"IEA".to_vec()]);
+  }
+}
@@ -0,0 +1,604 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::Segment;
+use crate::edi_envelope::EnvelopeState;
+use crate::edi_common::Separators;
+use std::collections::HashMap;
+use std::io::Error;
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct DiffOptions {
+  // Element and segment delimiters, and the ISA11 and ISA16 separators,
+  // which are also read as the same inside elements.
+  pub ignore_delimiters: bool,
+  pub ignore_line_endings: bool,
+  // ISA13, IEA02, GS06, GE02, ST02 and SE02.  Transactions are then matched
+  // by position instead of by ST01 and ST02.
+  pub ignore_control_numbers: bool
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct SegmentLocation {
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum DifferenceKind {
+  Added,
+  Removed,
+  Changed,
+  // The element delimiter or segment terminator differ.  The ISA element
+  // change holds both sides.
+  Delimiters,
+  LineEndings
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ElementChange {
+  pub element: usize,
+  pub left: Option<String>,
+  pub right: Option<String>
+}
+
+// An added or removed ISA, GS or ST stands for the whole envelope.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Difference {
+  pub kind: DifferenceKind,
+  pub interchange: usize,
+  pub group: Option<usize>,
+  pub transaction: Option<String>,
+  pub tag: String,
+  pub left: Option<SegmentLocation>,
+  pub right: Option<SegmentLocation>,
+  pub elements: Vec<ElementChange>
+}
+
+impl fmt::Display for Difference {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let line = |l: &Option<SegmentLocation>| l.map(|l| l.line.to_string()).unwrap_or_else(|| String::from("-"));
+    write!(f, "lines {}/{}, {:?} {}, interchange {}", line(&self.left), line(&self.right), self.kind, self.tag, self.interchange + 1)?;
+    if let Some(g) = self.group {
+      write!(f, ", group {}", g + 1)?;
+    }
+    if let Some(t) = &self.transaction {
+      write!(f, ", transaction {}", t)?;
+    }
+    for change in self.elements.iter() {
+      let text = |v: &Option<String>| v.clone().unwrap_or_default();
+      write!(f, "; {}{:02} {:?} -> {:?}", self.tag, change.element, text(&change.left), text(&change.right))?;
+    }
+    Ok(())
+  }
+}
+
+#[derive(Clone)]
+struct DiffSegment {
+  tag: Vec<u8>,
+  fields: Vec<Vec<u8>>,
+  location: SegmentLocation
+}
+
+impl DiffSegment {
+  fn new(segment: &Segment) -> Self {
+    DiffSegment {
+      tag: segment.tag.clone(),
+      fields: segment.fields.clone(),
+      location: SegmentLocation {
+        segment_index: segment.segment_index,
+        start_offset: segment.start_offset,
+        line: segment.line,
+        column: segment.column
+      }
+    }
+  }
+
+  fn text(&self, n: usize) -> Option<String> {
+    self.fields.get(n).filter(|v| !v.is_empty()).map(|v| String::from_utf8_lossy(v).to_string())
+  }
+}
+
+struct DiffTransaction {
+  // ST through SE.
+  segments: Vec<DiffSegment>
+}
+
+impl DiffTransaction {
+  fn label(&self) -> String {
+    let st = &self.segments[0];
+    format!("{}/{}", st.text(1).unwrap_or_default(), st.text(2).unwrap_or_default())
+  }
+}
+
+struct DiffGroup {
+  header: DiffSegment,
+  transactions: Vec<DiffTransaction>,
+  trailer: Option<DiffSegment>
+}
+
+struct DiffInterchange {
+  header: DiffSegment,
+  separators: Separators,
+  delimiters: Vec<u8>,
+  line_ending: Vec<u8>,
+  groups: Vec<DiffGroup>,
+  trailer: Option<DiffSegment>
+}
+
+pub struct DiffDocument {
+  interchanges: Vec<DiffInterchange>
+}
+
+// Keeps the elements of every envelope segment and every segment inside a
+// transaction, for `diff_documents`.
+pub struct DiffCollector {
+  state: EnvelopeState,
+  interchanges: Vec<DiffInterchange>,
+  error: Option<Error>
+}
+
+impl Default for DiffCollector {
+  fn default() -> Self {
+    DiffCollector::new()
+  }
+}
+
+impl DiffCollector {
+  pub fn new() -> Self {
+    DiffCollector {
+      state: EnvelopeState::Nothing,
+      interchanges: Vec::new(),
+      error: None
+    }
+  }
+
+  // The document, or the first read error.
+  pub fn into_document(self) -> Result<DiffDocument, Error> {
+    match self.error {
+      Some(e) => Err(e),
+      None => Ok(DiffDocument { interchanges: self.interchanges })
+    }
+  }
+
+  fn group(&mut self) -> Option<&mut DiffGroup> {
+    self.interchanges.last_mut().and_then(|i| i.groups.last_mut())
+  }
+}
+
+impl StreamParser for DiffCollector {
+  fn segment(&mut self, segment: &Segment) {
+    if !self.state.in_transaction() {
+      return;
+    }
+    if let Some(t) = self.group().and_then(|g| g.transactions.last_mut()) {
+      t.segments.push(DiffSegment::new(segment));
+    }
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    let body = segment.tag.len() + segment.fields[1..].iter().map(|f| f.len() + 1).sum::<usize>();
+    let terminator = segment.raw.get(body..).unwrap_or_default();
+    let split = terminator.iter().rposition(|b| *b != b'\r' && *b != b'\n').map(|p| p + 1).unwrap_or(0);
+    let mut delimiters = segment.raw.get(3..4).unwrap_or_default().to_vec();
+    delimiters.extend_from_slice(&terminator[..split]);
+    self.interchanges.push(DiffInterchange {
+      header: DiffSegment::new(segment),
+      separators: Separators::from_isa(segment),
+      delimiters,
+      line_ending: terminator[split..].to_vec(),
+      groups: Vec::new(),
+      trailer: None
+    });
+  }
+
+  fn interchange_end(&mut self, segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+    if let Some(i) = self.interchanges.last_mut() {
+      i.trailer = segment.map(DiffSegment::new);
+    }
+  }
+
+  fn functional_group_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    if let Some(i) = self.interchanges.last_mut() {
+      i.groups.push(DiffGroup {
+        header: DiffSegment::new(segment),
+        transactions: Vec::new(),
+        trailer: None
+      });
+    }
+  }
+
+  fn functional_group_end(&mut self, segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+    if let Some(g) = self.group() {
+      g.trailer = segment.map(DiffSegment::new);
+    }
+  }
+
+  fn transaction_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    if let Some(g) = self.group() {
+      g.transactions.push(DiffTransaction { segments: Vec::new() });
+    }
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, error: Error) {
+    self.error.get_or_insert(error);
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+// Above this many cells, segments of a transaction are lined up by tag alone
+// instead of by longest common subsequence.
+const MAX_ALIGNMENT_CELLS : usize = 1 << 22;
+
+// Interchanges and groups are matched by position, transactions by ST01 and
+// ST02 (or by position) and segments within them by longest common
+// subsequence, with removed and added segments of the same tag in between
+// reported as changed.
+pub fn diff_documents(left: &DiffDocument, right: &DiffDocument, options: &DiffOptions) -> Vec<Difference> {
+  let mut differ = Differ {
+    options,
+    interchange: 0,
+    group: None,
+    transaction: None,
+    differences: Vec::new()
+  };
+  let count = left.interchanges.len().max(right.interchanges.len());
+  for n in 0..count {
+    differ.interchange = n;
+    differ.group = None;
+    differ.transaction = None;
+    match (left.interchanges.get(n), right.interchanges.get(n)) {
+      (Some(l), Some(r)) => differ.interchanges(l, r),
+      (Some(l), None) => differ.push(DifferenceKind::Removed, Some(&l.header), None, Vec::new()),
+      (None, Some(r)) => differ.push(DifferenceKind::Added, None, Some(&r.header), Vec::new()),
+      (None, None) => ()
+    }
+  }
+  differ.differences
+}
+
+struct Differ<'a> {
+  options: &'a DiffOptions,
+  interchange: usize,
+  group: Option<usize>,
+  transaction: Option<String>,
+  differences: Vec<Difference>
+}
+
+impl Differ<'_> {
+  fn push(&mut self, kind: DifferenceKind, left: Option<&DiffSegment>, right: Option<&DiffSegment>, elements: Vec<ElementChange>) {
+    let tag = left.or(right).map(|s| String::from_utf8_lossy(&s.tag).to_string()).unwrap_or_default();
+    self.differences.push(Difference {
+      kind,
+      interchange: self.interchange,
+      group: self.group,
+      transaction: self.transaction.clone(),
+      tag,
+      left: left.map(|s| s.location),
+      right: right.map(|s| s.location),
+      elements
+    });
+  }
+
+  fn ignored(&self, tag: &[u8], n: usize) -> bool {
+    let control_number = matches!((tag, n), (b"ISA", 13) | (b"IEA", 2) | (b"GS", 6) | (b"GE", 2) | (b"ST", 2) | (b"SE", 2));
+    let separator = tag == b"ISA" && (n == 11 || n == 16);
+    (self.options.ignore_control_numbers && control_number) || (self.options.ignore_delimiters && separator)
+  }
+
+  // The elements as compared, with the tag first and trailing empty elements
+  // dropped.
+  fn normalize(&self, segment: &DiffSegment, separators: &Separators) -> Vec<Vec<u8>> {
+    let mut fields : Vec<Vec<u8>> = segment.fields.iter().enumerate().map(|(n, field)| {
+      if n > 0 && self.ignored(&segment.tag, n) {
+        return Vec::new();
+      }
+      if !self.options.ignore_delimiters || n == 0 {
+        return field.clone();
+      }
+      field.iter().map(|b| match *b {
+        b if b == separators.component => b':',
+        b if Some(b) == separators.repetition => b'^',
+        b => b
+      }).collect()
+    }).collect();
+    while fields.len() > 1 && fields.last().map(|f| f.is_empty()).unwrap_or(false) {
+      fields.pop();
+    }
+    fields
+  }
+
+  fn changes(&self, left: &DiffSegment, ls: &Separators, right: &DiffSegment, rs: &Separators) -> Vec<ElementChange> {
+    let (l, r) = (self.normalize(left, ls), self.normalize(right, rs));
+    let empty = Vec::new();
+    (1..l.len().max(r.len())).filter(|n| l.get(*n).unwrap_or(&empty) != r.get(*n).unwrap_or(&empty)).map(|n| ElementChange {
+      element: n,
+      left: left.text(n),
+      right: right.text(n)
+    }).collect()
+  }
+
+  fn pair(&mut self, left: Option<&DiffSegment>, ls: &Separators, right: Option<&DiffSegment>, rs: &Separators) {
+    match (left, right) {
+      (Some(l), Some(r)) => {
+        let elements = self.changes(l, ls, r, rs);
+        if !elements.is_empty() {
+          self.push(DifferenceKind::Changed, Some(l), Some(r), elements);
+        }
+      },
+      (Some(l), None) => self.push(DifferenceKind::Removed, Some(l), None, Vec::new()),
+      (None, Some(r)) => self.push(DifferenceKind::Added, None, Some(r), Vec::new()),
+      (None, None) => ()
+    }
+  }
+
+  fn interchanges(&mut self, left: &DiffInterchange, right: &DiffInterchange) {
+    let (ls, rs) = (&left.separators, &right.separators);
+    if !self.options.ignore_delimiters && left.delimiters != right.delimiters {
+      let change = ElementChange {
+        element: 0,
+        left: Some(String::from_utf8_lossy(&left.delimiters).to_string()),
+        right: Some(String::from_utf8_lossy(&right.delimiters).to_string())
+      };
+      self.push(DifferenceKind::Delimiters, Some(&left.header), Some(&right.header), vec![change]);
+    }
+    if !self.options.ignore_line_endings && left.line_ending != right.line_ending {
+      let change = ElementChange {
+        element: 0,
+        left: Some(line_ending_name(&left.line_ending)),
+        right: Some(line_ending_name(&right.line_ending))
+      };
+      self.push(DifferenceKind::LineEndings, Some(&left.header), Some(&right.header), vec![change]);
+    }
+    self.pair(Some(&left.header), ls, Some(&right.header), rs);
+    for n in 0..left.groups.len().max(right.groups.len()) {
+      self.group = Some(n);
+      self.transaction = None;
+      match (left.groups.get(n), right.groups.get(n)) {
+        (Some(l), Some(r)) => self.groups(l, ls, r, rs),
+        (l, r) => self.pair(l.map(|g| &g.header), ls, r.map(|g| &g.header), rs)
+      }
+    }
+    self.group = None;
+    self.transaction = None;
+    self.pair(left.trailer.as_ref(), ls, right.trailer.as_ref(), rs);
+  }
+
+  fn groups(&mut self, left: &DiffGroup, ls: &Separators, right: &DiffGroup, rs: &Separators) {
+    self.pair(Some(&left.header), ls, Some(&right.header), rs);
+    let mut used = vec![false; right.transactions.len()];
+    // Right transactions by ST01 and ST02, last first so each pairs with the
+    // earliest one left.
+    let mut by_key : HashMap<(Option<String>, Option<String>), Vec<usize>> = HashMap::new();
+    for (m, r) in right.transactions.iter().enumerate().rev() {
+      by_key.entry((r.segments[0].text(1), r.segments[0].text(2))).or_default().push(m);
+    }
+    for (n, l) in left.transactions.iter().enumerate() {
+      let found = if self.options.ignore_control_numbers {
+        Some(n).filter(|n| *n < right.transactions.len())
+      } else {
+        by_key.get_mut(&(l.segments[0].text(1), l.segments[0].text(2))).and_then(|m| m.pop())
+      };
+      self.transaction = Some(l.label());
+      match found {
+        Some(m) => {
+          used[m] = true;
+          self.segments(&l.segments, ls, &right.transactions[m].segments, rs);
+        },
+        None => self.push(DifferenceKind::Removed, Some(&l.segments[0]), None, Vec::new())
+      }
+    }
+    for (r, _) in right.transactions.iter().zip(used).filter(|(_, u)| !u) {
+      self.transaction = Some(r.label());
+      self.push(DifferenceKind::Added, None, Some(&r.segments[0]), Vec::new());
+    }
+    self.transaction = None;
+    self.pair(left.trailer.as_ref(), ls, right.trailer.as_ref(), rs);
+  }
+
+  fn segments(&mut self, left: &[DiffSegment], ls: &Separators, right: &[DiffSegment], rs: &Separators) {
+    let lk : Vec<Vec<Vec<u8>>> = left.iter().map(|s| self.normalize(s, ls)).collect();
+    let rk : Vec<Vec<Vec<u8>>> = right.iter().map(|s| self.normalize(s, rs)).collect();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for step in align(&lk, &rk) {
+      match step {
+        (Some(l), Some(r)) => {
+          self.gap(&removed, left, ls, &added, right, rs);
+          removed.clear();
+          added.clear();
+          self.pair(Some(&left[l]), ls, Some(&right[r]), rs);
+        },
+        (Some(l), None) => removed.push(l),
+        (None, Some(r)) => added.push(r),
+        (None, None) => ()
+      }
+    }
+    self.gap(&removed, left, ls, &added, right, rs);
+  }
+
+  // Segments between two matched ones: a removed and an added segment with
+  // the same tag, in order, are one changed segment.
+  fn gap(&mut self, removed: &[usize], left: &[DiffSegment], ls: &Separators, added: &[usize], right: &[DiffSegment], rs: &Separators) {
+    let mut next = 0;
+    for l in removed {
+      match added[next..].iter().position(|r| right[*r].tag == left[*l].tag) {
+        Some(p) => {
+          for r in added[next..next + p].iter() {
+            self.push(DifferenceKind::Added, None, Some(&right[*r]), Vec::new());
+          }
+          self.pair(Some(&left[*l]), ls, Some(&right[added[next + p]]), rs);
+          next += p + 1;
+        },
+        None => self.push(DifferenceKind::Removed, Some(&left[*l]), None, Vec::new())
+      }
+    }
+    for r in added[next..].iter() {
+      self.push(DifferenceKind::Added, None, Some(&right[*r]), Vec::new());
+    }
+  }
+}
+
+fn line_ending_name(ending: &[u8]) -> String {
+  String::from(match ending {
+    b"" => "none",
+    b"\n" => "LF",
+    b"\r\n" => "CRLF",
+    b"\r" => "CR",
+    _ => "mixed"
+  })
+}
+
+// Index pairs in order: both for a match, one side for a removal or addition.
+fn align<T: PartialEq>(left: &[T], right: &[T]) -> Vec<(Option<usize>, Option<usize>)> {
+  let prefix = left.iter().zip(right).take_while(|(l, r)| l == r).count();
+  let suffix = left[prefix..].iter().rev().zip(right[prefix..].iter().rev()).take_while(|(l, r)| l == r).count();
+  let (lm, rm) = (&left[prefix..left.len() - suffix], &right[prefix..right.len() - suffix]);
+  let mut steps : Vec<(Option<usize>, Option<usize>)> = (0..prefix).map(|n| (Some(n), Some(n))).collect();
+  let (mut i, mut j) = (0, 0);
+  for (a, b) in common_subsequence(lm, rm).into_iter().chain(std::iter::once((lm.len(), rm.len()))) {
+    steps.extend((i..a).map(|n| (Some(prefix + n), None)));
+    steps.extend((j..b).map(|n| (None, Some(prefix + n))));
+    if a < lm.len() {
+      steps.push((Some(prefix + a), Some(prefix + b)));
+    }
+    (i, j) = (a + 1, b + 1);
+  }
+  steps.extend((0..suffix).map(|n| (Some(left.len() - suffix + n), Some(right.len() - suffix + n))));
+  steps
+}
+
+fn common_subsequence<T: PartialEq>(left: &[T], right: &[T]) -> Vec<(usize, usize)> {
+  let (n, m) = (left.len(), right.len());
+  if n == 0 || m == 0 || n.saturating_mul(m) > MAX_ALIGNMENT_CELLS {
+    return Vec::new();
+  }
+  let width = m + 1;
+  let mut lengths = vec![0u32; (n + 1) * width];
+  for i in (0..n).rev() {
+    for j in (0..m).rev() {
+      lengths[i * width + j] = if left[i] == right[j] {
+        lengths[(i + 1) * width + j + 1] + 1
+      } else {
+        lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+      };
+    }
+  }
+  let mut pairs = Vec::new();
+  let (mut i, mut j) = (0, 0);
+  while i < n && j < m {
+    if left[i] == right[j] {
+      pairs.push((i, j));
+      i += 1;
+      j += 1;
+    } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+      i += 1;
+    } else {
+      j += 1;
+    }
+  }
+  pairs
+}
+
+#[cfg(test)]
+mod test {
+  use super::{DiffCollector, DiffDocument, DiffOptions, DifferenceKind, diff_documents};
+  use crate::edi_parsers::{create_edi_streamer, execute_streaming_parser};
+  use std::io::Cursor;
+
+  const LEFT : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001*005010X222A1~
+BHT*0019*00*A1*20230101*1200*CH~
+NM1*IL*1*DOE*JOHN~
+CLM*A1*100***11:B:1~
+HI*ABK:J020~
+SE*6*0001~
+ST*837*0002*005010X222A1~
+BHT*0019*00*A2*20230101*1200*CH~
+SE*3*0002~
+GE*2*1~
+IEA*1*000000001~
+";
+
+  const RIGHT : &str = "\
+ISA|00|          |00|          |ZZ|SENDER         |ZZ|RECEIVER       |230101|1200|^|00501|000000009|0|P|>~\r
+GS|HC|SENDER|RECEIVER|20230101|1200|9|X|005010X222A1~\r
+ST|837|0001|005010X222A1~\r
+BHT|0019|00|A1|20230101|1200|CH~\r
+NM1|IL|1|DOE|JANE~\r
+REF|EA|12345~\r
+CLM|A1|100|||11>B>1~\r
+HI|ABK>J020~\r
+SE|7|0001~\r
+GE|1|9~\r
+IEA|1|000000009~\r
+";
+
+  fn document(raw: &str) -> DiffDocument {
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let mut collector = DiffCollector::new();
+    execute_streaming_parser(&mut pi, &mut collector);
+    collector.into_document().unwrap()
+  }
+
+  #[test]
+  fn diffs_documents() {
+    let (left, right) = (document(LEFT), document(RIGHT));
+    assert!(diff_documents(&left, &document(LEFT), &DiffOptions::default()).is_empty());
+
+    let strict = diff_documents(&left, &right, &DiffOptions::default());
+    let kinds : Vec<(DifferenceKind, &str)> = strict.iter().map(|d| (d.kind.clone(), d.tag.as_str())).collect();
+    assert!(kinds.contains(&(DifferenceKind::Delimiters, "ISA")));
+    assert!(kinds.contains(&(DifferenceKind::LineEndings, "ISA")));
+    assert!(kinds.contains(&(DifferenceKind::Changed, "CLM")));
+    let isa = strict.iter().find(|d| d.kind == DifferenceKind::Changed && d.tag == "ISA").unwrap();
+    assert_eq!(isa.elements.iter().map(|e| e.element).collect::<Vec<_>>(), vec![13, 16]);
+
+    let options = DiffOptions {
+      ignore_delimiters: true,
+      ignore_line_endings: true,
+      ignore_control_numbers: true
+    };
+    let loose = diff_documents(&left, &right, &options);
+    let lines : Vec<String> = loose.iter().map(|d| d.to_string()).collect();
+    assert_eq!(lines, vec![
+      "lines 5/5, Changed NM1, interchange 1, group 1, transaction 837/0001; NM104 \"JOHN\" -> \"JANE\"",
+      "lines -/6, Added REF, interchange 1, group 1, transaction 837/0001",
+      "lines 8/9, Changed SE, interchange 1, group 1, transaction 837/0001; SE01 \"6\" -> \"7\"",
+      "lines 9/-, Removed ST, interchange 1, group 1, transaction 837/0002",
+      "lines 12/10, Changed GE, interchange 1, group 1; GE01 \"2\" -> \"1\""
+    ]);
+    assert_eq!(loose[1].right.map(|l| l.segment_index), Some(5));
+  }
+}
@@ -0,0 +1,335 @@
+use crate::edi_segments::Segment;
+use crate::edi_validation::{is_numeric, is_decimal, parse_date, parse_time};
+use std::cmp::Ordering;
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ElementValue<T> {
+  Absent,
+  Empty,
+  Malformed,
+  Present(T)
+}
+
+impl<T> ElementValue<T> {
+  pub fn value(self) -> Option<T> {
+    match self {
+      ElementValue::Present(v) => Some(v),
+      _ => None
+    }
+  }
+
+  pub fn is_present(&self) -> bool {
+    matches!(self, ElementValue::Present(_))
+  }
+
+  // Absent and empty elements are equivalent in X12 - both mean "not used".
+  pub fn is_unused(&self) -> bool {
+    matches!(self, ElementValue::Absent | ElementValue::Empty)
+  }
+
+  fn map<U, F: FnOnce(T) -> Option<U>>(self, f: F) -> ElementValue<U> {
+    match self {
+      ElementValue::Absent => ElementValue::Absent,
+      ElementValue::Empty => ElementValue::Empty,
+      ElementValue::Malformed => ElementValue::Malformed,
+      ElementValue::Present(v) => match f(v) {
+        Some(u) => ElementValue::Present(u),
+        None => ElementValue::Malformed
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+  pub mantissa: i128,
+  pub scale: u32
+}
+
+impl Decimal {
+  pub fn new(mantissa: i128, scale: u32) -> Self {
+    Decimal { mantissa, scale }
+  }
+
+  pub fn zero() -> Self {
+    Decimal { mantissa: 0, scale: 0 }
+  }
+
+  pub fn parse(value: &[u8]) -> Option<Decimal> {
+    if !is_decimal(value) {
+      return None;
+    }
+    let negative = value.first() == Some(&b'-');
+    let mut mantissa : i128 = 0;
+    let mut scale = 0;
+    let mut after_point = false;
+    for b in value {
+      match b {
+        b'.' => after_point = true,
+        b'-' => (),
+        d => {
+          mantissa = mantissa.checked_mul(10)?.checked_add((d - b'0') as i128)?;
+          if after_point {
+            scale += 1;
+          }
+        }
+      }
+    }
+    Some(Decimal { mantissa: if negative { -mantissa } else { mantissa }, scale })
+  }
+
+  pub fn to_f64(&self) -> f64 {
+    self.mantissa as f64 / 10f64.powi(self.scale as i32)
+  }
+
+  // Rounds half away from zero to `scale` decimal places.
+  pub fn round(&self, scale: u32) -> Decimal {
+    if self.scale <= scale {
+      return *self;
+    }
+    // A divisor past i128 is bigger than twice any mantissa, so it rounds to zero.
+    let divisor = match 10i128.checked_pow(self.scale - scale) {
+      Some(d) => d,
+      None => return Decimal { mantissa: 0, scale }
+    };
+    let (quotient, remainder) = (self.mantissa / divisor, self.mantissa % divisor);
+    let away = remainder.unsigned_abs() >= divisor.unsigned_abs() - remainder.unsigned_abs();
+    let mantissa = match (away, self.mantissa < 0) {
+      (true, true) => quotient - 1,
+      (true, false) => quotient + 1,
+      (false, _) => quotient
+    };
+    Decimal { mantissa, scale }
+  }
+
+  // `None` when the mantissa would not fit.
+  fn rescale(&self, scale: u32) -> Option<i128> {
+    if self.mantissa == 0 {
+      return Some(0);
+    }
+    10i128.checked_pow(scale - self.scale).and_then(|p| self.mantissa.checked_mul(p))
+  }
+
+  // The amounts come from partner data, so there are no arithmetic
+  // operators that could panic; these give `None` on overflow instead.
+  pub fn checked_add(self, other: Decimal) -> Option<Decimal> {
+    let scale = self.scale.max(other.scale);
+    let mantissa = self.rescale(scale)?.checked_add(other.rescale(scale)?)?;
+    Some(Decimal { mantissa, scale })
+  }
+
+  pub fn checked_sub(self, other: Decimal) -> Option<Decimal> {
+    self.checked_add(other.checked_neg()?)
+  }
+
+  pub fn checked_mul(self, other: Decimal) -> Option<Decimal> {
+    let mantissa = self.mantissa.checked_mul(other.mantissa)?;
+    Some(Decimal { mantissa, scale: self.scale.checked_add(other.scale)? })
+  }
+
+  pub fn checked_neg(self) -> Option<Decimal> {
+    Some(Decimal { mantissa: self.mantissa.checked_neg()?, scale: self.scale })
+  }
+}
+
+impl PartialEq for Decimal {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Decimal {
+  fn cmp(&self, other: &Self) -> Ordering {
+    let scale = self.scale.max(other.scale);
+    // Only the side with the smaller scale is rescaled, so if that overflows
+    // it is further from zero than the other side.
+    match (self.rescale(scale), other.rescale(scale)) {
+      (Some(a), Some(b)) => a.cmp(&b),
+      (None, _) => self.mantissa.cmp(&0),
+      (_, None) => 0.cmp(&other.mantissa)
+    }
+  }
+}
+
+impl fmt::Display for Decimal {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let sign = if self.mantissa < 0 { "-" } else { "" };
+    let digits = self.mantissa.unsigned_abs().to_string();
+    if self.scale == 0 {
+      return write!(f, "{}{}", sign, digits);
+    }
+    let scale = self.scale as usize;
+    let padded = format!("{:0>width$}", digits, width = scale + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - scale);
+    write!(f, "{}{}.{}", sign, whole, fraction)
+  }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct EdiDate {
+  pub year: u32,
+  pub month: u32,
+  pub day: u32
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct EdiTime {
+  pub hour: u32,
+  pub minute: u32,
+  pub second: u32,
+  pub hundredths: u32
+}
+
+// Two digit years below this value are placed in the 2000s, the rest in the 1900s.
+const CENTURY_PIVOT : u32 = 50;
+
+impl EdiDate {
+  pub fn parse(value: &[u8]) -> Option<EdiDate> {
+    let (year, month, day) = parse_date(value)?;
+    let year = match value.len() {
+      6 if year < CENTURY_PIVOT => 2000 + year,
+      6 => 1900 + year,
+      _ => year
+    };
+    Some(EdiDate { year, month, day })
+  }
+}
+
+impl EdiTime {
+  pub fn parse(value: &[u8]) -> Option<EdiTime> {
+    let (hour, minute, second, hundredths) = parse_time(value)?;
+    Some(EdiTime { hour, minute, second, hundredths })
+  }
+}
+
+impl Segment {
+  pub fn element(&self, n: usize) -> ElementValue<&[u8]> {
+    match self.fields.get(n) {
+      None => ElementValue::Absent,
+      Some(f) if f.is_empty() => ElementValue::Empty,
+      Some(f) => ElementValue::Present(f.as_slice())
+    }
+  }
+
+  pub fn element_str(&self, n: usize) -> ElementValue<&str> {
+    self.element(n).map(|v| std::str::from_utf8(v).ok())
+  }
+
+  pub fn element_int(&self, n: usize) -> ElementValue<i64> {
+    self.element(n).map(|v| {
+      if is_numeric(v) {
+        std::str::from_utf8(v).ok()?.parse().ok()
+      } else {
+        None
+      }
+    })
+  }
+
+  pub fn element_decimal(&self, n: usize) -> ElementValue<Decimal> {
+    self.element(n).map(Decimal::parse)
+  }
+
+  // For Nn elements, where the decimal point is implied `places` digits from the right.
+  pub fn element_implied_decimal(&self, n: usize, places: u32) -> ElementValue<Decimal> {
+    self.element(n).map(|v| {
+      if is_numeric(v) {
+        let d = Decimal::parse(v)?;
+        Some(Decimal { mantissa: d.mantissa, scale: places })
+      } else {
+        None
+      }
+    })
+  }
+
+  pub fn element_date(&self, n: usize) -> ElementValue<EdiDate> {
+    self.element(n).map(EdiDate::parse)
+  }
+
+  pub fn element_time(&self, n: usize) -> ElementValue<EdiTime> {
+    self.element(n).map(EdiTime::parse)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ElementValue, Decimal, EdiDate, EdiTime};
+  use crate::edi_segments::Segment;
+
+  fn segment(raw: &str) -> Segment {
+    let fields : Vec<Vec<u8>> = raw.split('*').map(|f| Vec::from(f.as_bytes())).collect();
+    Segment {
+      tag: fields[0].clone(),
+      fields,
+      start_offset: 0,
+      end_offset: raw.len() as u64,
+      segment_index: 0,
+      raw: Vec::from(raw.as_bytes()),
+      stray: Vec::new(),
+      line: 1,
+      column: 1
+    }
+  }
+
+  #[test]
+  fn distinguishes_absent_empty_and_malformed() {
+    let s = segment("DTP*348**2023X101");
+    assert_eq!(s.element_str(1), ElementValue::Present("348"));
+    assert_eq!(s.element_str(2), ElementValue::Empty);
+    assert_eq!(s.element_date(3), ElementValue::Malformed);
+    assert_eq!(s.element_date(4), ElementValue::Absent);
+    assert!(s.element_date(4).is_unused());
+  }
+
+  #[test]
+  fn parses_numbers() {
+    let s = segment("AMT*12*-0.5*1050*1.*12a");
+    assert_eq!(s.element_int(1), ElementValue::Present(12));
+    assert_eq!(s.element_decimal(2), ElementValue::Present(Decimal::new(-5, 1)));
+    assert_eq!(s.element_implied_decimal(3, 2), ElementValue::Present(Decimal::new(1050, 2)));
+    assert_eq!(s.element_decimal(4), ElementValue::Present(Decimal::new(1, 0)));
+    assert_eq!(s.element_int(5), ElementValue::Malformed);
+    assert_eq!(format!("{}", Decimal::new(-5, 2)), "-0.05");
+    assert_eq!(Decimal::new(150, 2).checked_sub(Decimal::new(5, 1)), Some(Decimal::new(1, 0)));
+    assert_eq!(Decimal::new(15, 1).checked_mul(Decimal::new(333, 2)), Some(Decimal::new(4995, 3)));
+    assert_eq!(Decimal::new(4995, 3).round(2), Decimal::new(500, 2));
+    assert_eq!(Decimal::new(-4994, 3).round(2), Decimal::new(-499, 2));
+    assert_eq!(Decimal::new(-4995, 3).round(2), Decimal::new(-500, 2));
+  }
+
+  #[test]
+  fn compares_long_fractions_without_overflow() {
+    let tiny = Decimal::parse(b"0.0000000000000000000000000000000000000001").unwrap();
+    let hundred = Decimal::parse(b"100").unwrap();
+    assert_ne!(tiny, hundred);
+    assert_eq!(hundred.cmp(&tiny), std::cmp::Ordering::Greater);
+    assert!(tiny < hundred);
+    assert!(hundred.checked_neg().unwrap() < tiny);
+    assert_eq!(Decimal::new(i128::MIN, 0).checked_neg(), None);
+    assert_eq!(Decimal::new(0, 0), Decimal::new(0, 60));
+    assert_eq!(tiny.checked_add(hundred), None);
+    assert_eq!(Decimal::new(i128::MAX, 0).checked_mul(Decimal::new(2, 0)), None);
+    assert_eq!(Decimal::new(1, 2).checked_add(Decimal::new(5, 1)), Some(Decimal::new(51, 2)));
+    assert_eq!(tiny.round(2), Decimal::new(0, 2));
+    assert_eq!(Decimal::new(i128::MAX, 1).round(0), Decimal::new(i128::MAX / 10 + 1, 0));
+  }
+
+  #[test]
+  fn parses_dates_and_times() {
+    let s = segment("DTM*20240229*240301*991231*1230*123045*12304599");
+    assert_eq!(s.element_date(1).value(), Some(EdiDate { year: 2024, month: 2, day: 29 }));
+    assert_eq!(s.element_date(2).value(), Some(EdiDate { year: 2024, month: 3, day: 1 }));
+    assert_eq!(s.element_date(3).value(), Some(EdiDate { year: 1999, month: 12, day: 31 }));
+    assert_eq!(s.element_time(4).value(), Some(EdiTime { hour: 12, minute: 30, second: 0, hundredths: 0 }));
+    assert_eq!(s.element_time(5).value(), Some(EdiTime { hour: 12, minute: 30, second: 45, hundredths: 0 }));
+    assert_eq!(s.element_time(6).value(), Some(EdiTime { hour: 12, minute: 30, second: 45, hundredths: 99 }));
+  }
+}
@@ -0,0 +1,543 @@
+use crate::edi_common::{TransactionHandler, TransactionParser, Separators, Reference, DateReference, Entity, Name, Demographics};
+use crate::edi_claims::Procedure;
+use crate::edi_elements::Decimal;
+use crate::edi_segments::Segment;
+use crate::edi_writer::X12Writer;
+use std::io::{Write, Error};
+
+// AAA.  The position is that of the AAA segment.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Rejection {
+  pub valid_request: bool,
+  pub reason_code: Option<String>,
+  pub follow_up_code: Option<String>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl Rejection {
+  fn from_aaa(segment: &Segment) -> Self {
+    Rejection {
+      valid_request: segment.element(1).value() == Some(b"Y".as_slice()),
+      reason_code: segment.text(3),
+      follow_up_code: segment.text(4),
+      segment_index: segment.segment_index,
+      start_offset: segment.start_offset,
+      line: segment.line,
+      column: segment.column
+    }
+  }
+}
+
+// One EQ of a 270.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ServiceInquiry {
+  pub service_types: Vec<String>,
+  pub procedure: Option<Procedure>,
+  pub coverage_level: Option<String>,
+  pub insurance_type: Option<String>
+}
+
+// One EB of a 271 with the segments of its 2110 loop.  `service_types` holds
+// every repeat of EB03 and `related_entities` the NM1 loops between LS and LE.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Benefit {
+  pub information_code: String,
+  pub coverage_level: Option<String>,
+  pub service_types: Vec<String>,
+  pub insurance_type: Option<String>,
+  pub plan_description: Option<String>,
+  pub time_period: Option<String>,
+  pub amount: Option<Decimal>,
+  pub percent: Option<Decimal>,
+  pub quantity_qualifier: Option<String>,
+  pub quantity: Option<Decimal>,
+  pub authorization_required: Option<String>,
+  pub in_network: Option<String>,
+  pub procedure: Option<Procedure>,
+  pub diagnosis_pointers: Vec<String>,
+  pub dates: Vec<DateReference>,
+  pub references: Vec<Reference>,
+  pub messages: Vec<String>,
+  pub rejections: Vec<Rejection>,
+  pub related_entities: Vec<Entity>
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum EligibilityLevel {
+  Subscriber,
+  Dependent
+}
+
+// A 2000C or 2000D loop.  A 270 fills `inquiries` and a 271 fills
+// `benefits`.  The position is that of its HL segment.
+#[derive(PartialEq, Debug, Clone)]
+pub struct EligibilityMember {
+  pub level: EligibilityLevel,
+  pub hl_id: String,
+  pub trace_numbers: Vec<Reference>,
+  pub entity: Option<Entity>,
+  pub demographics: Option<Demographics>,
+  pub dates: Vec<DateReference>,
+  pub rejections: Vec<Rejection>,
+  pub inquiries: Vec<ServiceInquiry>,
+  pub benefits: Vec<Benefit>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+// The information source (2000A) and receiver (2000B) above the member being
+// handed over, with any AAA found at those levels.  `subscriber` is set for
+// dependents.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct EligibilityParties {
+  pub set_id: String,
+  pub control_number: String,
+  pub source: Option<Entity>,
+  pub receiver: Option<Entity>,
+  pub rejections: Vec<Rejection>,
+  pub subscriber: Option<Entity>
+}
+
+pub trait EligibilityHandler {
+  fn member(&mut self, parties: &EligibilityParties, member: EligibilityMember);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Loop {
+  Header,
+  Source,
+  Receiver,
+  Member,
+  Benefit,
+  BenefitEntity,
+  Skipped
+}
+
+// Reads 270 and 271 transaction sets and hands over each subscriber and
+// dependent as soon as the next HL or the SE is seen.  Other transaction sets
+// are skipped.
+pub struct EligibilityReader<T: EligibilityHandler> {
+  parties: EligibilityParties,
+  member: Option<EligibilityMember>,
+  current: Loop,
+  handler: T
+}
+
+pub type EligibilityParser<T> = TransactionParser<EligibilityReader<T>>;
+
+impl<T: EligibilityHandler> EligibilityReader<T> {
+  pub fn new(handler: T) -> Self {
+    EligibilityReader {
+      parties: EligibilityParties::default(),
+      member: None,
+      current: Loop::Skipped,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+
+  fn flush(&mut self) {
+    if let Some(member) = self.member.take() {
+      if member.level == EligibilityLevel::Subscriber {
+        self.parties.subscriber = member.entity.clone();
+      }
+      self.handler.member(&self.parties, member);
+    }
+  }
+
+  fn hierarchical_level(&mut self, segment: &Segment) {
+    self.flush();
+    let level = match segment.element(3).value() {
+      Some(b"20") => {
+        self.parties.source = None;
+        self.parties.receiver = None;
+        self.parties.rejections.clear();
+        self.current = Loop::Source;
+        return;
+      },
+      Some(b"21") => {
+        self.parties.receiver = None;
+        self.current = Loop::Receiver;
+        return;
+      },
+      Some(b"22") => {
+        self.parties.subscriber = None;
+        EligibilityLevel::Subscriber
+      },
+      Some(b"23") => EligibilityLevel::Dependent,
+      _ => {
+        self.current = Loop::Header;
+        return;
+      }
+    };
+    self.member = Some(EligibilityMember {
+      level,
+      hl_id: segment.text(1).unwrap_or_default(),
+      trace_numbers: Vec::new(),
+      entity: None,
+      demographics: None,
+      dates: Vec::new(),
+      rejections: Vec::new(),
+      inquiries: Vec::new(),
+      benefits: Vec::new(),
+      segment_index: segment.segment_index,
+      start_offset: segment.start_offset,
+      line: segment.line,
+      column: segment.column
+    });
+    self.current = Loop::Member;
+  }
+
+  fn party_segment(&mut self, segment: &Segment) {
+    let parties = &mut self.parties;
+    let entity = match self.current {
+      Loop::Source => &mut parties.source,
+      Loop::Receiver => &mut parties.receiver,
+      _ => return
+    };
+    match segment.tag.as_slice() {
+      b"NM1" => *entity = Some(Entity::from_nm1(segment)),
+      b"AAA" => parties.rejections.push(Rejection::from_aaa(segment)),
+      _ => {
+        if let Some(e) = entity.as_mut() {
+          e.apply(segment);
+        }
+      }
+    }
+  }
+
+  fn member_segment(&mut self, segment: &Segment, separators: &Separators) {
+    let member = match self.member.as_mut() {
+      None => return,
+      Some(m) => m
+    };
+    let tag = segment.tag.as_slice();
+    self.current = match (tag, self.current) {
+      (b"EB", _) => {
+        member.benefits.push(Benefit {
+          information_code: segment.text(1).unwrap_or_default(),
+          coverage_level: segment.text(2),
+          service_types: segment.repetitions(3, separators).into_iter().filter_map(|r| r.into_iter().next()).collect(),
+          insurance_type: segment.text(4),
+          plan_description: segment.text(5),
+          time_period: segment.text(6),
+          amount: segment.element_decimal(7).value(),
+          percent: segment.element_decimal(8).value(),
+          quantity_qualifier: segment.text(9),
+          quantity: segment.element_decimal(10).value(),
+          authorization_required: segment.text(11),
+          in_network: segment.text(12),
+          procedure: Procedure::from_components(segment.components(13, separators)),
+          diagnosis_pointers: segment.components(14, separators).into_iter().filter(|p| !p.is_empty()).collect(),
+          ..Benefit::default()
+        });
+        Loop::Benefit
+      },
+      (b"EQ", _) => {
+        member.inquiries.push(ServiceInquiry {
+          service_types: segment.repetitions(1, separators).into_iter().filter_map(|r| r.into_iter().next()).collect(),
+          procedure: Procedure::from_components(segment.components(2, separators)),
+          coverage_level: segment.text(3),
+          insurance_type: segment.text(4)
+        });
+        Loop::Member
+      },
+      (b"LS", Loop::Benefit) => Loop::BenefitEntity,
+      (b"LE", _) => Loop::Benefit,
+      (_, Loop::BenefitEntity) => {
+        if let Some(benefit) = member.benefits.last_mut() {
+          match tag {
+            b"NM1" => benefit.related_entities.push(Entity::from_nm1(segment)),
+            _ => {
+              if let Some(entity) = benefit.related_entities.last_mut() {
+                entity.apply(segment);
+              }
+            }
+          }
+        }
+        Loop::BenefitEntity
+      },
+      (_, Loop::Benefit) => {
+        if let Some(benefit) = member.benefits.last_mut() {
+          match tag {
+            b"DTP" => benefit.dates.push(DateReference::from_dtp(segment)),
+            b"REF" => benefit.references.push(Reference::from_ref(segment)),
+            b"MSG" => benefit.messages.extend(segment.text(1)),
+            b"AAA" => benefit.rejections.push(Rejection::from_aaa(segment)),
+            _ => ()
+          }
+        }
+        Loop::Benefit
+      },
+      (_, current) => {
+        match tag {
+          b"TRN" => member.trace_numbers.push(Reference {
+            qualifier: segment.text(1).unwrap_or_default(),
+            value: segment.text(2).unwrap_or_default(),
+            description: segment.text(3)
+          }),
+          b"NM1" => member.entity = Some(Entity::from_nm1(segment)),
+          b"DMG" => member.demographics = Some(Demographics::from_dmg(segment)),
+          b"DTP" => member.dates.push(DateReference::from_dtp(segment)),
+          b"AAA" => member.rejections.push(Rejection::from_aaa(segment)),
+          _ => {
+            if let Some(entity) = member.entity.as_mut() {
+              entity.apply(segment);
+            }
+          }
+        }
+        current
+      }
+    };
+  }
+}
+
+impl<T: EligibilityHandler> TransactionHandler for EligibilityReader<T> {
+  fn transaction_start(&mut self, header: &Segment, _separators: &Separators) {
+    let set_id = header.text(1).unwrap_or_default();
+    if set_id != "270" && set_id != "271" {
+      self.current = Loop::Skipped;
+      return;
+    }
+    self.parties = EligibilityParties {
+      set_id,
+      control_number: header.text(2).unwrap_or_default(),
+      ..EligibilityParties::default()
+    };
+    self.current = Loop::Header;
+  }
+
+  fn segment(&mut self, segment: &Segment, separators: &Separators) {
+    if self.current == Loop::Skipped {
+      return;
+    }
+    match (segment.tag.as_slice(), self.current) {
+      (b"HL", _) => self.hierarchical_level(segment),
+      (_, Loop::Header) => (),
+      (_, Loop::Source) | (_, Loop::Receiver) => self.party_segment(segment),
+      _ => self.member_segment(segment, separators)
+    }
+  }
+
+  fn transaction_end(&mut self, _trailer: Option<&Segment>) {
+    self.flush();
+    self.current = Loop::Skipped;
+  }
+}
+
+// A subscriber or dependent to ask about.  `date` becomes a DTP with its own
+// qualifier, usually 291 for the plan date, and each of `service_types` its
+// own EQ.
+pub struct InquiryMember {
+  pub trace_number: String,
+  pub trace_originator: String,
+  pub name: Name,
+  pub demographics: Option<Demographics>,
+  pub date: Option<DateReference>,
+  pub service_types: Vec<String>
+}
+
+pub struct EligibilityRequest {
+  pub source: Name,
+  pub receiver: Name,
+  pub subscriber: InquiryMember,
+  pub dependent: Option<InquiryMember>
+}
+
+fn write_member<W: Write>(writer: &mut X12Writer<W>, member: &InquiryMember) -> Result<(), Error> {
+  writer.segment("TRN", &["1", &member.trace_number, &member.trace_originator])?;
+  writer.nm1(&member.name)?;
+  if let Some(demographics) = &member.demographics {
+    writer.dmg(demographics)?;
+  }
+  if let Some(date) = &member.date {
+    writer.segment("DTP", &[&date.qualifier, &date.format, &date.value])?;
+  }
+  for service_type in member.service_types.iter() {
+    writer.segment("EQ", &[service_type])?;
+  }
+  Ok(())
+}
+
+// Writes a 270 holding one HL chain for each request.  The writer has to be
+// inside a functional group.
+pub fn write_eligibility_request<W: Write>(writer: &mut X12Writer<W>, control_number: &str, reference: &str, date: &str, time: &str, requests: &[EligibilityRequest]) -> Result<(), Error> {
+  writer.begin_transaction("270", control_number, Some("005010X279A1"))?;
+  writer.segment("BHT", &["0022", "13", reference, date, time])?;
+  let mut hl = 0;
+  for request in requests.iter() {
+    let source = hl + 1;
+    writer.segment("HL", &[&source.to_string(), "", "20", "1"])?;
+    writer.nm1(&request.source)?;
+    let receiver = source + 1;
+    writer.segment("HL", &[&receiver.to_string(), &source.to_string(), "21", "1"])?;
+    writer.nm1(&request.receiver)?;
+    let subscriber = receiver + 1;
+    let child = if request.dependent.is_some() { "1" } else { "0" };
+    writer.segment("HL", &[&subscriber.to_string(), &receiver.to_string(), "22", child])?;
+    write_member(writer, &request.subscriber)?;
+    hl = subscriber;
+    if let Some(dependent) = &request.dependent {
+      hl += 1;
+      writer.segment("HL", &[&hl.to_string(), &subscriber.to_string(), "23", "0"])?;
+      write_member(writer, dependent)?;
+    }
+  }
+  writer.end_transaction()
+}
+
+#[cfg(test)]
+mod test {
+  use super::{EligibilityHandler, EligibilityParties, EligibilityReader, EligibilityMember, EligibilityLevel, EligibilityRequest, InquiryMember, write_eligibility_request};
+  use crate::edi_common::{Name, DateReference};
+  use crate::edi_common::testing::{Collector, read_transactions};
+  use crate::edi_writer::{X12Writer, InterchangeHeader, GroupHeader};
+  use crate::edi_elements::{Decimal, EdiDate, EdiTime};
+
+  impl EligibilityHandler for Collector<(EligibilityParties, EligibilityMember)> {
+    fn member(&mut self, parties: &EligibilityParties, member: EligibilityMember) {
+      self.items.push((parties.clone(), member));
+    }
+  }
+
+  fn read(raw: &[u8]) -> Vec<(EligibilityParties, EligibilityMember)> {
+    read_transactions(raw, EligibilityReader::new(Collector::new())).into_handler().items
+  }
+
+  #[test]
+  fn reads_benefits_and_rejections() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*PAYER          *ZZ*PROVIDER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HB*PAYER*PROVIDER*20230101*1200*1*X*005010X279A1~
+ST*271*0001*005010X279A1~
+BHT*0022*11*REF1*20230101*1200~
+HL*1**20*1~
+NM1*PR*2*ABC COMPANY*****PI*842610001~
+HL*2*1*21*1~
+NM1*1P*1*JONES*MARCUS****XX*1234567893~
+HL*3*2*22*1~
+TRN*2*93175-012547*9877281234~
+NM1*IL*1*SMITH*ROBERT****MI*11122333301~
+DMG*D8*19430519*M~
+DTP*346*D8*20230101~
+EB*1*FAM*30^1^35*HM*GOLD PLAN~
+EB*C*IND*30***23*500*****Y~
+DTP*292*RD8*20230101-20231231~
+MSG*DEDUCTIBLE APPLIES~
+EB*B**98^UC*HM**27*25*****Y~
+LS*2120~
+NM1*P3*1*JONES*MARCUS****XX*1234567893~
+N3*15 N MAIN ST~
+LE*2120~
+HL*4*3*23*0~
+NM1*03*1*SMITH*MARY~
+AAA*N**72*C~
+SE*24*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let members = read(raw.as_bytes());
+    assert_eq!(members.len(), 2);
+    let (parties, subscriber) = &members[0];
+    assert_eq!(parties.set_id, "271");
+    assert_eq!(parties.source.as_ref().map(|s| s.name.last_name.as_str()), Some("ABC COMPANY"));
+    assert_eq!(parties.receiver.as_ref().and_then(|r| r.name.id.clone()), Some(String::from("1234567893")));
+    assert_eq!(subscriber.level, EligibilityLevel::Subscriber);
+    assert_eq!(subscriber.trace_numbers[0].value, "93175-012547");
+    assert_eq!(subscriber.dates.len(), 1);
+    assert_eq!(subscriber.benefits.len(), 3);
+    assert_eq!(subscriber.benefits[0].service_types, vec!["30", "1", "35"]);
+    assert_eq!(subscriber.benefits[1].amount, Some(Decimal::new(500, 0)));
+    assert_eq!(subscriber.benefits[1].in_network.as_deref(), Some("Y"));
+    assert_eq!(subscriber.benefits[1].dates.len(), 1);
+    assert_eq!(subscriber.benefits[1].messages, vec![String::from("DEDUCTIBLE APPLIES")]);
+    assert_eq!(subscriber.benefits[2].service_types, vec!["98", "UC"]);
+    assert_eq!(subscriber.benefits[2].related_entities[0].address.lines, vec![String::from("15 N MAIN ST")]);
+    let (parties, dependent) = &members[1];
+    assert_eq!(parties.subscriber.as_ref().map(|s| s.name.last_name.as_str()), Some("SMITH"));
+    assert_eq!(dependent.level, EligibilityLevel::Dependent);
+    assert_eq!(dependent.rejections[0].reason_code.as_deref(), Some("72"));
+    assert!(!dependent.rejections[0].valid_request);
+    assert_eq!(dependent.rejections[0].line, 25);
+    assert_eq!(dependent.rejections[0].start_offset, raw.find("AAA*N**72").unwrap() as u64);
+    assert_eq!(dependent.rejections[0].column, 1);
+  }
+
+  #[test]
+  fn writes_requests() {
+    let mut writer = X12Writer::new(Vec::new());
+    let date = EdiDate { year: 2023, month: 1, day: 2 };
+    let time = EdiTime { hour: 9, minute: 5, second: 0, hundredths: 0 };
+    writer.begin_interchange(&InterchangeHeader {
+      sender_qualifier: String::from("ZZ"),
+      sender_id: String::from("PROVIDER"),
+      receiver_qualifier: String::from("ZZ"),
+      receiver_id: String::from("PAYER"),
+      date,
+      time,
+      control_number: 1,
+      version: String::from("00501"),
+      acknowledgment_requested: false,
+      test: false
+    }).unwrap();
+    writer.begin_group(&GroupHeader {
+      functional_id: String::from("HS"),
+      sender: String::from("PROVIDER"),
+      receiver: String::from("PAYER"),
+      date,
+      time,
+      control_number: 1,
+      version: String::from("005010X279A1")
+    }).unwrap();
+    let name = |code: &str, entity_type: &str, last: &str, id: Option<&str>| Name {
+      entity_code: String::from(code),
+      entity_type: String::from(entity_type),
+      last_name: String::from(last),
+      id_qualifier: id.map(|_| String::from("PI")),
+      id: id.map(String::from),
+      ..Name::default()
+    };
+    let request = EligibilityRequest {
+      source: name("PR", "2", "ABC COMPANY", Some("842610001")),
+      receiver: name("1P", "1", "JONES", Some("1234567893")),
+      subscriber: InquiryMember {
+        trace_number: String::from("93175-012547"),
+        trace_originator: String::from("9877281234"),
+        name: name("IL", "1", "SMITH", Some("11122333301")),
+        demographics: None,
+        date: Some(DateReference { qualifier: String::from("291"), format: String::from("D8"), value: String::from("20230102") }),
+        service_types: vec![String::from("30")]
+      },
+      dependent: Some(InquiryMember {
+        trace_number: String::from("93175-012548"),
+        trace_originator: String::from("9877281234"),
+        name: name("03", "1", "SMITH", None),
+        demographics: None,
+        date: Some(DateReference { qualifier: String::from("472"), format: String::from("D8"), value: String::from("20221215") }),
+        service_types: vec![String::from("1"), String::from("35")]
+      })
+    };
+    write_eligibility_request(&mut writer, "0001", "REF1", "20230102", "0905", &[request]).unwrap();
+    writer.end_group().unwrap();
+    writer.end_interchange().unwrap();
+    let raw = writer.into_inner();
+    assert!(String::from_utf8_lossy(&raw).contains("HL*4*3*23*0~\nTRN*1*93175-012548*9877281234~\nNM1*03*1*SMITH~\nDTP*472*D8*20221215~\nEQ*1~\nEQ*35~\nSE*18*0001~"));
+    let members = read(&raw);
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[0].0.set_id, "270");
+    assert_eq!(members[0].1.inquiries[0].service_types, vec!["30"]);
+    assert_eq!(members[1].1.inquiries.len(), 2);
+  }
+}
@@ -0,0 +1,465 @@
+use crate::edi_common::{TransactionHandler, TransactionParser, Separators, Reference, DateReference, Amount, Party, Entity, Demographics};
+use crate::edi_elements::EdiDate;
+use crate::edi_segments::Segment;
+
+// The 834 up to the first INS: BGN and the 1000 loop parties.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct EnrollmentHeader {
+  pub control_number: String,
+  pub purpose_code: Option<String>,
+  pub reference: Option<String>,
+  pub date: Option<EdiDate>,
+  pub action_code: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub parties: Vec<Party>
+}
+
+impl EnrollmentHeader {
+  fn party(&self, code: &str) -> Option<&Party> {
+    self.parties.iter().find(|p| p.entity_code == code)
+  }
+
+  pub fn sponsor(&self) -> Option<&Party> {
+    self.party("P5")
+  }
+
+  pub fn payer(&self) -> Option<&Party> {
+    self.party("IN")
+  }
+}
+
+// A 2100 loop.  2100A is the member name (NM1*IL), 2100B an incorrect name
+// (NM1*70) and 2100C the mailing address (NM1*31).
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct MemberName {
+  pub entity: Entity,
+  pub demographics: Option<Demographics>,
+  pub amounts: Vec<Amount>
+}
+
+// 2200
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Disability {
+  pub type_code: Option<String>,
+  pub dates: Vec<DateReference>
+}
+
+// 2310, started by LX.  `change_reason` is PLA05.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct CoverageProvider {
+  pub entity: Entity,
+  pub change_reason: Option<String>
+}
+
+// 2320 with its 2330 entities.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct CoordinationOfBenefits {
+  pub payer_responsibility: Option<String>,
+  pub policy_number: Option<String>,
+  pub coordination_code: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub entities: Vec<Entity>
+}
+
+// 2300
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Coverage {
+  pub maintenance_type: Option<String>,
+  pub insurance_line: Option<String>,
+  pub plan_description: Option<String>,
+  pub coverage_level: Option<String>,
+  pub dates: Vec<DateReference>,
+  pub amounts: Vec<Amount>,
+  pub references: Vec<Reference>,
+  pub providers: Vec<CoverageProvider>,
+  pub coordination: Vec<CoordinationOfBenefits>
+}
+
+// One 2000 loop.  The position is that of its INS segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Member {
+  pub subscriber: bool,
+  pub relationship_code: String,
+  pub maintenance_type: Option<String>,
+  pub maintenance_reason: Option<String>,
+  pub benefit_status: Option<String>,
+  pub employment_status: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub names: Vec<MemberName>,
+  pub disabilities: Vec<Disability>,
+  pub coverages: Vec<Coverage>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl Member {
+  fn name_loop(&self, code: &str) -> Option<&MemberName> {
+    self.names.iter().find(|n| n.entity.name.entity_code == code)
+  }
+
+  pub fn member_name(&self) -> Option<&MemberName> {
+    self.name_loop("IL")
+  }
+
+  pub fn incorrect_name(&self) -> Option<&MemberName> {
+    self.name_loop("70")
+  }
+
+  pub fn mailing_address(&self) -> Option<&MemberName> {
+    self.name_loop("31")
+  }
+
+  // REF*0F
+  pub fn subscriber_id(&self) -> Option<&str> {
+    self.references.iter().find(|r| r.qualifier == "0F").map(|r| r.value.as_str())
+  }
+}
+
+pub trait EnrollmentHandler {
+  fn member(&mut self, header: &EnrollmentHeader, member: Member);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Loop {
+  Header,
+  HeaderParty,
+  Member,
+  MemberName,
+  Disability,
+  Coverage,
+  Provider,
+  Coordination,
+  CoordinationEntity,
+  Reporting,
+  Skipped
+}
+
+// Assembles each 2000 loop of an 834 and hands it to the handler as soon as
+// the next INS or the SE is seen, so only one member is held at a time.
+// Other transaction sets are skipped.
+pub struct EnrollmentReader<T: EnrollmentHandler> {
+  header: EnrollmentHeader,
+  member: Option<Member>,
+  current: Loop,
+  handler: T
+}
+
+pub type EnrollmentParser<T> = TransactionParser<EnrollmentReader<T>>;
+
+impl<T: EnrollmentHandler> EnrollmentReader<T> {
+  pub fn new(handler: T) -> Self {
+    EnrollmentReader {
+      header: EnrollmentHeader::default(),
+      member: None,
+      current: Loop::Skipped,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+
+  fn flush(&mut self) {
+    if let Some(member) = self.member.take() {
+      self.handler.member(&self.header, member);
+    }
+  }
+
+  fn header_segment(&mut self, segment: &Segment) {
+    let header = &mut self.header;
+    if self.current == Loop::HeaderParty {
+      if let Some(party) = header.parties.last_mut() {
+        if party.apply(segment) {
+          return;
+        }
+      }
+    }
+    match segment.tag.as_slice() {
+      b"BGN" => {
+        header.purpose_code = segment.text(1);
+        header.reference = segment.text(2);
+        header.date = segment.element_date(3).value();
+        header.action_code = segment.text(8);
+      },
+      b"REF" => header.references.push(Reference::from_ref(segment)),
+      b"DTP" => header.dates.push(DateReference::from_dtp(segment)),
+      b"N1" => {
+        header.parties.push(Party::from_n1(segment));
+        self.current = Loop::HeaderParty;
+      },
+      _ => ()
+    }
+  }
+
+  fn member_segment(&mut self, segment: &Segment) {
+    let member = match self.member.as_mut() {
+      None => return,
+      Some(m) => m
+    };
+    let tag = segment.tag.as_slice();
+    let current = match tag {
+      b"NM1" => match self.current {
+        Loop::Provider => {
+          if let Some(provider) = last_provider(member) {
+            provider.entity = Entity::from_nm1(segment);
+          }
+          Loop::Provider
+        },
+        Loop::Coordination | Loop::CoordinationEntity => {
+          if let Some(cob) = last_coordination(member) {
+            cob.entities.push(Entity::from_nm1(segment));
+          }
+          Loop::CoordinationEntity
+        },
+        Loop::Reporting => Loop::Reporting,
+        _ => {
+          member.names.push(MemberName {
+            entity: Entity::from_nm1(segment),
+            ..MemberName::default()
+          });
+          Loop::MemberName
+        }
+      },
+      b"DSB" => {
+        member.disabilities.push(Disability {
+          type_code: segment.text(1),
+          dates: Vec::new()
+        });
+        Loop::Disability
+      },
+      b"HD" => {
+        member.coverages.push(Coverage {
+          maintenance_type: segment.text(1),
+          insurance_line: segment.text(3),
+          plan_description: segment.text(4),
+          coverage_level: segment.text(5),
+          ..Coverage::default()
+        });
+        Loop::Coverage
+      },
+      b"LX" if self.current != Loop::Reporting => {
+        if let Some(coverage) = member.coverages.last_mut() {
+          coverage.providers.push(CoverageProvider::default());
+        }
+        Loop::Provider
+      },
+      b"COB" => {
+        if let Some(coverage) = member.coverages.last_mut() {
+          coverage.coordination.push(CoordinationOfBenefits {
+            payer_responsibility: segment.text(1),
+            policy_number: segment.text(2),
+            coordination_code: segment.text(3),
+            ..CoordinationOfBenefits::default()
+          });
+        }
+        Loop::Coordination
+      },
+      b"LS" => Loop::Reporting,
+      _ => {
+        apply_member_segment(member, self.current, segment);
+        self.current
+      }
+    };
+    self.current = current;
+  }
+}
+
+fn last_provider(member: &mut Member) -> Option<&mut CoverageProvider> {
+  member.coverages.last_mut().and_then(|c| c.providers.last_mut())
+}
+
+fn last_coordination(member: &mut Member) -> Option<&mut CoordinationOfBenefits> {
+  member.coverages.last_mut().and_then(|c| c.coordination.last_mut())
+}
+
+// Segments that do not start a loop go to the loop they are in.
+fn apply_member_segment(member: &mut Member, current: Loop, segment: &Segment) {
+  let tag = segment.tag.as_slice();
+  match current {
+    Loop::Member => match tag {
+      b"REF" => member.references.push(Reference::from_ref(segment)),
+      b"DTP" => member.dates.push(DateReference::from_dtp(segment)),
+      _ => ()
+    },
+    Loop::MemberName => if let Some(name) = member.names.last_mut() {
+      match tag {
+        b"DMG" => name.demographics = Some(Demographics::from_dmg(segment)),
+        b"AMT" => name.amounts.push(Amount::from_amt(segment)),
+        _ => {
+          name.entity.apply(segment);
+        }
+      }
+    },
+    Loop::Disability => if let (Some(disability), b"DTP") = (member.disabilities.last_mut(), tag) {
+      disability.dates.push(DateReference::from_dtp(segment));
+    },
+    Loop::Coverage => if let Some(coverage) = member.coverages.last_mut() {
+      match tag {
+        b"DTP" => coverage.dates.push(DateReference::from_dtp(segment)),
+        b"AMT" => coverage.amounts.push(Amount::from_amt(segment)),
+        b"REF" => coverage.references.push(Reference::from_ref(segment)),
+        _ => ()
+      }
+    },
+    Loop::Provider => if let Some(provider) = last_provider(member) {
+      match tag {
+        b"PLA" => provider.change_reason = segment.text(5),
+        _ => {
+          provider.entity.apply(segment);
+        }
+      }
+    },
+    Loop::Coordination => if let Some(cob) = last_coordination(member) {
+      match tag {
+        b"REF" => cob.references.push(Reference::from_ref(segment)),
+        b"DTP" => cob.dates.push(DateReference::from_dtp(segment)),
+        _ => ()
+      }
+    },
+    Loop::CoordinationEntity => if let Some(entity) = last_coordination(member).and_then(|c| c.entities.last_mut()) {
+      entity.apply(segment);
+    },
+    _ => ()
+  }
+}
+
+impl<T: EnrollmentHandler> TransactionHandler for EnrollmentReader<T> {
+  fn transaction_start(&mut self, header: &Segment, _separators: &Separators) {
+    if header.element(1).value() == Some(b"834".as_slice()) {
+      self.header = EnrollmentHeader {
+        control_number: header.text(2).unwrap_or_default(),
+        ..EnrollmentHeader::default()
+      };
+      self.current = Loop::Header;
+    } else {
+      self.current = Loop::Skipped;
+    }
+  }
+
+  fn segment(&mut self, segment: &Segment, _separators: &Separators) {
+    match (self.current, segment.tag.as_slice()) {
+      (Loop::Skipped, _) => (),
+      (_, b"INS") => {
+        self.flush();
+        self.member = Some(Member {
+          subscriber: segment.element(1).value() == Some(b"Y".as_slice()),
+          relationship_code: segment.text(2).unwrap_or_default(),
+          maintenance_type: segment.text(3),
+          maintenance_reason: segment.text(4),
+          benefit_status: segment.text(5),
+          employment_status: segment.text(8),
+          segment_index: segment.segment_index,
+          start_offset: segment.start_offset,
+          line: segment.line,
+          column: segment.column,
+          ..Member::default()
+        });
+        self.current = Loop::Member;
+      },
+      (Loop::Header, _) | (Loop::HeaderParty, _) => self.header_segment(segment),
+      _ => self.member_segment(segment)
+    }
+  }
+
+  fn transaction_end(&mut self, _trailer: Option<&Segment>) {
+    self.flush();
+    self.current = Loop::Skipped;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{EnrollmentHandler, EnrollmentHeader, EnrollmentReader, Member};
+  use crate::edi_common::testing::{Collector, read_transactions};
+  use crate::edi_elements::EdiDate;
+
+  impl EnrollmentHandler for Collector<(String, Member)> {
+    fn member(&mut self, header: &EnrollmentHeader, member: Member) {
+      let sponsor = header.sponsor().and_then(|p| p.name.clone()).unwrap_or_default();
+      self.items.push((sponsor, member));
+    }
+  }
+
+  #[test]
+  fn streams_members() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SPONSOR        *ZZ*PAYER          *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SPONSOR*PAYER*20230101*1200*1*X*005010X220A1~
+ST*834*0001*005010X220A1~
+BGN*00*12456*20230101*1200****4~
+REF*38*GROUP1~
+N1*P5*ACME CORP*FI*123456789~
+N1*IN*HEALTH PLAN*FI*987654321~
+INS*Y*18*021*28*A***FT~
+REF*0F*SUB001~
+DTP*336*D8*20200101~
+NM1*IL*1*DOE*JOHN*Q***34*123456789~
+PER*IP**HP*5551234567~
+N3*1 MAIN ST~
+N4*ANYTOWN*NY*12345~
+DMG*D8*19800101*M*M~
+NM1*31*1~
+N3*PO BOX 9~
+N4*ANYTOWN*NY*12345~
+HD*021**HLT*PLAN A*FAM~
+DTP*348*D8*20230101~
+LX*1~
+NM1*P3*1*SMITH*ANNA****XX*1234567890~
+PLA*2*1P*20230101**14~
+COB*P*POL99*1~
+REF*6P*G77~
+NM1*IN*2*OTHER INSURER~
+N3*9 ELM ST~
+INS*N*19*021*28*A~
+REF*0F*SUB001~
+NM1*IL*1*DOE*JANE~
+DSB*2~
+DTP*360*D8*20220101~
+HD*021**DEN**FAM~
+SE*29*0001~
+ST*999*0002~
+AK1*BE*1~
+SE*3*0002~
+GE*2*1~
+IEA*1*000000001~
+";
+    let members = read_transactions(raw.as_bytes(), EnrollmentReader::new(Collector::new())).into_handler().items;
+    assert_eq!(members.len(), 2);
+    let (sponsor, subscriber) = &members[0];
+    assert_eq!(sponsor, "ACME CORP");
+    assert!(subscriber.subscriber);
+    assert_eq!(subscriber.subscriber_id(), Some("SUB001"));
+    assert_eq!(subscriber.employment_status.as_deref(), Some("FT"));
+    assert_eq!(subscriber.line, 8);
+    let name = subscriber.member_name().unwrap();
+    assert_eq!(name.entity.name.first_name.as_deref(), Some("JOHN"));
+    assert_eq!(name.entity.address.city.as_deref(), Some("ANYTOWN"));
+    assert_eq!(name.entity.contacts.len(), 1);
+    assert_eq!(name.demographics.as_ref().and_then(|d| d.birth_date), Some(EdiDate { year: 1980, month: 1, day: 1 }));
+    assert_eq!(subscriber.mailing_address().map(|m| m.entity.address.lines.clone()), Some(vec![String::from("PO BOX 9")]));
+    let coverage = &subscriber.coverages[0];
+    assert_eq!(coverage.insurance_line.as_deref(), Some("HLT"));
+    assert_eq!(coverage.dates[0].date(), Some(EdiDate { year: 2023, month: 1, day: 1 }));
+    assert_eq!(coverage.providers[0].entity.name.id.as_deref(), Some("1234567890"));
+    assert_eq!(coverage.providers[0].change_reason.as_deref(), Some("14"));
+    let cob = &coverage.coordination[0];
+    assert_eq!(cob.policy_number.as_deref(), Some("POL99"));
+    assert_eq!(cob.references.len(), 1);
+    assert_eq!(cob.entities[0].address.lines, vec![String::from("9 ELM ST")]);
+    let (_, dependent) = &members[1];
+    assert!(!dependent.subscriber);
+    assert_eq!(dependent.disabilities[0].dates.len(), 1);
+    assert_eq!(dependent.coverages[0].insurance_line.as_deref(), Some("DEN"));
+    assert!(dependent.dates.is_empty());
+  }
+}
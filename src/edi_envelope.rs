@@ -0,0 +1,21 @@
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum EnvelopeState {
+  Nothing,
+  InInterchange,
+  InFunctionalGroup,
+  InTransaction
+}
+
+impl EnvelopeState {
+  pub fn in_interchange(&self) -> bool {
+    (*self == EnvelopeState::InTransaction) || (*self == EnvelopeState::InFunctionalGroup) || (*self == EnvelopeState::InInterchange)
+  }
+
+  pub fn in_functional_group(&self) -> bool {
+    (*self == EnvelopeState::InTransaction) || (*self == EnvelopeState::InFunctionalGroup)
+  }
+
+  pub fn in_transaction(&self) -> bool {
+    *self == EnvelopeState::InTransaction
+  }
+}
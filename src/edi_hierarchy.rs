@@ -0,0 +1,376 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::Segment;
+use crate::edi_envelope::EnvelopeState;
+use crate::edi_constants::{HL_TAG, SE_TAG};
+use std::collections::HashMap;
+use std::io::Error;
+use std::fmt;
+
+pub struct HierarchicalLevel {
+  pub id: Vec<u8>,
+  pub parent_id: Option<Vec<u8>>,
+  pub level_code: Vec<u8>,
+  pub child_code: Option<Vec<u8>>,
+  pub parent: Option<usize>,
+  pub children: Vec<usize>,
+  pub segments: Vec<Segment>
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum HierarchyErrorKind {
+  MissingId,
+  DuplicateId,
+  UnknownParent,
+  ChildIndicatorMismatch
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct HierarchyError {
+  pub kind: HierarchyErrorKind,
+  pub id: Vec<u8>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl fmt::Display for HierarchyError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let what = match self.kind {
+      HierarchyErrorKind::MissingId => "has no id",
+      HierarchyErrorKind::DuplicateId => "repeats an earlier id",
+      HierarchyErrorKind::UnknownParent => "names a parent that has not been seen",
+      HierarchyErrorKind::ChildIndicatorMismatch => "has a child code that does not match its children"
+    };
+    write!(f, "HL {} {} at line {}, column {}", String::from_utf8_lossy(&self.id), what, self.line, self.column)
+  }
+}
+
+impl std::error::Error for HierarchyError {}
+
+pub struct HierarchyTree {
+  pub header: Vec<Segment>,
+  pub levels: Vec<HierarchicalLevel>,
+  pub roots: Vec<usize>,
+  pub trailer: Option<Segment>,
+  pub errors: Vec<HierarchyError>
+}
+
+impl HierarchyTree {
+  pub fn find(&self, id: &[u8]) -> Option<&HierarchicalLevel> {
+    self.levels.iter().find(|l| l.id.as_slice() == id)
+  }
+
+  pub fn parent(&self, level: &HierarchicalLevel) -> Option<&HierarchicalLevel> {
+    level.parent.map(|p| &self.levels[p])
+  }
+
+  pub fn children<'a>(&'a self, level: &'a HierarchicalLevel) -> impl Iterator<Item = &'a HierarchicalLevel> {
+    level.children.iter().map(move |c| &self.levels[*c])
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.errors.is_empty()
+  }
+}
+
+pub struct HierarchyBuilder {
+  header: Vec<Segment>,
+  levels: Vec<HierarchicalLevel>,
+  roots: Vec<usize>,
+  trailer: Option<Segment>,
+  errors: Vec<HierarchyError>,
+  ids: HashMap<Vec<u8>, usize>,
+  hl_segments: Vec<(u64, u64, u64, u64)>
+}
+
+#[allow(clippy::new_without_default)]
+impl HierarchyBuilder {
+  pub fn new() -> Self {
+    HierarchyBuilder {
+      header: Vec::new(),
+      levels: Vec::new(),
+      roots: Vec::new(),
+      trailer: None,
+      errors: Vec::new(),
+      ids: HashMap::new(),
+      hl_segments: Vec::new()
+    }
+  }
+
+  pub fn segment(&mut self, segment: &Segment) {
+    let tag_compare = segment.tag.as_slice();
+    if HL_TAG.eq(tag_compare) {
+      self.start_level(segment);
+    } else if SE_TAG.eq(tag_compare) {
+      self.trailer = Some(segment.clone());
+    } else {
+      match self.levels.last_mut() {
+        None => self.header.push(segment.clone()),
+        Some(l) => l.segments.push(segment.clone())
+      }
+    }
+  }
+
+  // The levels so far, in the order of their HL segments.
+  pub fn levels(&self) -> &[HierarchicalLevel] {
+    &self.levels
+  }
+
+  pub fn finish(mut self) -> HierarchyTree {
+    for (idx, level) in self.levels.iter().enumerate() {
+      let mismatch = match level.child_code.as_deref() {
+        Some(b"1") => level.children.is_empty(),
+        Some(b"0") => !level.children.is_empty(),
+        _ => false
+      };
+      if mismatch {
+        let (segment_index, start_offset, line, column) = self.hl_segments[idx];
+        self.errors.push(HierarchyError {
+          kind: HierarchyErrorKind::ChildIndicatorMismatch,
+          id: level.id.clone(),
+          segment_index,
+          start_offset,
+          line,
+          column
+        });
+      }
+    }
+    HierarchyTree {
+      header: self.header,
+      levels: self.levels,
+      roots: self.roots,
+      trailer: self.trailer,
+      errors: self.errors
+    }
+  }
+
+  fn start_level(&mut self, segment: &Segment) {
+    let idx = self.levels.len();
+    let id = non_empty_element(segment, 1).unwrap_or_default();
+    let parent_id = non_empty_element(segment, 2);
+    let level_code = non_empty_element(segment, 3).unwrap_or_default();
+    let child_code = non_empty_element(segment, 4);
+    if id.is_empty() {
+      self.push_error(HierarchyErrorKind::MissingId, &id, segment);
+    } else if self.ids.contains_key(&id) {
+      self.push_error(HierarchyErrorKind::DuplicateId, &id, segment);
+    } else {
+      self.ids.insert(id.clone(), idx);
+    }
+    let parent = match &parent_id {
+      None => None,
+      Some(p) => match self.ids.get(p) {
+        Some(pidx) if *pidx != idx => Some(*pidx),
+        _ => {
+          self.push_error(HierarchyErrorKind::UnknownParent, &id, segment);
+          None
+        }
+      }
+    };
+    match parent {
+      None => self.roots.push(idx),
+      Some(p) => self.levels[p].children.push(idx)
+    }
+    self.hl_segments.push((segment.segment_index, segment.start_offset, segment.line, segment.column));
+    self.levels.push(HierarchicalLevel {
+      id,
+      parent_id,
+      level_code,
+      child_code,
+      parent,
+      children: Vec::new(),
+      segments: vec![segment.clone()]
+    });
+  }
+
+  fn push_error(&mut self, kind: HierarchyErrorKind, id: &[u8], segment: &Segment) {
+    self.errors.push(HierarchyError {
+      kind,
+      id: Vec::from(id),
+      segment_index: segment.segment_index,
+      start_offset: segment.start_offset,
+      line: segment.line,
+      column: segment.column
+    });
+  }
+}
+
+fn non_empty_element(segment: &Segment, index: usize) -> Option<Vec<u8>> {
+  match segment.fields.get(index) {
+    Some(f) if !f.is_empty() => Some(f.clone()),
+    _ => None
+  }
+}
+
+pub trait HierarchyHandler {
+  fn transaction(&mut self, tree: HierarchyTree);
+}
+
+pub struct HierarchyParser<T: HierarchyHandler> {
+  state: EnvelopeState,
+  builder: Option<HierarchyBuilder>,
+  handler: T
+}
+
+impl<T: HierarchyHandler> HierarchyParser<T> {
+  pub fn new(handler: T) -> Self {
+    HierarchyParser {
+      state: EnvelopeState::Nothing,
+      builder: None,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+}
+
+impl<T: HierarchyHandler> StreamParser for HierarchyParser<T> {
+  fn segment(&mut self, segment: &Segment) {
+    if let Some(b) = &mut self.builder {
+      b.segment(segment);
+    }
+  }
+
+  fn interchange_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+  }
+
+  fn functional_group_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn transaction_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    self.builder = Some(HierarchyBuilder::new());
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    if let Some(b) = self.builder.take() {
+      self.handler.transaction(b.finish());
+    }
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, _error: Error) {
+
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{HierarchyHandler, HierarchyParser, HierarchyTree, HierarchyErrorKind};
+  use crate::edi_parsers::create_edi_streamer;
+  use crate::edi_parsers::execute_streaming_parser;
+  use std::io::Cursor;
+
+  struct Collector {
+    trees: Vec<HierarchyTree>
+  }
+
+  impl HierarchyHandler for Collector {
+    fn transaction(&mut self, tree: HierarchyTree) {
+      self.trees.push(tree);
+    }
+  }
+
+  fn parse(raw: &str) -> Vec<HierarchyTree> {
+    let mut hp = HierarchyParser::new(Collector { trees: Vec::new() });
+    let mut ioish = Cursor::new(raw.as_bytes());
+    match &mut create_edi_streamer(&mut ioish) {
+      Ok(pi) => execute_streaming_parser(pi, &mut hp),
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    }
+    hp.into_handler().trees
+  }
+
+  #[test]
+  fn builds_nested_levels() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001~
+BHT*0019*00*1*20230101*1200*CH~
+HL*1**20*1~
+NM1*85*2*BILLING~
+HL*2*1*22*1~
+NM1*IL*1*DOE*JOHN~
+HL*3*2*23*0~
+NM1*QC*1*DOE*JANE~
+CLM*1*100~
+SE*11*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let trees = parse(raw);
+    assert_eq!(trees.len(), 1);
+    let tree = &trees[0];
+    assert!(tree.is_valid());
+    assert_eq!(tree.header.len(), 2);
+    assert_eq!(tree.roots, vec![0]);
+    assert_eq!(tree.levels[0].children, vec![1]);
+    assert_eq!(tree.levels[2].parent, Some(1));
+    assert_eq!(tree.levels[2].level_code, b"23".to_vec());
+    assert_eq!(tree.levels[2].segments.len(), 3);
+    let child_ids : Vec<Vec<u8>> = tree.children(&tree.levels[1]).map(|l| l.id.clone()).collect();
+    assert_eq!(child_ids, vec![b"3".to_vec()]);
+    assert!(tree.trailer.is_some());
+  }
+
+  #[test]
+  fn reports_bad_references() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001~
+HL*1**20*1~
+HL*2*9*22*0~
+HL*2*1*22*1~
+SE*5*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let trees = parse(raw);
+    let kinds : Vec<HierarchyErrorKind> = trees[0].errors.iter().map(|e| e.kind).collect();
+    assert_eq!(
+      kinds,
+      vec![
+        HierarchyErrorKind::UnknownParent,
+        HierarchyErrorKind::DuplicateId,
+        HierarchyErrorKind::ChildIndicatorMismatch
+      ]
+    );
+    assert_eq!(trees[0].roots, vec![0, 1]);
+    assert_eq!(trees[0].errors[0].to_string(), "HL 2 names a parent that has not been seen at line 5, column 1");
+  }
+}
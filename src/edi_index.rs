@@ -0,0 +1,501 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::{Segment, create_segment_iterator, rebase_position};
+use crate::edi_envelope::EnvelopeState;
+use std::collections::HashMap;
+use std::io::{Read, Write, Seek, SeekFrom, Error, ErrorKind};
+
+const INDEX_MAGIC : [u8; 4] = [b'E', b'D', b'I', b'X'];
+const INDEX_VERSION : u64 = 2;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct InterchangeEntry {
+  pub start_offset: u64,
+  pub end_offset: u64,
+  pub line: u64,
+  pub column: u64,
+  pub control_number: Vec<u8>
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct FunctionalGroupEntry {
+  pub interchange: u64,
+  pub start_offset: u64,
+  pub end_offset: u64,
+  pub line: u64,
+  pub column: u64,
+  pub control_number: Vec<u8>
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TransactionEntry {
+  pub functional_group: u64,
+  pub start_offset: u64,
+  pub end_offset: u64,
+  pub line: u64,
+  pub column: u64,
+  pub segment_index: u64,
+  pub transaction_set_code: Vec<u8>,
+  pub control_number: Vec<u8>,
+  pub keys: Vec<Vec<u8>>
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct KeyElement {
+  pub tag: Vec<u8>,
+  pub position: u64
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct EdiIndex {
+  pub element_delimiter: Vec<u8>,
+  pub segment_delimiter: Vec<u8>,
+  pub key_elements: Vec<KeyElement>,
+  pub interchanges: Vec<InterchangeEntry>,
+  pub functional_groups: Vec<FunctionalGroupEntry>,
+  pub transactions: Vec<TransactionEntry>,
+  // The first transaction with each control number, and with each value of
+  // each key element.
+  by_control_number: HashMap<Vec<u8>, usize>,
+  by_key: Vec<HashMap<Vec<u8>, usize>>
+}
+
+impl EdiIndex {
+  pub fn find_transaction(&self, control_number: &[u8]) -> Option<usize> {
+    self.by_control_number.get(control_number).copied()
+  }
+
+  pub fn find_transaction_by_key(&self, key: usize, value: &[u8]) -> Option<usize> {
+    self.by_key.get(key).and_then(|k| k.get(value)).copied()
+  }
+
+  // Adds a transaction to the lookups once its keys are known.
+  fn add_lookups(&mut self, position: usize) {
+    let t = &self.transactions[position];
+    self.by_control_number.entry(t.control_number.clone()).or_insert(position);
+    self.by_key.resize_with(t.keys.len(), HashMap::new);
+    for (k, value) in t.keys.iter().enumerate() {
+      self.by_key[k].entry(value.clone()).or_insert(position);
+    }
+  }
+
+  pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+    w.write_all(&INDEX_MAGIC)?;
+    write_u64(w, INDEX_VERSION)?;
+    write_bytes(w, &self.element_delimiter)?;
+    write_bytes(w, &self.segment_delimiter)?;
+    write_u64(w, self.key_elements.len() as u64)?;
+    for k in self.key_elements.iter() {
+      write_bytes(w, &k.tag)?;
+      write_u64(w, k.position)?;
+    }
+    write_u64(w, self.interchanges.len() as u64)?;
+    for i in self.interchanges.iter() {
+      write_u64(w, i.start_offset)?;
+      write_u64(w, i.end_offset)?;
+      write_u64(w, i.line)?;
+      write_u64(w, i.column)?;
+      write_bytes(w, &i.control_number)?;
+    }
+    write_u64(w, self.functional_groups.len() as u64)?;
+    for g in self.functional_groups.iter() {
+      write_u64(w, g.interchange)?;
+      write_u64(w, g.start_offset)?;
+      write_u64(w, g.end_offset)?;
+      write_u64(w, g.line)?;
+      write_u64(w, g.column)?;
+      write_bytes(w, &g.control_number)?;
+    }
+    write_u64(w, self.transactions.len() as u64)?;
+    for t in self.transactions.iter() {
+      write_u64(w, t.functional_group)?;
+      write_u64(w, t.start_offset)?;
+      write_u64(w, t.end_offset)?;
+      write_u64(w, t.line)?;
+      write_u64(w, t.column)?;
+      write_u64(w, t.segment_index)?;
+      write_bytes(w, &t.transaction_set_code)?;
+      write_bytes(w, &t.control_number)?;
+      for k in t.keys.iter() {
+        write_bytes(w, k)?;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn read_from<R: Read>(r: &mut R) -> Result<EdiIndex, Error> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if magic != INDEX_MAGIC || read_u64(r)? != INDEX_VERSION {
+      return Err(Error::new(ErrorKind::InvalidData, "not an EDI index file"));
+    }
+    let element_delimiter = read_bytes(r)?;
+    let segment_delimiter = read_bytes(r)?;
+    let mut key_elements = Vec::new();
+    for _ in 0..read_u64(r)? {
+      let tag = read_bytes(r)?;
+      let position = read_u64(r)?;
+      key_elements.push(KeyElement { tag, position });
+    }
+    let mut interchanges = Vec::new();
+    for _ in 0..read_u64(r)? {
+      interchanges.push(InterchangeEntry {
+        start_offset: read_u64(r)?,
+        end_offset: read_u64(r)?,
+        line: read_u64(r)?,
+        column: read_u64(r)?,
+        control_number: read_bytes(r)?
+      });
+    }
+    let mut functional_groups = Vec::new();
+    for _ in 0..read_u64(r)? {
+      functional_groups.push(FunctionalGroupEntry {
+        interchange: read_u64(r)?,
+        start_offset: read_u64(r)?,
+        end_offset: read_u64(r)?,
+        line: read_u64(r)?,
+        column: read_u64(r)?,
+        control_number: read_bytes(r)?
+      });
+    }
+    let mut transactions = Vec::new();
+    for _ in 0..read_u64(r)? {
+      let functional_group = read_u64(r)?;
+      let start_offset = read_u64(r)?;
+      let end_offset = read_u64(r)?;
+      let line = read_u64(r)?;
+      let column = read_u64(r)?;
+      let segment_index = read_u64(r)?;
+      let transaction_set_code = read_bytes(r)?;
+      let control_number = read_bytes(r)?;
+      let mut keys = Vec::new();
+      for _ in 0..key_elements.len() {
+        keys.push(read_bytes(r)?);
+      }
+      transactions.push(TransactionEntry {
+        functional_group,
+        start_offset,
+        end_offset,
+        line,
+        column,
+        segment_index,
+        transaction_set_code,
+        control_number,
+        keys
+      });
+    }
+    let mut index = EdiIndex {
+      element_delimiter,
+      segment_delimiter,
+      key_elements,
+      interchanges,
+      functional_groups,
+      transactions,
+      by_control_number: HashMap::new(),
+      by_key: Vec::new()
+    };
+    for position in 0..index.transactions.len() {
+      index.add_lookups(position);
+    }
+    Ok(index)
+  }
+}
+
+pub(crate) fn write_u64<W: Write>(w: &mut W, value: u64) -> Result<(), Error> {
+  w.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_bytes<W: Write>(w: &mut W, value: &[u8]) -> Result<(), Error> {
+  write_u64(w, value.len() as u64)?;
+  w.write_all(value)
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> Result<u64, Error> {
+  let mut buff = [0; 8];
+  r.read_exact(&mut buff)?;
+  Ok(u64::from_le_bytes(buff))
+}
+
+pub(crate) fn read_bytes<R: Read>(r: &mut R) -> Result<Vec<u8>, Error> {
+  let len = read_u64(r)?;
+  let mut buff = Vec::new();
+  r.take(len).read_to_end(&mut buff)?;
+  if buff.len() as u64 != len {
+    return Err(Error::from(ErrorKind::UnexpectedEof));
+  }
+  Ok(buff)
+}
+
+pub struct EdiIndexer {
+  state: EnvelopeState,
+  index: EdiIndex
+}
+
+#[allow(clippy::new_without_default)]
+impl EdiIndexer {
+  pub fn new() -> Self {
+    EdiIndexer {
+      state: EnvelopeState::Nothing,
+      index: EdiIndex {
+        element_delimiter: Vec::new(),
+        segment_delimiter: Vec::new(),
+        key_elements: Vec::new(),
+        interchanges: Vec::new(),
+        functional_groups: Vec::new(),
+        transactions: Vec::new(),
+        by_control_number: HashMap::new(),
+        by_key: Vec::new()
+      }
+    }
+  }
+
+  pub fn with_key_element(mut self, tag: &str, position: u64) -> Self {
+    self.index.key_elements.push(KeyElement { tag: Vec::from(tag.as_bytes()), position });
+    self
+  }
+
+  pub fn index(&self) -> &EdiIndex {
+    &self.index
+  }
+
+  pub fn into_index(self) -> EdiIndex {
+    self.index
+  }
+}
+
+fn element(segment: &Segment, position: usize) -> Vec<u8> {
+  segment.fields.get(position).cloned().unwrap_or_default()
+}
+
+// The terminator is whatever follows the last element in the raw bytes.
+fn segment_terminator(segment: &Segment) -> Vec<u8> {
+  let content : usize = segment.fields.iter().map(|f| f.len()).sum::<usize>() + segment.fields.len().saturating_sub(1);
+  segment.raw.get(content..).map(Vec::from).unwrap_or_default()
+}
+
+impl StreamParser for EdiIndexer {
+  fn segment(&mut self, segment: &Segment) {
+    if self.state.in_transaction() {
+      if let Some(t) = self.index.transactions.last_mut() {
+        t.end_offset = segment.end_offset;
+        for (k, key) in self.index.key_elements.iter().enumerate() {
+          if t.keys[k].is_empty() && key.tag == segment.tag {
+            t.keys[k] = element(segment, key.position as usize);
+          }
+        }
+      }
+    }
+    if self.state.in_functional_group() {
+      if let Some(g) = self.index.functional_groups.last_mut() {
+        g.end_offset = segment.end_offset;
+      }
+    }
+    if self.state.in_interchange() {
+      if let Some(i) = self.index.interchanges.last_mut() {
+        i.end_offset = segment.end_offset;
+      }
+    }
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    if self.index.element_delimiter.is_empty() {
+      self.index.element_delimiter = segment.raw.get(3..4).map(Vec::from).unwrap_or_default();
+      self.index.segment_delimiter = segment_terminator(segment);
+    }
+    self.index.interchanges.push(InterchangeEntry {
+      start_offset: segment.start_offset,
+      end_offset: segment.end_offset,
+      line: segment.line,
+      column: segment.column,
+      control_number: element(segment, 13)
+    });
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+  }
+
+  fn functional_group_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    self.index.functional_groups.push(FunctionalGroupEntry {
+      interchange: self.index.interchanges.len().saturating_sub(1) as u64,
+      start_offset: segment.start_offset,
+      end_offset: segment.end_offset,
+      line: segment.line,
+      column: segment.column,
+      control_number: element(segment, 6)
+    });
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn transaction_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    self.index.transactions.push(TransactionEntry {
+      functional_group: self.index.functional_groups.len().saturating_sub(1) as u64,
+      start_offset: segment.start_offset,
+      end_offset: segment.end_offset,
+      line: segment.line,
+      column: segment.column,
+      segment_index: segment.segment_index,
+      transaction_set_code: element(segment, 1),
+      control_number: element(segment, 2),
+      keys: vec![Vec::new(); self.index.key_elements.len()]
+    });
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    if let Some(position) = self.index.transactions.len().checked_sub(1) {
+      self.index.add_lookups(position);
+    }
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, _error: Error) {
+
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+pub struct IndexedReader<T: Read + Seek> {
+  source: T,
+  index: EdiIndex
+}
+
+impl<T: Read + Seek> IndexedReader<T> {
+  pub fn new(source: T, index: EdiIndex) -> Self {
+    IndexedReader { source, index }
+  }
+
+  pub fn index(&self) -> &EdiIndex {
+    &self.index
+  }
+
+  pub fn read_interchange(&mut self, position: usize) -> Result<Vec<Segment>, Error> {
+    let entry = self.index.interchanges.get(position).ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+    let (start, end, position) = (entry.start_offset, entry.end_offset, (entry.line, entry.column));
+    self.read_range(start, end, 0, position)
+  }
+
+  pub fn read_functional_group(&mut self, position: usize) -> Result<Vec<Segment>, Error> {
+    let entry = self.index.functional_groups.get(position).ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+    let (start, end, position) = (entry.start_offset, entry.end_offset, (entry.line, entry.column));
+    self.read_range(start, end, 0, position)
+  }
+
+  pub fn read_transaction(&mut self, position: usize) -> Result<Vec<Segment>, Error> {
+    let entry = self.index.transactions.get(position).ok_or_else(|| Error::from(ErrorKind::NotFound))?;
+    let (start, end, segment_index, position) = (entry.start_offset, entry.end_offset, entry.segment_index, (entry.line, entry.column));
+    self.read_range(start, end, segment_index, position)
+  }
+
+  pub fn read_transaction_by_control_number(&mut self, control_number: &[u8]) -> Result<Vec<Segment>, Error> {
+    match self.index.find_transaction(control_number) {
+      None => Err(Error::from(ErrorKind::NotFound)),
+      Some(position) => self.read_transaction(position)
+    }
+  }
+
+  // Offsets are inclusive.  Segment indexes before the start of an
+  // interchange or group are not recorded, so those ranges are numbered from 0.
+  fn read_range(&mut self, start: u64, end: u64, first_segment_index: u64, position: (u64, u64)) -> Result<Vec<Segment>, Error> {
+    self.source.seek(SeekFrom::Start(start))?;
+    let mut ranged = (&mut self.source).take(end + 1 - start);
+    let pi = create_segment_iterator(&mut ranged, self.index.element_delimiter.clone(), self.index.segment_delimiter.clone());
+    let mut segments = Vec::new();
+    for res in pi {
+      let mut segment = res?;
+      if segment.fields.is_empty() || (segment.fields.len() == 1 && segment.tag.is_empty()) {
+        continue;
+      }
+      segment.start_offset += start;
+      segment.end_offset += start;
+      segment.segment_index += first_segment_index;
+      rebase_position(&mut segment, position);
+      segments.push(segment);
+    }
+    Ok(segments)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{EdiIndexer, EdiIndex, IndexedReader};
+  use crate::edi_parsers::create_edi_streamer;
+  use crate::edi_parsers::execute_streaming_parser;
+  use std::io::Cursor;
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0041~
+BHT*0019*00*A1*20230101*1200*CH~
+SE*3*0041~
+ST*837*0042~
+BHT*0019*00*B2*20230101*1200*CH~
+CLM*X*100~
+SE*4*0042~
+GE*2*1~
+IEA*1*000000001~
+";
+
+  fn build_index() -> EdiIndex {
+    let mut indexer = EdiIndexer::new().with_key_element("BHT", 3);
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    match &mut create_edi_streamer(&mut ioish) {
+      Ok(pi) => execute_streaming_parser(pi, &mut indexer),
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    }
+    indexer.into_index()
+  }
+
+  #[test]
+  fn indexes_envelopes() {
+    let index = build_index();
+    assert_eq!(index.element_delimiter, b"*".to_vec());
+    assert_eq!(index.segment_delimiter, b"~\n".to_vec());
+    assert_eq!(index.interchanges.len(), 1);
+    assert_eq!(index.functional_groups.len(), 1);
+    assert_eq!(index.transactions.len(), 2);
+    assert_eq!(index.transactions[1].keys, vec![b"B2".to_vec()]);
+    assert_eq!(index.find_transaction_by_key(0, b"A1"), Some(0));
+    assert_eq!(index.find_transaction_by_key(0, b"B2"), Some(1));
+    assert_eq!(index.find_transaction_by_key(1, b"A1"), None);
+    assert_eq!(index.find_transaction(b"0042"), Some(1));
+    assert_eq!(index.find_transaction(b"9999"), None);
+  }
+
+  #[test]
+  fn round_trips_and_seeks() {
+    let index = build_index();
+    let mut written = Vec::new();
+    index.write_to(&mut written).unwrap();
+    let loaded = EdiIndex::read_from(&mut Cursor::new(written)).unwrap();
+    assert!(loaded == index);
+    let mut reader = IndexedReader::new(Cursor::new(RAW.as_bytes()), loaded);
+    let segments = reader.read_transaction_by_control_number(b"0042").unwrap();
+    let tags : Vec<Vec<u8>> = segments.iter().map(|s| s.tag.clone()).collect();
+    assert_eq!(tags, vec![b"ST".to_vec(), b"BHT".to_vec(), b"CLM".to_vec(), b"SE".to_vec()]);
+    assert_eq!(segments[0].segment_index, 5);
+    assert_eq!(segments[0].start_offset, RAW.find("ST*837*0042").unwrap() as u64);
+    assert_eq!((segments[1].line, segments[1].column), (7, 1));
+    assert_eq!(reader.read_interchange(0).unwrap().len(), 11);
+  }
+}
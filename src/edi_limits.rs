@@ -0,0 +1,178 @@
+use crate::edi_segments::{Segment, ParserState, PState, ParserOutput};
+use crate::edi_constants::{ST_TAG, SE_TAG, GS_TAG};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+// Bounds on what the tokenizer will hold or accept from untrusted input.
+// `None` means unlimited.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct ParserLimits {
+  pub max_segment_bytes: Option<u64>,
+  pub max_elements_per_segment: Option<u64>,
+  pub max_segments_per_transaction: Option<u64>,
+  pub max_transactions_per_group: Option<u64>,
+  pub max_total_bytes: Option<u64>
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LimitKind {
+  SegmentBytes,
+  ElementsPerSegment,
+  SegmentsPerTransaction,
+  TransactionsPerGroup,
+  TotalBytes
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct LimitExceeded {
+  pub kind: LimitKind,
+  pub limit: u64,
+  pub byte_index: u64,
+  pub segment_index: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl LimitExceeded {
+  pub fn from_error(error: &Error) -> Option<&LimitExceeded> {
+    error.get_ref().and_then(|e| e.downcast_ref::<LimitExceeded>())
+  }
+}
+
+impl fmt::Display for LimitExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let what = match self.kind {
+      LimitKind::SegmentBytes => "bytes in a segment",
+      LimitKind::ElementsPerSegment => "elements in a segment",
+      LimitKind::SegmentsPerTransaction => "segments in a transaction",
+      LimitKind::TransactionsPerGroup => "transactions in a functional group",
+      LimitKind::TotalBytes => "bytes in the input"
+    };
+    write!(f, "more than {} {} at line {}, column {}", self.limit, what, self.line, self.column)
+  }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+// How far the segments read so far count towards the transaction and group
+// limits, so that a resumed tokenizer carries on counting.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct LimitCounts {
+  pub in_transaction: bool,
+  pub transaction_segments: u64,
+  pub group_transactions: u64
+}
+
+pub(crate) struct LimitState {
+  pub(crate) limits: ParserLimits,
+  pub(crate) counts: LimitCounts
+}
+
+impl LimitState {
+  pub(crate) fn new(limits: ParserLimits) -> Self {
+    LimitState {
+      limits,
+      counts: LimitCounts::default()
+    }
+  }
+
+  // Checks the tokenizer after a step, and the segment it completed if any.
+  pub(crate) fn check(&mut self, ps: &ParserState, output: ParserOutput) -> Result<ParserOutput, Error> {
+    let buffered = (ps.current_string.len() + ps.pending.len() + ps.stray.len()) as u64;
+    let at = (ps.segment_index, ps);
+    exceeds(LimitKind::TotalBytes, self.limits.max_total_bytes, ps.byte_index, at)?;
+    exceeds(LimitKind::SegmentBytes, self.limits.max_segment_bytes, buffered, at)?;
+    if let PState::InField = ps.state {
+      exceeds(LimitKind::ElementsPerSegment, self.limits.max_elements_per_segment, ps.current_segment.len() as u64, at)?;
+    }
+    if let Some(segment) = &output {
+      self.count(segment, ps)?;
+    }
+    Ok(output)
+  }
+
+  fn count(&mut self, segment: &Segment, ps: &ParserState) -> Result<(), Error> {
+    let tag = segment.tag.as_slice();
+    let at = (segment.segment_index, ps);
+    let counts = &mut self.counts;
+    if GS_TAG.eq(tag) {
+      counts.group_transactions = 0;
+    } else if ST_TAG.eq(tag) {
+      counts.in_transaction = true;
+      counts.transaction_segments = 0;
+      counts.group_transactions += 1;
+      exceeds(LimitKind::TransactionsPerGroup, self.limits.max_transactions_per_group, counts.group_transactions, at)?;
+    }
+    if counts.in_transaction {
+      counts.transaction_segments += 1;
+      exceeds(LimitKind::SegmentsPerTransaction, self.limits.max_segments_per_transaction, counts.transaction_segments, at)?;
+    }
+    if SE_TAG.eq(tag) {
+      counts.in_transaction = false;
+    }
+    Ok(())
+  }
+}
+
+// `at` is the segment the limit applies to and the tokenizer's position.
+fn exceeds(kind: LimitKind, limit: Option<u64>, value: u64, at: (u64, &ParserState)) -> Result<(), Error> {
+  let (segment_index, ps) = at;
+  match limit {
+    Some(limit) if value > limit => Err(Error::new(ErrorKind::InvalidData, LimitExceeded {
+      kind,
+      limit,
+      byte_index: ps.byte_index,
+      segment_index,
+      line: ps.line,
+      column: ps.column
+    })),
+    _ => Ok(())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ParserLimits, LimitExceeded, LimitKind};
+  use crate::edi_segments::create_segment_iterator;
+  use std::io::Cursor;
+
+  fn first_error(raw: &str, limits: ParserLimits) -> (usize, LimitExceeded) {
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let pi = create_segment_iterator(&mut ioish, b"*".to_vec(), b"~".to_vec()).with_limits(limits);
+    let results : Vec<_> = pi.collect();
+    let good = results.iter().filter(|r| r.is_ok()).count();
+    match results.last() {
+      Some(Err(e)) => (good, LimitExceeded::from_error(e).unwrap().clone()),
+      _ => panic!("expected a limit error")
+    }
+  }
+
+  #[test]
+  fn unterminated_segment() {
+    let raw = format!("ST*1~N1*{}", "X".repeat(10000));
+    let limits = ParserLimits { max_segment_bytes: Some(64), ..ParserLimits::default() };
+    let (good, e) = first_error(&raw, limits);
+    assert_eq!(good, 1);
+    assert_eq!(e.kind, LimitKind::SegmentBytes);
+    assert_eq!(e.byte_index, 70);
+  }
+
+  #[test]
+  fn elements_and_counts() {
+    let limits = ParserLimits { max_elements_per_segment: Some(2), ..ParserLimits::default() };
+    assert_eq!(first_error("ST*1*2~N1*A*B*C~", limits).1.kind, LimitKind::ElementsPerSegment);
+    let limits = ParserLimits { max_segments_per_transaction: Some(3), ..ParserLimits::default() };
+    assert_eq!(first_error("GS*1~ST*1~N1~N2~SE*4~", limits).1.kind, LimitKind::SegmentsPerTransaction);
+    let limits = ParserLimits { max_transactions_per_group: Some(1), ..ParserLimits::default() };
+    assert_eq!(first_error("GS*1~ST*1~SE*2~ST*2~SE*2~", limits), (3, LimitExceeded {
+      kind: LimitKind::TransactionsPerGroup,
+      limit: 1,
+      byte_index: 23,
+      segment_index: 3,
+      line: 1,
+      column: 24
+    }));
+    let limits = ParserLimits { max_total_bytes: Some(8), ..ParserLimits::default() };
+    assert_eq!(first_error("ST*1~SE*2~", limits).1.kind, LimitKind::TotalBytes);
+  }
+}
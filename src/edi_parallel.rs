@@ -0,0 +1,484 @@
+use crate::edi_segments::{Segment, create_segment_iterator, advance_position, rebase_position};
+use crate::edi_delimiters::{Delimiters, DelimiterResult, detect_delimiters};
+use crate::edi_tag_rules::SegmentTagRule;
+use crate::edi_constants::{ST_TAG, SE_TAG, GS_TAG, GE_TAG, ISA_TAG, IEA_TAG};
+use std::collections::BTreeMap;
+use std::io::{BufReader, Cursor, Read, Seek, Error};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::thread;
+
+pub struct ParallelOptions {
+  pub workers: usize,
+  pub max_in_flight: usize,
+  pub tag_rule: SegmentTagRule
+}
+
+impl Default for ParallelOptions {
+  fn default() -> Self {
+    let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    ParallelOptions {
+      workers,
+      max_in_flight: workers * 4,
+      tag_rule: SegmentTagRule::default()
+    }
+  }
+}
+
+pub struct TransactionSet {
+  pub sequence: u64,
+  pub interchange: u64,
+  pub functional_group: u64,
+  pub start_offset: u64,
+  pub segments: Vec<Segment>
+}
+
+struct TransactionChunk {
+  sequence: u64,
+  interchange: u64,
+  functional_group: u64,
+  start_offset: u64,
+  segment_index: u64,
+  position: (u64, u64),
+  bytes: Vec<u8>
+}
+
+type ChunkResult<R> = (u64, Result<R, Error>);
+
+// Splits the input into transaction sets by looking only at segment
+// terminators and tags.  Elements are not split out here - that is left to
+// the workers.  As in the tokenizer, the bytes after a terminator are held
+// as a candidate until the next delimiter, and the tag rule decides where
+// the next segment starts in them.
+struct BoundaryScanner {
+  element_delimiter: u8,
+  segment_delimiter: Vec<u8>,
+  tag_rule: SegmentTagRule,
+  // How much of the terminator has been seen; all of it once the candidate
+  // is being collected.
+  terminator_matched: usize,
+  candidate: Vec<u8>,
+  candidate_position: (u64, u64),
+  byte_index: u64,
+  segment_start: u64,
+  segment_index: u64,
+  position: (u64, u64),
+  segment_position: (u64, u64),
+  segment: Vec<u8>,
+  tag_length: Option<usize>,
+  interchange: u64,
+  functional_group: u64,
+  sequence: u64,
+  transaction: Option<TransactionChunk>
+}
+
+impl BoundaryScanner {
+  fn new(element_delimiter: u8, segment_delimiter: Vec<u8>, tag_rule: SegmentTagRule) -> Self {
+    BoundaryScanner {
+      element_delimiter,
+      segment_delimiter,
+      tag_rule,
+      terminator_matched: 0,
+      candidate: Vec::new(),
+      candidate_position: (1, 1),
+      byte_index: 0,
+      segment_start: 0,
+      segment_index: 0,
+      position: (1, 1),
+      segment_position: (1, 1),
+      segment: Vec::new(),
+      tag_length: None,
+      interchange: 0,
+      functional_group: 0,
+      sequence: 0,
+      transaction: None
+    }
+  }
+
+  fn push(&mut self, byte: u8, completed: &mut Vec<TransactionChunk>) {
+    let position = self.position;
+    self.byte_index += 1;
+    self.position = advance_position(position, &[byte]);
+    let terminator_length = self.segment_delimiter.len();
+    if self.terminator_matched > 0 && self.terminator_matched < terminator_length {
+      if byte == self.segment_delimiter[self.terminator_matched] {
+        self.terminator_matched += 1;
+        self.segment.push(byte);
+        return;
+      }
+      // A short terminator; whatever follows is a candidate for the next tag.
+      self.terminator_matched = terminator_length;
+    }
+    if self.terminator_matched == terminator_length {
+      if byte != self.element_delimiter && byte != self.segment_delimiter[0] {
+        if self.candidate.is_empty() {
+          self.candidate_position = position;
+        }
+        self.candidate.push(byte);
+        return;
+      }
+      match self.tag_rule.split(&self.candidate) {
+        None => {
+          self.segment.append(&mut self.candidate);
+          self.segment.push(byte);
+          return;
+        },
+        Some(k) => self.begin_segment(k, completed)
+      }
+    }
+    if byte == self.element_delimiter || byte == self.segment_delimiter[0] {
+      if self.tag_length.is_none() {
+        self.tag_length = Some(self.segment.len());
+      }
+      if byte == self.segment_delimiter[0] {
+        self.terminator_matched = 1;
+      }
+    }
+    self.segment.push(byte);
+  }
+
+  // Ends the current segment with the first `k` candidate bytes and starts
+  // the next one with the rest.
+  fn begin_segment(&mut self, k: usize, completed: &mut Vec<TransactionChunk>) {
+    let mut candidate = std::mem::take(&mut self.candidate);
+    self.segment.extend_from_slice(&candidate[..k]);
+    self.finish_segment(completed);
+    self.segment_start = self.byte_index - 1 - (candidate.len() - k) as u64;
+    self.segment_position = advance_position(self.candidate_position, &candidate[..k]);
+    self.segment_index += 1;
+    self.segment = candidate.split_off(k);
+    self.terminator_matched = 0;
+  }
+
+  fn finish(&mut self, completed: &mut Vec<TransactionChunk>) {
+    // An unterminated tag at the very end is a segment of its own.
+    match self.tag_rule.split(&self.candidate) {
+      Some(k) => {
+        self.begin_segment(k, completed);
+        self.segment_start += 1;
+      },
+      None => self.segment.append(&mut self.candidate)
+    }
+    self.finish_segment(completed);
+    if let Some(t) = self.transaction.take() {
+      completed.push(t);
+    }
+  }
+
+  fn finish_segment(&mut self, completed: &mut Vec<TransactionChunk>) {
+    let tag_length = self.tag_length.unwrap_or(self.segment.len());
+    let tag = &self.segment[0..tag_length];
+    let is_trailer = SE_TAG.eq(tag);
+    let is_envelope = ST_TAG.eq(tag) || GS_TAG.eq(tag) || GE_TAG.eq(tag) || ISA_TAG.eq(tag) || IEA_TAG.eq(tag);
+    if is_envelope {
+      if let Some(t) = self.transaction.take() {
+        completed.push(t);
+      }
+    }
+    if ISA_TAG.eq(tag) {
+      self.interchange += 1;
+    } else if GS_TAG.eq(tag) {
+      self.functional_group += 1;
+    } else if ST_TAG.eq(tag) {
+      self.transaction = Some(TransactionChunk {
+        sequence: self.sequence,
+        interchange: self.interchange.saturating_sub(1),
+        functional_group: self.functional_group.saturating_sub(1),
+        start_offset: self.segment_start,
+        segment_index: self.segment_index,
+        position: self.segment_position,
+        bytes: Vec::new()
+      });
+      self.sequence += 1;
+    }
+    if let Some(t) = &mut self.transaction {
+      t.bytes.append(&mut self.segment);
+    }
+    if is_trailer {
+      if let Some(t) = self.transaction.take() {
+        completed.push(t);
+      }
+    }
+    self.segment.clear();
+    self.tag_length = None;
+  }
+}
+
+fn scan<T: Read>(ioish: &mut T, scanner: &mut BoundaryScanner, tokens: &Receiver<()>, work: &SyncSender<TransactionChunk>) -> Result<u64, Error> {
+  let mut completed = Vec::new();
+  let mut buff = [0; 8192];
+  loop {
+    let size = ioish.read(&mut buff)?;
+    if size == 0 {
+      scanner.finish(&mut completed);
+    } else {
+      for b in buff[0..size].iter() {
+        scanner.push(*b, &mut completed);
+      }
+    }
+    for chunk in completed.drain(..) {
+      if tokens.recv().is_err() || work.send(chunk).is_err() {
+        return Ok(scanner.sequence);
+      }
+    }
+    if size == 0 {
+      return Ok(scanner.sequence);
+    }
+  }
+}
+
+fn tokenize(chunk: TransactionChunk, delimiters: &Delimiters, tag_rule: SegmentTagRule) -> Result<TransactionSet, Error> {
+  let mut ioish = Cursor::new(chunk.bytes);
+  let pi = create_segment_iterator(&mut ioish, delimiters.element_delimiter.clone(), delimiters.segment_delimiter.clone()).with_tag_rule(tag_rule);
+  let mut segments = Vec::new();
+  for res in pi {
+    let mut segment = res?;
+    segment.start_offset += chunk.start_offset;
+    segment.end_offset += chunk.start_offset;
+    segment.segment_index += chunk.segment_index;
+    rebase_position(&mut segment, chunk.position);
+    segments.push(segment);
+  }
+  Ok(TransactionSet {
+    sequence: chunk.sequence,
+    interchange: chunk.interchange,
+    functional_group: chunk.functional_group,
+    start_offset: chunk.start_offset,
+    segments
+  })
+}
+
+fn work_loop<R, F: Fn(TransactionSet) -> R>(work: &Mutex<Receiver<TransactionChunk>>, results: Sender<ChunkResult<R>>, process: &F, panicked: &AtomicUsize, delimiters: &Delimiters, tag_rule: SegmentTagRule) {
+  loop {
+    let next = match work.lock() {
+      Ok(rx) => rx.recv(),
+      Err(_) => return
+    };
+    let chunk = match next {
+      Ok(c) => c,
+      Err(_) => return
+    };
+    let sequence = chunk.sequence;
+    // A panic takes the place of the result, so the ones after it can still
+    // be handed on.
+    let res = catch_unwind(AssertUnwindSafe(|| tokenize(chunk, delimiters, tag_rule).map(process)))
+      .unwrap_or_else(|_| {
+        panicked.fetch_add(1, Ordering::SeqCst);
+        Err(Error::other(format!("processing transaction set {} panicked", sequence)))
+      });
+    if results.send((sequence, res)).is_err() {
+      return;
+    }
+  }
+}
+
+// Scans envelope boundaries on one thread, tokenizes and processes complete
+// transaction sets on `options.workers` threads, and hands the results to
+// `handle` in file order.  At most `options.max_in_flight` transaction sets
+// are buffered at any time.  If `process` panics, `handle` gets an error in
+// place of that transaction set and the call fails once the rest are
+// handled.
+pub fn execute_parallel_parser<T, F, R, H>(ioish: &mut T, options: &ParallelOptions, process: F, mut handle: H) -> Result<(), Error>
+  where T: Read + Seek + Send,
+        F: Fn(TransactionSet) -> R + Sync,
+        R: Send,
+        H: FnMut(Result<R, Error>) {
+  let tag_rule = options.tag_rule;
+  let delimiters = match detect_delimiters(ioish, &tag_rule) {
+    DelimiterResult::DelimiterReadError(e) => return Err(e),
+    DelimiterResult::DelimitersFound(d) => d
+  };
+  let max_in_flight = options.max_in_flight.max(1);
+  let (token_tx, token_rx) = sync_channel::<()>(max_in_flight);
+  for _ in 0..max_in_flight {
+    let _ = token_tx.send(());
+  }
+  let (work_tx, work_rx) = sync_channel::<TransactionChunk>(max_in_flight);
+  let (result_tx, result_rx) = channel::<ChunkResult<R>>();
+  let work_rx = Mutex::new(work_rx);
+  let panicked = AtomicUsize::new(0);
+  let process = &process;
+  let delimiters = &delimiters;
+  let panicked = &panicked;
+  thread::scope(|s| {
+    // Owned here so that a panic in `handle` drops them, which stops the
+    // scanner and the workers instead of leaving them blocked.
+    let (token_tx, result_rx) = (token_tx, result_rx);
+    for _ in 0..options.workers.max(1) {
+      let results = result_tx.clone();
+      let work = &work_rx;
+      s.spawn(move || work_loop(work, results, process, panicked, delimiters, tag_rule));
+    }
+    let scan_results = result_tx;
+    let scanning = s.spawn(move || {
+      let mut reader = BufReader::new(ioish);
+      let mut scanner = BoundaryScanner::new(delimiters.element_delimiter[0], delimiters.segment_delimiter.clone(), tag_rule);
+      match scan(&mut reader, &mut scanner, &token_rx, &work_tx) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+          // The transaction being scanned was never sent, so the error takes
+          // its place in the order.
+          let sequence = scanner.transaction.as_ref().map(|t| t.sequence).unwrap_or(scanner.sequence);
+          let _ = scan_results.send((sequence, Err(Error::new(e.kind(), e.to_string()))));
+          Err(e)
+        }
+      }
+    });
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (sequence, res) in result_rx.iter() {
+      pending.insert(sequence, res);
+      while let Some(res) = pending.remove(&next) {
+        handle(res);
+        next += 1;
+        let _ = token_tx.try_send(());
+      }
+    }
+    match scanning.join() {
+      Ok(Ok(())) => match panicked.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        n => Err(Error::other(format!("processing panicked for {} transaction sets", n)))
+      },
+      Ok(Err(e)) => Err(e),
+      Err(_) => Err(Error::other("the scanning thread panicked"))
+    }
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::{execute_parallel_parser, ParallelOptions};
+  use crate::edi_tag_rules::SegmentTagRule;
+  use std::io::{Cursor, Read, Seek, SeekFrom, Error, ErrorKind};
+
+  struct FailingReader {
+    inner: Cursor<Vec<u8>>,
+    fail_at: u64
+  }
+
+  impl Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+      let position = self.inner.position();
+      if position >= self.fail_at {
+        return Err(Error::new(ErrorKind::ConnectionReset, "disk went away"));
+      }
+      let size = buf.len().min((self.fail_at - position) as usize);
+      self.inner.read(&mut buf[..size])
+    }
+  }
+
+  impl Seek for FailingReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Error> {
+      self.inner.seek(pos)
+    }
+  }
+
+  fn transactions(count: usize) -> String {
+    let mut raw = String::from("ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~\nGS*BE*S*R*20230101*1200*1*X*005010X220A1~\n");
+    for i in 0..count {
+      raw.push_str(&format!("ST*834*{:04}~\nINS*Y*18~\nREF*0F*{}~\nSE*4*{:04}~\n", i, i, i));
+    }
+    raw.push_str(&format!("GE*{}*1~\nIEA*1*000000001~\n", count));
+    raw
+  }
+
+  #[test]
+  fn processes_transactions_in_order() {
+    let raw = transactions(50);
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let options = ParallelOptions { workers: 4, max_in_flight: 3, tag_rule: SegmentTagRule::default() };
+    let mut seen = Vec::new();
+    let res = execute_parallel_parser(
+      &mut ioish,
+      &options,
+      |t| {
+        let reference = String::from_utf8_lossy(&t.segments[2].fields[2]).to_string();
+        (t.sequence, t.segments.len(), reference, t.segments[0].start_offset, t.segments[0].segment_index, t.segments[2].line)
+      },
+      |r| seen.push(r.unwrap())
+    );
+    assert!(res.is_ok());
+    assert_eq!(seen.len(), 50);
+    for (i, (sequence, count, reference, _, _, _)) in seen.iter().enumerate() {
+      assert_eq!(*sequence, i as u64);
+      assert_eq!(*count, 4);
+      assert_eq!(*reference, i.to_string());
+    }
+    let (_, _, _, start, index, line) = &seen[1];
+    assert_eq!(*start, raw.find("ST*834*0001").unwrap() as u64);
+    assert_eq!(*index, 6);
+    assert_eq!(*line, 9);
+  }
+
+  #[test]
+  fn reports_read_errors_mid_transaction() {
+    let raw = "ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~\nGS*BE*S*R*20230101*1200*1*X*005010X220A1~\nST*834*0001~\nINS*Y*18~\nSE*3*0001~\nST*834*0002~\nINS*Y*18~\nREF*0F*1~\nSE*4*0002~\nGE*2*1~\nIEA*1*000000001~\n";
+    let fail_at = raw.find("REF*0F").unwrap() as u64;
+    let mut ioish = FailingReader { inner: Cursor::new(raw.as_bytes().to_vec()), fail_at };
+    let options = ParallelOptions { workers: 2, max_in_flight: 2, tag_rule: SegmentTagRule::default() };
+    let mut seen = Vec::new();
+    let res = execute_parallel_parser(&mut ioish, &options, |t| t.sequence, |r| seen.push(r.map_err(|e| e.kind())));
+    assert_eq!(res.map_err(|e| e.kind()), Err(ErrorKind::ConnectionReset));
+    assert_eq!(seen, vec![Ok(0), Err(ErrorKind::ConnectionReset)]);
+  }
+
+  #[test]
+  fn fails_when_processing_panics() {
+    let mut ioish = Cursor::new(transactions(8).into_bytes());
+    let options = ParallelOptions { workers: 2, max_in_flight: 4, tag_rule: SegmentTagRule::default() };
+    let mut seen = Vec::new();
+    let res = execute_parallel_parser(
+      &mut ioish,
+      &options,
+      |t| {
+        if t.sequence == 3 {
+          panic!("process failed");
+        }
+        t.sequence
+      },
+      |r| seen.push(r.is_ok())
+    );
+    assert!(res.is_err());
+    assert_eq!(seen, vec![true, true, true, false, true, true, true, true]);
+  }
+
+  #[test]
+  fn stops_when_handling_panics() {
+    let res = std::panic::catch_unwind(|| {
+      let mut ioish = Cursor::new(transactions(8).into_bytes());
+      let options = ParallelOptions { workers: 2, max_in_flight: 4, tag_rule: SegmentTagRule::default() };
+      let _ = execute_parallel_parser(&mut ioish, &options, |t| t.sequence, |r| {
+        if r.ok() == Some(3) {
+          panic!("handle failed");
+        }
+      });
+    });
+    assert!(res.is_err());
+  }
+
+  #[test]
+  fn splits_with_the_tag_rule() {
+    let raw = transactions(3).replace("INS*Y*18~\n", "INS*Y*18~\n1A*X~\n");
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let options = ParallelOptions { workers: 2, max_in_flight: 2, tag_rule: SegmentTagRule::Custom(|c| c.iter().position(|b| b.is_ascii_alphanumeric())) };
+    let mut seen = Vec::new();
+    let res = execute_parallel_parser(&mut ioish, &options, |t| t.segments.iter().map(|s| s.tag.clone()).collect::<Vec<_>>(), |r| seen.push(r.unwrap()));
+    assert!(res.is_ok());
+    assert_eq!(seen.len(), 3);
+    assert_eq!(seen[0], vec![b"ST".to_vec(), b"INS".to_vec(), b"1A".to_vec(), b"REF".to_vec(), b"SE".to_vec()]);
+  }
+
+  #[test]
+  fn splits_on_long_terminators() {
+    let raw = transactions(3).replace("~\n", "~X\n");
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let options = ParallelOptions { workers: 2, max_in_flight: 2, tag_rule: SegmentTagRule::default() };
+    let mut seen = Vec::new();
+    let res = execute_parallel_parser(&mut ioish, &options, |t| (t.segments.len(), t.segments[1].start_offset), |r| seen.push(r.unwrap()));
+    assert!(res.is_ok());
+    assert_eq!(seen.len(), 3);
+    assert_eq!(seen[0], (4, raw.find("INS*Y*18").unwrap() as u64));
+  }
+}
@@ -2,6 +2,7 @@ use crate::edi_segments::Segment;
 use crate::edi_segments::ParserIterator;
 use crate::edi_delimiters::DelimiterResult;
 use crate::edi_delimiters::detect_delimiters;
+use crate::edi_tag_rules::SegmentTagRule;
 use crate::edi_segments::create_segment_iterator;
 use crate::edi_constants::{ST_TAG, SE_TAG, GS_TAG, GE_TAG, IEA_TAG, ISA_TAG};
 use std::io::Error;
@@ -12,10 +13,16 @@ type StreamerCreationResult<'a, T> = Result<ParserIterator<'a, T>, Error>;
 
 #[allow(clippy::needless_lifetimes)]
 pub fn create_edi_streamer<'a, T: Read + Seek>(ioish: &'a mut T) -> StreamerCreationResult<'a, T> {
-  let delim_result = detect_delimiters(ioish);
+  create_edi_streamer_with_tag_rule(ioish, SegmentTagRule::default())
+}
+
+// The tag rule is also used to find where the ISA's terminator ends.
+#[allow(clippy::needless_lifetimes)]
+pub fn create_edi_streamer_with_tag_rule<'a, T: Read + Seek>(ioish: &'a mut T, tag_rule: SegmentTagRule) -> StreamerCreationResult<'a, T> {
+  let delim_result = detect_delimiters(ioish, &tag_rule);
   match delim_result {
     DelimiterResult::DelimiterReadError(e) => Err(e),
-    DelimiterResult::DelimitersFound(d) => Ok(create_segment_iterator(ioish, d.element_delimiter, d.segment_delimiter))
+    DelimiterResult::DelimitersFound(d) => Ok(create_segment_iterator(ioish, d.element_delimiter, d.segment_delimiter).with_tag_rule(tag_rule))
   }
 }
 
@@ -55,113 +62,140 @@ pub fn execute_streaming_parser<T: Read, U: StreamParser>(parser_iterator: &mut
   complete_parsing(stream_parser);
 }
 
-fn complete_parsing<T: StreamParser>(stream_parser: &mut T) {
-  if stream_parser.in_transaction() {
-    stream_parser.transaction_end(None);
-    stream_parser.functional_group_end(None);
-    stream_parser.interchange_end(None);
-  } else if stream_parser.in_functional_group() {
-    stream_parser.functional_group_end(None);
-    stream_parser.interchange_end(None);
-  } else if stream_parser.in_interchange() {
-    stream_parser.interchange_end(None);
+// For iterators created `with_recovery`: every error is handed to
+// `stream_parser.error` and parsing carries on until the iterator is done.
+pub fn execute_lenient_streaming_parser<T: Read, U: StreamParser>(parser_iterator: &mut ParserIterator<T>, stream_parser: &mut U) {
+  for pr in parser_iterator.by_ref() {
+    match pr {
+      Err(e) => stream_parser.error(e),
+      Ok(segment) => consume_segment(stream_parser, &segment)
+    }
+  }
+  complete_parsing(stream_parser);
+}
+
+pub(crate) fn complete_parsing<T: StreamParser>(stream_parser: &mut T) {
+  let events = closing_events(stream_parser.in_transaction(), stream_parser.in_functional_group(), stream_parser.in_interchange());
+  for event in events {
+    apply_event(stream_parser, *event, None);
   }
   stream_parser.stream_end();
 }
 
-fn consume_segment<T: StreamParser>(stream_parser: &mut T, segment: &Segment) {
-  if stream_parser.in_transaction() {
-    consume_segment_in_transaction(stream_parser, segment);
-  } else if stream_parser.in_functional_group() {
-    consume_segment_in_functional_group(stream_parser, segment);
-  } else if stream_parser.in_interchange() {
-    consume_segment_in_interchange(stream_parser, segment);
+pub(crate) fn consume_segment<T: StreamParser>(stream_parser: &mut T, segment: &Segment) {
+  let events = segment_events(stream_parser.in_transaction(), stream_parser.in_functional_group(), stream_parser.in_interchange(), segment.tag.as_slice());
+  for event in events {
+    apply_event(stream_parser, *event, Some(segment));
+  }
+}
+
+// The envelope callbacks to make for a segment depend only on the envelope
+// state before the segment and on its tag, so they are worked out once here
+// and shared by the blocking and async executors.  The `bool` on the end
+// events is whether the triggering segment is passed along as the trailer.
+#[derive(Clone, Copy)]
+pub(crate) enum EnvelopeEvent {
+  Segment,
+  InterchangeStart,
+  InterchangeEnd(bool),
+  FunctionalGroupStart,
+  FunctionalGroupEnd(bool),
+  TransactionStart,
+  TransactionEnd(bool)
+}
+
+fn apply_event<T: StreamParser>(stream_parser: &mut T, event: EnvelopeEvent, segment: Option<&Segment>) {
+  match (event, segment) {
+    (EnvelopeEvent::Segment, Some(s)) => stream_parser.segment(s),
+    (EnvelopeEvent::InterchangeStart, Some(s)) => stream_parser.interchange_start(s),
+    (EnvelopeEvent::FunctionalGroupStart, Some(s)) => stream_parser.functional_group_start(s),
+    (EnvelopeEvent::TransactionStart, Some(s)) => stream_parser.transaction_start(s),
+    (EnvelopeEvent::InterchangeEnd(closing), s) => stream_parser.interchange_end(s.filter(|_| closing)),
+    (EnvelopeEvent::FunctionalGroupEnd(closing), s) => stream_parser.functional_group_end(s.filter(|_| closing)),
+    (EnvelopeEvent::TransactionEnd(closing), s) => stream_parser.transaction_end(s.filter(|_| closing)),
+    (_, None) => ()
+  }
+}
+
+pub(crate) fn closing_events(in_transaction: bool, in_functional_group: bool, in_interchange: bool) -> &'static [EnvelopeEvent] {
+  use EnvelopeEvent::*;
+  if in_transaction {
+    &[TransactionEnd(false), FunctionalGroupEnd(false), InterchangeEnd(false)]
+  } else if in_functional_group {
+    &[FunctionalGroupEnd(false), InterchangeEnd(false)]
+  } else if in_interchange {
+    &[InterchangeEnd(false)]
+  } else {
+    &[]
+  }
+}
+
+pub(crate) fn segment_events(in_transaction: bool, in_functional_group: bool, in_interchange: bool, tag: &[u8]) -> &'static [EnvelopeEvent] {
+  if in_transaction {
+    segment_events_in_transaction(tag)
+  } else if in_functional_group {
+    segment_events_in_functional_group(tag)
+  } else if in_interchange {
+    segment_events_in_interchange(tag)
   } else {
-    consume_segment_in_nothing(stream_parser, segment);
+    segment_events_in_nothing(tag)
   }
 }
 
-fn consume_segment_in_nothing<T: StreamParser>(stream_parser: &mut T, segment: &Segment) {
-  let tag_compare = segment.tag.as_slice();
+fn segment_events_in_nothing(tag_compare: &[u8]) -> &'static [EnvelopeEvent] {
+  use EnvelopeEvent::*;
   if ISA_TAG.eq(tag_compare) {
-    stream_parser.interchange_start(segment);
+    &[InterchangeStart, Segment]
+  } else {
+    &[Segment]
   }
-  stream_parser.segment(segment);
 }
 
-fn consume_segment_in_transaction<T: StreamParser>(stream_parser: &mut T, segment: &Segment) {
-  let tag_compare = segment.tag.as_slice();
+fn segment_events_in_transaction(tag_compare: &[u8]) -> &'static [EnvelopeEvent] {
+  use EnvelopeEvent::*;
   if SE_TAG.eq(tag_compare) {
-    stream_parser.segment(segment);
-    stream_parser.transaction_end(Some(segment));
+    &[Segment, TransactionEnd(true)]
   } else if ST_TAG.eq(tag_compare) {
-    stream_parser.transaction_end(None);
-    stream_parser.transaction_start(segment);
-    stream_parser.segment(segment);
+    &[TransactionEnd(false), TransactionStart, Segment]
   } else if GE_TAG.eq(tag_compare) {
-    stream_parser.transaction_end(None);
-    stream_parser.segment(segment);
-    stream_parser.functional_group_end(Some(segment));
+    &[TransactionEnd(false), Segment, FunctionalGroupEnd(true)]
   } else if GS_TAG.eq(tag_compare) {
-    stream_parser.transaction_end(None);
-    stream_parser.functional_group_end(None);
-    stream_parser.functional_group_start(segment);
-    stream_parser.segment(segment);
+    &[TransactionEnd(false), FunctionalGroupEnd(false), FunctionalGroupStart, Segment]
   } else if IEA_TAG.eq(tag_compare) {
-    stream_parser.transaction_end(None);
-    stream_parser.functional_group_end(None);
-    stream_parser.segment(segment);
-    stream_parser.interchange_end(Some(segment));
+    &[TransactionEnd(false), FunctionalGroupEnd(false), Segment, InterchangeEnd(true)]
   } else if ISA_TAG.eq(tag_compare) {
-    stream_parser.transaction_end(None);
-    stream_parser.functional_group_end(None);
-    stream_parser.interchange_end(None);
-    stream_parser.interchange_start(segment);
-    stream_parser.segment(segment);
+    &[TransactionEnd(false), FunctionalGroupEnd(false), InterchangeEnd(false), InterchangeStart, Segment]
   } else {
-    stream_parser.segment(segment);
+    &[Segment]
   }
 }
 
-fn consume_segment_in_functional_group<T: StreamParser>(stream_parser: &mut T, segment: &Segment) {
-  let tag_compare = segment.tag.as_slice();
+fn segment_events_in_functional_group(tag_compare: &[u8]) -> &'static [EnvelopeEvent] {
+  use EnvelopeEvent::*;
   if GS_TAG.eq(tag_compare) {
-    stream_parser.functional_group_end(None);
-    stream_parser.functional_group_start(segment);
-    stream_parser.segment(segment);
+    &[FunctionalGroupEnd(false), FunctionalGroupStart, Segment]
   } else if GE_TAG.eq(tag_compare) {
-    stream_parser.segment(segment);
-    stream_parser.functional_group_end(Some(segment));
+    &[Segment, FunctionalGroupEnd(true)]
   } else if ST_TAG.eq(tag_compare) {
-    stream_parser.transaction_start(segment);
-    stream_parser.segment(segment);
+    &[TransactionStart, Segment]
   } else if IEA_TAG.eq(tag_compare) {
-    stream_parser.functional_group_end(None);
-    stream_parser.segment(segment);
-    stream_parser.interchange_end(Some(segment));
+    &[FunctionalGroupEnd(false), Segment, InterchangeEnd(true)]
   } else if ISA_TAG.eq(tag_compare) {
-    stream_parser.functional_group_end(None);
-    stream_parser.interchange_end(None);
-    stream_parser.interchange_start(segment);
-    stream_parser.segment(segment);
+    &[FunctionalGroupEnd(false), InterchangeEnd(false), InterchangeStart, Segment]
   } else {
-    stream_parser.segment(segment);
+    &[Segment]
   }
 }
 
-fn consume_segment_in_interchange<T: StreamParser>(stream_parser: &mut T, segment: &Segment) {
-  let tag_compare = segment.tag.as_slice();
+fn segment_events_in_interchange(tag_compare: &[u8]) -> &'static [EnvelopeEvent] {
+  use EnvelopeEvent::*;
   if GS_TAG.eq(tag_compare) {
-    stream_parser.functional_group_start(segment);
-    stream_parser.segment(segment);
-  } else if IEA_TAG.eq(segment.tag.as_slice()) {
-    stream_parser.segment(segment);
-    stream_parser.interchange_end(Some(segment));
+    &[FunctionalGroupStart, Segment]
+  } else if IEA_TAG.eq(tag_compare) {
+    &[Segment, InterchangeEnd(true)]
   } else if ISA_TAG.eq(tag_compare) {
-    stream_parser.interchange_end(None);
-    stream_parser.interchange_start(segment);
-    stream_parser.segment(segment);
+    &[InterchangeEnd(false), InterchangeStart, Segment]
   } else {
-    stream_parser.segment(segment);
+    &[Segment]
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,485 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::Segment;
+use crate::edi_envelope::EnvelopeState;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{Error, ErrorKind};
+
+#[derive(PartialEq, Debug, Clone)]
+enum Condition {
+  Present,
+  Equals(Vec<u8>),
+  NotEquals(Vec<u8>)
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Predicate {
+  element: usize,
+  condition: Condition
+}
+
+impl Predicate {
+  fn holds(&self, segment: &Segment) -> bool {
+    let value = segment.fields.get(self.element).map(|v| v.as_slice()).unwrap_or_default();
+    match &self.condition {
+      Condition::Present => !value.is_empty(),
+      Condition::Equals(v) => value == v.as_slice(),
+      Condition::NotEquals(v) => value != v.as_slice()
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Step {
+  // `None` for `*`, any tag.
+  Segment(Option<Vec<u8>>, Vec<Predicate>),
+  Parent
+}
+
+// A path such as `ST[01=834]/INS[01=Y]/../NM1[01=IL]/09`.
+//
+// Each segment step matches segments after the one before it and within its
+// scope.  A segment's scope runs up to the next segment with the same tag (the
+// next pass through its loop) or the end of the scope it was found in; ISA, GS
+// and ST scopes run to the end of the transaction.  `..` goes back to the
+// segment the step before was found from, and the steps after it match
+// segments that follow the one `..` was taken from.  A trailing number picks
+// an element.  Predicates are `[NN]` (not empty), `[NN=value]` and
+// `[NN!=value]`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Query {
+  steps: Vec<Step>,
+  element: Option<usize>
+}
+
+fn invalid(path: &str, message: &str) -> Error {
+  Error::new(ErrorKind::InvalidInput, format!("{} in query {}", message, path))
+}
+
+impl Query {
+  pub fn parse(path: &str) -> Result<Self, Error> {
+    let mut steps = Vec::new();
+    let mut element = None;
+    for part in split_steps(path)? {
+      if element.is_some() {
+        return Err(invalid(path, "the element number must come last"));
+      }
+      if part == ".." {
+        steps.push(Step::Parent);
+      } else if !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()) {
+        element = Some(part.parse().map_err(|_| invalid(path, "bad element number"))?);
+      } else {
+        steps.push(parse_segment_step(path, part)?);
+      }
+    }
+    if !steps.iter().any(|s| *s != Step::Parent) {
+      return Err(invalid(path, "no segment step"));
+    }
+    Ok(Query { steps, element })
+  }
+}
+
+// A segment a step was found at, while its scope is open.  `scope_tag` is the
+// tag that ends the scope, `None` for ISA, GS and ST, whose scopes end with
+// the one they were found in.
+struct Node {
+  segment: Segment,
+  scope_tag: Option<Vec<u8>>,
+  parent: Option<usize>
+}
+
+// Evaluates a query over one transaction as its segments arrive.  Only the
+// segments whose scopes are still open are held, along with the steps being
+// looked for in each of them.
+struct Evaluation {
+  nodes: BTreeMap<usize, Node>,
+  next_id: usize,
+  // (step, node) pairs: the step to match next, in the scope of the node.
+  // `None` is the whole transaction.
+  cursors: BTreeSet<(usize, Option<usize>)>
+}
+
+impl Evaluation {
+  fn new() -> Self {
+    Evaluation {
+      nodes: BTreeMap::new(),
+      next_id: 0,
+      cursors: BTreeSet::from([(0, None)])
+    }
+  }
+
+  // Hands `found` each segment the path ends on, as soon as it is known.
+  fn segment(&mut self, query: &Query, segment: &Segment, found: &mut dyn FnMut(&Segment)) {
+    self.close_scopes(&segment.tag);
+    let mut opened = Vec::new();
+    for (step, node) in self.cursors.iter() {
+      let matched = match &query.steps[*step] {
+        Step::Segment(tag, predicates) => tag.as_ref().map(|t| *t == segment.tag).unwrap_or(true) && predicates.iter().all(|p| p.holds(segment)),
+        Step::Parent => false
+      };
+      if matched {
+        let scope_tag = match segment.tag.as_slice() {
+          b"ISA" | b"GS" | b"ST" => None,
+          tag => Some(tag.to_vec())
+        };
+        self.nodes.insert(self.next_id, Node { segment: segment.clone(), scope_tag, parent: *node });
+        opened.push((step + 1, Some(self.next_id)));
+        self.next_id += 1;
+      }
+    }
+    for (step, node) in opened {
+      self.advance(query, step, node, found);
+    }
+  }
+
+  // Follows `..` steps from a node that has just been reached.
+  fn advance(&mut self, query: &Query, mut step: usize, mut node: Option<usize>, found: &mut dyn FnMut(&Segment)) {
+    loop {
+      if step == query.steps.len() {
+        if let Some(n) = node.and_then(|n| self.nodes.get(&n)) {
+          found(&n.segment);
+        }
+        return;
+      }
+      match query.steps[step] {
+        Step::Parent => match node.and_then(|n| self.nodes.get(&n)) {
+          None => return,
+          Some(n) => {
+            node = n.parent;
+            step += 1;
+          }
+        },
+        Step::Segment(_, _) => {
+          self.cursors.insert((step, node));
+          return;
+        }
+      }
+    }
+  }
+
+  // A segment ends the scopes with its tag, and those ending scopes end the
+  // scopes found within them.  Parents come before their children.
+  fn close_scopes(&mut self, tag: &[u8]) {
+    let mut closed = HashSet::new();
+    for (id, node) in self.nodes.iter() {
+      if node.scope_tag.as_deref() == Some(tag) || node.parent.map(|p| closed.contains(&p)).unwrap_or(false) {
+        closed.insert(*id);
+      }
+    }
+    for id in closed.iter() {
+      self.nodes.remove(id);
+    }
+    self.cursors.retain(|(_, node)| node.map(|n| !closed.contains(&n)).unwrap_or(true));
+  }
+}
+
+fn split_steps(path: &str) -> Result<Vec<&str>, Error> {
+  let mut parts = Vec::new();
+  let mut start = 0;
+  let mut depth = 0;
+  for (n, c) in path.char_indices() {
+    match c {
+      '[' if depth == 0 => depth = 1,
+      ']' if depth == 1 => depth = 0,
+      '/' if depth == 0 => {
+        parts.push(&path[start..n]);
+        start = n + 1;
+      },
+      _ => ()
+    }
+  }
+  if depth != 0 {
+    return Err(invalid(path, "unclosed ["));
+  }
+  parts.push(&path[start..]);
+  if parts.iter().any(|p| p.is_empty()) {
+    return Err(invalid(path, "empty step"));
+  }
+  Ok(parts)
+}
+
+fn parse_segment_step(path: &str, part: &str) -> Result<Step, Error> {
+  let tag_end = part.find('[').unwrap_or(part.len());
+  let tag = match &part[..tag_end] {
+    "*" => None,
+    t if (2..=3).contains(&t.len()) && t.starts_with(|c: char| c.is_ascii_uppercase()) && t.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) => Some(t.as_bytes().to_vec()),
+    t => return Err(invalid(path, &format!("bad segment tag {}", t)))
+  };
+  let mut predicates = Vec::new();
+  let mut rest = &part[tag_end..];
+  while !rest.is_empty() {
+    let close = match rest.strip_prefix('[').and_then(|r| r.find(']')) {
+      Some(c) => c + 1,
+      None => return Err(invalid(path, &format!("bad predicate {}", rest)))
+    };
+    let text = &rest[1..close];
+    let (number, condition) = match text.split_once("!=") {
+      Some((n, v)) => (n, Condition::NotEquals(v.as_bytes().to_vec())),
+      None => match text.split_once('=') {
+        Some((n, v)) => (n, Condition::Equals(v.as_bytes().to_vec())),
+        None => (text, Condition::Present)
+      }
+    };
+    let element = match number.parse() {
+      Ok(e) if e > 0 && number.bytes().all(|b| b.is_ascii_digit()) => e,
+      _ => return Err(invalid(path, &format!("bad element number {}", number)))
+    };
+    predicates.push(Predicate { element, condition });
+    rest = &rest[close + 1..];
+  }
+  Ok(Step::Segment(tag, predicates))
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct QueryMatch {
+  pub interchange_control_number: Option<String>,
+  pub group_control_number: Option<String>,
+  pub transaction_set_id: Option<String>,
+  pub transaction_control_number: Option<String>,
+  pub tag: String,
+  pub element: Option<usize>,
+  // The element, or the elements of the whole segment joined by `*`.
+  pub value: String,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub end_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+pub trait QueryHandler {
+  fn matched(&mut self, result: QueryMatch);
+}
+
+impl QueryHandler for Vec<QueryMatch> {
+  fn matched(&mut self, result: QueryMatch) {
+    self.push(result);
+  }
+}
+
+fn text(segment: Option<&Segment>, n: usize) -> Option<String> {
+  segment.and_then(|s| s.fields.get(n)).filter(|v| !v.is_empty()).map(|v| String::from_utf8_lossy(v).to_string())
+}
+
+// Runs a query over each transaction along with its ISA and GS as the
+// segments arrive, holding only the segments whose scopes are still open.  An
+// ISA or GS that several transactions match is reported once; segments
+// outside transactions and the GE and IEA trailers are not queried.
+pub struct QueryParser<H: QueryHandler> {
+  query: Query,
+  handler: H,
+  state: EnvelopeState,
+  interchange: Option<Segment>,
+  group: Option<Segment>,
+  transaction: Option<Segment>,
+  evaluation: Evaluation,
+  reported: HashSet<u64>,
+  emitted: HashSet<u64>,
+  error: Option<Error>
+}
+
+impl<H: QueryHandler> QueryParser<H> {
+  pub fn new(query: Query, handler: H) -> Self {
+    QueryParser {
+      query,
+      handler,
+      state: EnvelopeState::Nothing,
+      interchange: None,
+      group: None,
+      transaction: None,
+      evaluation: Evaluation::new(),
+      reported: HashSet::new(),
+      emitted: HashSet::new(),
+      error: None
+    }
+  }
+
+  pub fn handler(&self) -> &H {
+    &self.handler
+  }
+
+  // The handler, or the first read error.
+  pub fn finish(self) -> Result<H, Error> {
+    match self.error {
+      Some(e) => Err(e),
+      None => Ok(self.handler)
+    }
+  }
+
+  fn evaluate(&mut self, segment: &Segment) {
+    let mut found = Vec::new();
+    self.evaluation.segment(&self.query, segment, &mut |s| found.push(s.clone()));
+    for segment in found {
+      self.matched(&segment);
+    }
+  }
+
+  fn matched(&mut self, segment: &Segment) {
+    if !self.emitted.insert(segment.segment_index) {
+      return;
+    }
+    let envelope = segment.tag == b"ISA" || segment.tag == b"GS";
+    if envelope && !self.reported.insert(segment.segment_index) {
+      return;
+    }
+    let value = match self.query.element {
+      Some(e) => match text(Some(segment), e) {
+        Some(v) => v,
+        None => return
+      },
+      None => segment.fields[1..].iter().map(|f| String::from_utf8_lossy(f)).collect::<Vec<_>>().join("*")
+    };
+    let st = self.transaction.as_ref();
+    self.handler.matched(QueryMatch {
+      interchange_control_number: text(self.interchange.as_ref(), 13),
+      group_control_number: text(self.group.as_ref(), 6),
+      transaction_set_id: text(st, 1),
+      transaction_control_number: text(st, 2),
+      tag: String::from_utf8_lossy(&segment.tag).to_string(),
+      element: self.query.element,
+      value,
+      segment_index: segment.segment_index,
+      start_offset: segment.start_offset,
+      end_offset: segment.end_offset,
+      line: segment.line,
+      column: segment.column
+    });
+  }
+}
+
+impl<H: QueryHandler> StreamParser for QueryParser<H> {
+  fn segment(&mut self, segment: &Segment) {
+    if !self.state.in_transaction() {
+      return;
+    }
+    // The ISA and GS are queried along with each transaction, once its ST
+    // is known.
+    if self.transaction.is_none() {
+      self.transaction = Some(segment.clone());
+      let envelope : Vec<Segment> = self.interchange.iter().chain(self.group.iter()).cloned().collect();
+      for s in envelope.iter() {
+        self.evaluate(s);
+      }
+    }
+    self.evaluate(segment);
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    self.interchange = Some(segment.clone());
+    self.reported.clear();
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+    self.interchange = None;
+  }
+
+  fn functional_group_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    self.group = Some(segment.clone());
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+    self.group = None;
+  }
+
+  fn transaction_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    self.transaction = None;
+    self.evaluation = Evaluation::new();
+    self.emitted.clear();
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    self.transaction = None;
+    self.evaluation = Evaluation::new();
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, error: Error) {
+    self.error.get_or_insert(error);
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Query, QueryParser, QueryMatch};
+  use crate::edi_parsers::{create_edi_streamer, execute_streaming_parser};
+  use std::io::Cursor;
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SPONSOR        *ZZ*PAYER          *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SPONSOR*PAYER*20230101*1200*7*X*005010X220A1~
+ST*834*0001*005010X220A1~
+INS*Y*18*021*28*A***FT~
+NM1*IL*1*DOE*JOHN****34*111223333~
+INS*N*19*021*28*A~
+NM1*IL*1*DOE*JANE****34*444556666~
+SE*6*0001~
+ST*834*0002*005010X220A1~
+INS*N*19*021*28*A~
+NM1*IL*1*ROE*JIM****34*777889999~
+SE*4*0002~
+ST*820*0003~
+NM1*IL*1*POE*ANN****34*000000000~
+SE*3*0003~
+GE*3*7~
+IEA*1*000000001~
+";
+
+  fn query(path: &str) -> Vec<QueryMatch> {
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let mut parser = QueryParser::new(Query::parse(path).unwrap(), Vec::new());
+    execute_streaming_parser(&mut pi, &mut parser);
+    parser.finish().unwrap()
+  }
+
+  fn values(path: &str) -> Vec<String> {
+    query(path).into_iter().map(|m| m.value).collect()
+  }
+
+  #[test]
+  fn evaluates_paths() {
+    assert_eq!(values("ST[01=834]/INS[01=Y]/../NM1[01=IL]/09"), vec!["111223333", "444556666"]);
+    assert_eq!(values("INS[01=Y]/NM1/09"), vec!["111223333"]);
+    assert_eq!(values("INS[01!=Y]/NM1/04"), vec!["JANE", "JIM"]);
+    assert_eq!(values("ST[01=834]/NM1[03=DOE][09]/04"), vec!["JOHN", "JANE"]);
+    assert_eq!(values("GS/06"), vec!["7"]);
+    assert_eq!(values("*[01=820]"), vec!["820*0003"]);
+    assert_eq!(values("INS/NM1[04=JANE]/../01"), vec!["N"]);
+    assert_eq!(values("ST/NM1[04=JOHN]/../INS/01"), vec!["N"]);
+
+    let found = query("ST[01=820]/NM1/03");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].interchange_control_number.as_deref(), Some("000000001"));
+    assert_eq!(found[0].group_control_number.as_deref(), Some("7"));
+    assert_eq!(found[0].transaction_control_number.as_deref(), Some("0003"));
+    assert_eq!((found[0].line, found[0].segment_index), (14, 13));
+    assert_eq!(found[0].start_offset, RAW.find("NM1*IL*1*POE").unwrap() as u64);
+
+    for bad in ["", "ST//NM1", "ST/09/NM1", "st", "NM1[01=IL", "NM1[x=1]", ".."] {
+      assert!(Query::parse(bad).is_err(), "{}", bad);
+    }
+  }
+}
@@ -0,0 +1,313 @@
+use crate::edi_segments::{Segment, ParserConfig, ParserState, PState, step_byte, step_eof, step_interrupted, check_limits, advance_position};
+use crate::edi_limits::LimitState;
+use std::fmt;
+use std::io::{Read, Error, ErrorKind};
+
+// Give up after this many read errors in a row without any progress.
+pub const MAX_CONSECUTIVE_READ_ERRORS : u32 = 8;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum RecoveryKind {
+  MalformedSegment,
+  ReadError(ErrorKind)
+}
+
+// The offsets cover the whole skipped region.  With limits set, `skipped`
+// holds no more than `max_segment_bytes` of it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RecoveryDiagnostic {
+  pub kind: RecoveryKind,
+  pub start_offset: u64,
+  pub end_offset: u64,
+  pub segment_index: u64,
+  pub line: u64,
+  pub column: u64,
+  pub skipped: Vec<u8>
+}
+
+impl RecoveryDiagnostic {
+  pub fn from_error(error: &Error) -> Option<&RecoveryDiagnostic> {
+    error.get_ref().and_then(|e| e.downcast_ref::<RecoveryDiagnostic>())
+  }
+}
+
+impl fmt::Display for RecoveryDiagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let what = match &self.kind {
+      RecoveryKind::MalformedSegment => String::from("malformed segment"),
+      RecoveryKind::ReadError(k) => format!("read error ({})", k)
+    };
+    write!(f, "{}: skipped bytes {} to {} at line {}, column {}", what, self.start_offset, self.end_offset, self.line, self.column)
+  }
+}
+
+impl std::error::Error for RecoveryDiagnostic {}
+
+pub(crate) struct RecoveryState {
+  region: Option<RecoveryDiagnostic>,
+  pending: Option<Segment>,
+  damaged: Option<ErrorKind>,
+  consecutive_errors: u32
+}
+
+// What recovery mode carries from one segment to the next: a skipped region
+// not yet reported and any read error since the last segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct RecoveryCheckpoint {
+  pub region: Option<RecoveryDiagnostic>,
+  pub damaged: Option<ErrorKind>,
+  pub consecutive_errors: u32
+}
+
+impl RecoveryState {
+  pub(crate) fn new() -> Self {
+    RecoveryState {
+      region: None,
+      pending: None,
+      damaged: None,
+      consecutive_errors: 0
+    }
+  }
+
+  // A segment held back behind a diagnostic is not captured, so checkpoints
+  // are taken after a segment has been handed back.
+  pub(crate) fn checkpoint(&self) -> RecoveryCheckpoint {
+    RecoveryCheckpoint {
+      region: self.region.clone(),
+      damaged: self.damaged,
+      consecutive_errors: self.consecutive_errors
+    }
+  }
+
+  pub(crate) fn restore(checkpoint: &RecoveryCheckpoint) -> Self {
+    RecoveryState {
+      region: checkpoint.region.clone(),
+      pending: None,
+      damaged: checkpoint.damaged,
+      consecutive_errors: checkpoint.consecutive_errors
+    }
+  }
+
+  fn quarantine(&mut self, kind: RecoveryKind, segment: &Segment, cap: Option<u64>) {
+    let end_offset = segment.end_offset + segment.stray.len() as u64;
+    match &mut self.region {
+      None => {
+        let mut skipped = Vec::new();
+        extend_capped(&mut skipped, &segment.raw, cap);
+        extend_capped(&mut skipped, &segment.stray, cap);
+        self.region = Some(RecoveryDiagnostic {
+          kind,
+          start_offset: segment.start_offset,
+          end_offset,
+          segment_index: segment.segment_index,
+          line: segment.line,
+          column: segment.column,
+          skipped
+        });
+      },
+      Some(r) => {
+        r.end_offset = end_offset;
+        extend_capped(&mut r.skipped, &segment.raw, cap);
+        extend_capped(&mut r.skipped, &segment.stray, cap);
+      }
+    }
+  }
+
+  // Stray bytes after a good segment are skipped like a malformed segment
+  // that sits between it and the next one.  They are already bounded by the
+  // segment byte limit.
+  fn quarantine_stray(&mut self, segment: &Segment) {
+    let (line, column) = advance_position((segment.line, segment.column), &segment.raw);
+    self.region = Some(RecoveryDiagnostic {
+      kind: RecoveryKind::MalformedSegment,
+      start_offset: segment.end_offset + 1,
+      end_offset: segment.end_offset + segment.stray.len() as u64,
+      segment_index: segment.segment_index + 1,
+      line,
+      column,
+      skipped: segment.stray.clone()
+    });
+  }
+
+  // Hands back a good segment, after any region skipped before it.
+  fn accept(&mut self, segment: Segment) -> Result<Segment, Error> {
+    let earlier = self.take_region();
+    if !segment.stray.is_empty() {
+      self.quarantine_stray(&segment);
+    }
+    match earlier {
+      Some(e) => {
+        self.pending = Some(segment);
+        Err(e)
+      },
+      None => Ok(segment)
+    }
+  }
+
+  fn take_region(&mut self) -> Option<Error> {
+    self.region.take().map(|r| {
+      let kind = match &r.kind {
+        RecoveryKind::MalformedSegment => ErrorKind::InvalidData,
+        RecoveryKind::ReadError(k) => *k
+      };
+      Error::new(kind, r)
+    })
+  }
+}
+
+fn extend_capped(skipped: &mut Vec<u8>, bytes: &[u8], cap: Option<u64>) {
+  let room = cap.map_or(bytes.len(), |c| (c as usize).saturating_sub(skipped.len()).min(bytes.len()));
+  skipped.extend_from_slice(&bytes[..room]);
+}
+
+// Like the normal tokenizer loop, except that segments whose tag the tag
+// rule rejects, and the segment being read when a read fails, are skipped.
+// Each run of skipped bytes is reported as an `Err` carrying a
+// `RecoveryDiagnostic` before the next good segment.
+pub(crate) fn recovering_next<T: Read>(pc: &ParserConfig, ps: &mut ParserState, rs: &mut RecoveryState, ls: &mut Option<LimitState>, ioish: &mut T) -> Option<Result<Segment, Error>> {
+  loop {
+    if let Some(seg) = rs.pending.take() {
+      return Some(Ok(seg));
+    }
+    match ps.state {
+      PState::Errored | PState::EOF => return rs.take_region().map(Err),
+      _ => ()
+    }
+    let mut buff = [0; 1];
+    let completed = match ioish.read(&mut buff) {
+      Ok(1) => step_byte(pc, ps, buff[0]),
+      Ok(_) => step_eof(pc, ps),
+      Err(e) if e.kind() == ErrorKind::Interrupted => None,
+      Err(e) => {
+        rs.consecutive_errors += 1;
+        if rs.consecutive_errors > MAX_CONSECUTIVE_READ_ERRORS {
+          ps.state = PState::Errored;
+          return Some(Err(e));
+        }
+        rs.damaged.get_or_insert(e.kind());
+        if let Some(seg) = step_interrupted(ps) {
+          return match check_limits(ls, ps, Some(seg)) {
+            Ok(output) => output.map(|seg| rs.accept(seg)),
+            Err(e) => {
+              ps.state = PState::Errored;
+              Some(Err(e))
+            }
+          };
+        }
+        None
+      }
+    };
+    let completed = match check_limits(ls, ps, completed) {
+      Ok(c) => c,
+      Err(e) => {
+        ps.state = PState::Errored;
+        return Some(Err(e));
+      }
+    };
+    let seg = match completed {
+      None => continue,
+      Some(s) => s
+    };
+    rs.consecutive_errors = 0;
+    let cap = ls.as_ref().and_then(|l| l.limits.max_segment_bytes);
+    if let Some(kind) = rs.damaged.take() {
+      rs.quarantine(RecoveryKind::ReadError(kind), &seg, cap);
+    } else if pc.tag_rule.split(&seg.tag) != Some(0) {
+      rs.quarantine(RecoveryKind::MalformedSegment, &seg, cap);
+    } else {
+      return Some(rs.accept(seg));
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{RecoveryDiagnostic, RecoveryKind};
+  use crate::edi_segments::create_segment_iterator;
+  use crate::edi_tag_rules::SegmentTagRule;
+  use crate::edi_limits::ParserLimits;
+  use std::io::{Cursor, Read, Error, ErrorKind};
+
+  // Fails once at each of the offsets in `fail_at`, which are in order.
+  struct FlakyReader {
+    inner: Cursor<Vec<u8>>,
+    fail_at: Vec<u64>
+  }
+
+  impl Read for FlakyReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+      if self.fail_at.first() == Some(&self.inner.position()) {
+        self.fail_at.remove(0);
+        return Err(Error::from(ErrorKind::TimedOut));
+      }
+      self.inner.read(buf)
+    }
+  }
+
+  fn tags(results: &[Result<crate::edi_segments::Segment, Error>]) -> Vec<String> {
+    results.iter().map(|r| match r {
+      Ok(s) => String::from_utf8_lossy(&s.tag).to_string(),
+      Err(_) => String::from("!")
+    }).collect()
+  }
+
+  #[test]
+  fn skips_malformed_segments() {
+    let mut ioish = Cursor::new("ST*834*1~N1*X~Q\x01*junk~Z~REF*0F*1~SE*4*1~".as_bytes());
+    let pi = create_segment_iterator(&mut ioish, b"*".to_vec(), b"~".to_vec()).with_recovery();
+    let results : Vec<_> = pi.collect();
+    assert_eq!(tags(&results), vec!["ST", "N1", "!", "REF", "SE"]);
+    let diagnostic = match &results[2] {
+      Err(e) => RecoveryDiagnostic::from_error(e).unwrap().clone(),
+      Ok(_) => panic!("expected a diagnostic")
+    };
+    assert_eq!(diagnostic.kind, RecoveryKind::MalformedSegment);
+    assert_eq!(diagnostic.skipped, b"Q\x01*junk~Z~".to_vec());
+    assert_eq!(diagnostic.start_offset, 14);
+    assert_eq!((diagnostic.line, diagnostic.column), (1, 15));
+    assert_eq!(diagnostic.segment_index, 2);
+  }
+
+  #[test]
+  fn resynchronizes_after_read_error() {
+    let raw = b"ST*834*1~BGN*00*1~REF*0F*1~SE*4*1~".to_vec();
+    let mut reader = FlakyReader { inner: Cursor::new(raw), fail_at: vec![12] };
+    let pi = create_segment_iterator(&mut reader, b"*".to_vec(), b"~".to_vec()).with_recovery();
+    let results : Vec<_> = pi.collect();
+    assert_eq!(tags(&results), vec!["ST", "!", "REF", "SE"]);
+    match &results[1] {
+      Err(e) => {
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+        let diagnostic = RecoveryDiagnostic::from_error(e).unwrap();
+        assert_eq!(diagnostic.kind, RecoveryKind::ReadError(ErrorKind::TimedOut));
+        assert_eq!(diagnostic.skipped, b"BGN*00*1~".to_vec());
+      },
+      Ok(_) => panic!("expected a diagnostic")
+    }
+  }
+
+  #[test]
+  fn uses_the_configured_tag_rule() {
+    let mut ioish = Cursor::new("ST*834*1~N1*X~Q\x01*junk~Z~REF*0F*1~SE*4*1~".as_bytes());
+    let pi = create_segment_iterator(&mut ioish, b"*".to_vec(), b"~".to_vec()).with_tag_rule(SegmentTagRule::AnyByte).with_recovery();
+    let results : Vec<_> = pi.collect();
+    assert_eq!(tags(&results), vec!["ST", "N1", "Q\x01", "Z", "REF", "SE"]);
+  }
+
+  #[test]
+  fn bounds_skipped_bytes_by_the_limits() {
+    let raw = format!("ST*834*1~{}SE*4*1~", "REF*0F*1~".repeat(10));
+    let mut reader = FlakyReader { inner: Cursor::new(raw.into_bytes()), fail_at: (0..10).map(|i| 13 + 9 * i).collect() };
+    let limits = ParserLimits { max_segment_bytes: Some(16), ..ParserLimits::default() };
+    let pi = create_segment_iterator(&mut reader, b"*".to_vec(), b"~".to_vec()).with_limits(limits).with_recovery();
+    let results : Vec<_> = pi.collect();
+    assert_eq!(tags(&results), vec!["ST", "!", "SE"]);
+    let diagnostic = match &results[1] {
+      Err(e) => RecoveryDiagnostic::from_error(e).unwrap().clone(),
+      Ok(_) => panic!("expected a diagnostic")
+    };
+    assert_eq!(diagnostic.kind, RecoveryKind::ReadError(ErrorKind::TimedOut));
+    assert_eq!(diagnostic.skipped, b"REF*0F*1~REF*0F*".to_vec());
+    assert_eq!((diagnostic.start_offset, diagnostic.end_offset), (9, 98));
+  }
+}
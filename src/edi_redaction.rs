@@ -0,0 +1,343 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::Segment;
+use crate::edi_envelope::EnvelopeState;
+use std::io::{Write, Error};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Replacement {
+  // The same value always becomes the same made-up value of the same shape:
+  // letters stay letters (in the same case), digits stay digits and anything
+  // else is kept.
+  Pseudonym,
+  // Letters become X and digits 9.
+  Mask,
+  // For CCYYMMDD dates: only the year is kept, as CCYY0101.
+  YearOnly
+}
+
+// Element `element` of `tag` segments is replaced.  With `when` set, only
+// segments whose element `when.0` holds one of the listed values are.
+#[derive(Clone)]
+pub struct RedactionRule {
+  pub tag: Vec<u8>,
+  pub element: usize,
+  pub when: Option<(usize, Vec<Vec<u8>>)>,
+  pub replacement: Replacement
+}
+
+impl RedactionRule {
+  pub fn new(tag: &[u8], element: usize, replacement: Replacement) -> Self {
+    RedactionRule {
+      tag: tag.to_vec(),
+      element,
+      when: None,
+      replacement
+    }
+  }
+
+  pub fn when(mut self, element: usize, values: &[&[u8]]) -> Self {
+    self.when = Some((element, values.iter().map(|v| v.to_vec()).collect()));
+    self
+  }
+
+  fn applies(&self, segment: &Segment) -> bool {
+    match &self.when {
+      None => true,
+      Some((n, values)) => segment.fields.get(*n).map(|v| values.contains(v)).unwrap_or(false)
+    }
+  }
+}
+
+// REF qualifiers that hold member and patient identifiers: SSN, subscriber
+// and member numbers, client and prior ids, HICN/MBI and patient account.
+const IDENTIFIER_QUALIFIERS : [&[u8]; 9] = [b"SY", b"0F", b"1W", b"23", b"ABB", b"Q4", b"F6", b"EJ", b"IG"];
+
+pub struct RedactionRules {
+  rules: Vec<RedactionRule>,
+  salt: Vec<u8>
+}
+
+impl RedactionRules {
+  // No rules.  `salt` changes every pseudonym, so that they can not be
+  // matched up with those from another run.
+  pub fn new(salt: &[u8]) -> Self {
+    RedactionRules {
+      rules: Vec::new(),
+      salt: salt.to_vec()
+    }
+  }
+
+  // Names and ids of people (NM102 = 1), N3 and N4 addresses, PER contact
+  // numbers, DMG dates of birth and the REF qualifiers for member and patient
+  // identifiers.
+  pub fn healthcare(salt: &[u8]) -> Self {
+    let person : &[&[u8]] = &[b"1"];
+    let mut rules = RedactionRules::new(salt);
+    for n in [3, 4, 5, 9] {
+      rules = rules.with_rule(RedactionRule::new(b"NM1", n, Replacement::Pseudonym).when(2, person));
+    }
+    for n in [1, 2] {
+      rules = rules.with_rule(RedactionRule::new(b"N3", n, Replacement::Pseudonym));
+    }
+    rules = rules.with_rule(RedactionRule::new(b"N4", 1, Replacement::Pseudonym))
+      .with_rule(RedactionRule::new(b"N4", 3, Replacement::Mask));
+    for n in [2, 4, 6, 8] {
+      rules = rules.with_rule(RedactionRule::new(b"PER", n, Replacement::Mask));
+    }
+    rules.with_rule(RedactionRule::new(b"DMG", 2, Replacement::YearOnly))
+      .with_rule(RedactionRule::new(b"REF", 2, Replacement::Pseudonym).when(1, &IDENTIFIER_QUALIFIERS))
+  }
+
+  pub fn with_rule(mut self, rule: RedactionRule) -> Self {
+    self.rules.push(rule);
+    self
+  }
+
+  pub fn pseudonym(&self, value: &[u8]) -> Vec<u8> {
+    let mut state = fnv1a(fnv1a(0xcbf29ce484222325, &self.salt), value);
+    value.iter().map(|b| {
+      state = state.wrapping_mul(0x100000001b3) ^ (state >> 29);
+      let pick = (state % 26) as u8;
+      match b {
+        b'0'..=b'9' => b'0' + pick % 10,
+        b'A'..=b'Z' => b'A' + pick,
+        b'a'..=b'z' => b'a' + pick,
+        other => *other
+      }
+    }).collect()
+  }
+
+  fn replace(&self, value: &[u8], replacement: Replacement) -> Vec<u8> {
+    match replacement {
+      Replacement::Pseudonym => self.pseudonym(value),
+      Replacement::Mask => value.iter().map(|b| match b {
+        b'0'..=b'9' => b'9',
+        b if b.is_ascii_alphabetic() => b'X',
+        other => *other
+      }).collect(),
+      Replacement::YearOnly if value.len() == 8 => [&value[..4], b"0101".as_slice()].concat(),
+      Replacement::YearOnly => value.to_vec()
+    }
+  }
+
+  // The segment's fields after redaction, or `None` when nothing changed.
+  pub fn redact(&self, segment: &Segment) -> Option<Vec<Vec<u8>>> {
+    let mut fields = None;
+    for rule in self.rules.iter().filter(|r| r.tag == segment.tag && r.applies(segment)) {
+      let value = match segment.fields.get(rule.element) {
+        Some(v) if !v.is_empty() => v,
+        _ => continue
+      };
+      let replaced = self.replace(value, rule.replacement);
+      fields.get_or_insert_with(|| segment.fields.clone())[rule.element] = replaced;
+    }
+    fields
+  }
+}
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+  for b in bytes {
+    hash ^= *b as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  hash
+}
+
+// Writes every segment back out with the rules applied.  Segments that are
+// not changed are written byte for byte, and changed ones keep their
+// delimiters, terminator and any bytes that followed them, so the output has
+// the same envelopes and segment counts and parses the same way.
+pub struct Redactor<W: Write> {
+  out: W,
+  rules: RedactionRules,
+  state: EnvelopeState,
+  element_delimiter: Vec<u8>,
+  redacted: u64,
+  error: Option<Error>
+}
+
+impl<W: Write> Redactor<W> {
+  pub fn new(out: W, rules: RedactionRules) -> Self {
+    Redactor {
+      out,
+      rules,
+      state: EnvelopeState::Nothing,
+      element_delimiter: b"*".to_vec(),
+      redacted: 0,
+      error: None
+    }
+  }
+
+  // The number of segments that were changed.
+  pub fn redacted(&self) -> u64 {
+    self.redacted
+  }
+
+  // The output, or the first read or write error.
+  pub fn finish(self) -> Result<W, Error> {
+    match self.error {
+      Some(e) => Err(e),
+      None => Ok(self.out)
+    }
+  }
+
+  fn write(&mut self, segment: &Segment) -> Result<(), Error> {
+    let fields = match self.rules.redact(segment) {
+      None => {
+        self.out.write_all(&segment.raw)?;
+        return self.out.write_all(&segment.stray);
+      },
+      Some(f) => f
+    };
+    self.redacted += 1;
+    let body = segment.tag.len() + segment.fields[1..].iter().map(|f| f.len() + self.element_delimiter.len()).sum::<usize>();
+    let terminator = segment.raw.get(body..).unwrap_or_default();
+    let mut bytes = segment.tag.clone();
+    for field in fields[1..].iter() {
+      bytes.extend_from_slice(&self.element_delimiter);
+      bytes.extend_from_slice(field);
+    }
+    bytes.extend_from_slice(terminator);
+    bytes.extend_from_slice(&segment.stray);
+    self.out.write_all(&bytes)
+  }
+}
+
+impl<W: Write> StreamParser for Redactor<W> {
+  fn segment(&mut self, segment: &Segment) {
+    if self.error.is_some() {
+      return;
+    }
+    if let Err(e) = self.write(segment) {
+      self.error = Some(e);
+    }
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    if let Some(d) = segment.raw.get(3) {
+      self.element_delimiter = vec![*d];
+    }
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+  }
+
+  fn functional_group_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn transaction_start(&mut self, _segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+  }
+
+  fn stream_end(&mut self) {
+    if let Err(e) = self.out.flush() {
+      self.error.get_or_insert(e);
+    }
+  }
+
+  fn error(&mut self, error: Error) {
+    self.error.get_or_insert(error);
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{Redactor, RedactionRules, RedactionRule, Replacement};
+  use crate::edi_parsers::{create_edi_streamer, execute_streaming_parser};
+  use std::io::Cursor;
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SPONSOR        *ZZ*PAYER          *230101*1200*^*00501*000000001*0*P*:~\r
+GS*BE*SPONSOR*PAYER*20230101*1200*1*X*005010X220A1~\r
+ST*834*0001*005010X220A1~\r
+BGN*00*12456*20230101*1200****4~\r
+N1*P5*ACME CORP*FI*123456789~\r
+INS*Y*18*021*28*A***FT~\r
+REF*0F*123456789~\r
+REF*1L*GROUP1~\r
+NM1*IL*1*DOE*JOHN*Q***34*123456789~\r
+PER*IP**HP*5551234567~\r
+N3*1 MAIN ST~\r
+N4*ANYTOWN*NY*12345~\r
+DMG*D8*19800517*M~\r
+HD*021**HLT~\r
+SE*13*0001~\r
+GE*1*1~\r
+IEA*1*000000001~\r
+";
+
+  fn redact(raw: &[u8], rules: RedactionRules) -> (Vec<u8>, u64) {
+    let mut ioish = Cursor::new(raw);
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let mut redactor = Redactor::new(Vec::new(), rules);
+    execute_streaming_parser(&mut pi, &mut redactor);
+    let count = redactor.redacted();
+    (redactor.finish().unwrap(), count)
+  }
+
+  fn tags(raw: &[u8]) -> Vec<(Vec<u8>, usize)> {
+    let mut ioish = Cursor::new(raw);
+    let pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    pi.map(|r| r.unwrap()).map(|s| (s.tag, s.fields.len())).collect()
+  }
+
+  #[test]
+  fn copies_without_rules() {
+    let (out, count) = redact(RAW.as_bytes(), RedactionRules::new(b""));
+    assert_eq!(count, 0);
+    assert_eq!(out, RAW.as_bytes());
+  }
+
+  #[test]
+  fn redacts_people_consistently() {
+    let (out, count) = redact(RAW.as_bytes(), RedactionRules::healthcare(b"salt"));
+    let text = String::from_utf8(out.clone()).unwrap();
+    assert_eq!(count, 6);
+    assert_eq!(tags(&out), tags(RAW.as_bytes()));
+    assert!(!text.contains("DOE") && !text.contains("MAIN ST") && !text.contains("19800517"));
+    assert!(text.contains("N1*P5*ACME CORP*FI*123456789~\r\n"));
+    assert!(text.contains("REF*1L*GROUP1~\r\n"));
+    assert!(text.contains("PER*IP**HP*9999999999~\r\n"));
+    assert!(text.contains("N4*") && text.contains("*NY*99999~\r\n"));
+    assert!(text.contains("DMG*D8*19800101*M~\r\n"));
+    let rules = RedactionRules::healthcare(b"salt");
+    let member_id = String::from_utf8(rules.pseudonym(b"123456789")).unwrap();
+    assert_eq!(member_id.len(), 9);
+    assert!(member_id.bytes().all(|b| b.is_ascii_digit()));
+    assert!(text.contains(&format!("REF*0F*{}~", member_id)));
+    assert!(text.contains(&format!("***34*{}~", member_id)));
+    assert_ne!(rules.pseudonym(b"DOE"), RedactionRules::healthcare(b"pepper").pseudonym(b"DOE"));
+    let custom = RedactionRules::new(b"").with_rule(RedactionRule::new(b"BGN", 2, Replacement::Mask));
+    let (out, _) = redact(RAW.as_bytes(), custom);
+    assert!(String::from_utf8(out).unwrap().contains("BGN*00*99999*20230101"));
+  }
+}
@@ -0,0 +1,537 @@
+use crate::edi_common::{TransactionHandler, TransactionParser, Separators, Reference, DateReference, Amount, Party, Entity};
+use crate::edi_claims::Procedure;
+use crate::edi_elements::{Decimal, EdiDate};
+use crate::edi_segments::Segment;
+use std::fmt;
+
+// BPR, TRN and the 1000A and 1000B loops.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct RemittanceHeader {
+  pub control_number: String,
+  pub handling_code: Option<String>,
+  pub total_payment: Option<Decimal>,
+  pub credit_debit: Option<String>,
+  pub payment_method: Option<String>,
+  pub payment_date: Option<EdiDate>,
+  pub trace_number: Option<String>,
+  pub originator_id: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub payer: Option<Party>,
+  pub payee: Option<Party>
+}
+
+// One reason, amount and quantity triple from CAS.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Adjustment {
+  pub group_code: String,
+  pub reason_code: String,
+  pub amount: Decimal,
+  pub quantity: Option<Decimal>
+}
+
+fn adjustments(segment: &Segment) -> Vec<Adjustment> {
+  let group_code = segment.text(1).unwrap_or_default();
+  (2..segment.fields.len()).step_by(3)
+    .filter_map(|n| Some(Adjustment {
+      group_code: group_code.clone(),
+      reason_code: segment.text(n)?,
+      amount: segment.element_decimal(n + 1).value()?,
+      quantity: segment.element_decimal(n + 2).value()
+    }))
+    .collect()
+}
+
+// The CAS amounts taken off a claim or service line, or `None` when they
+// are too large to add up.
+fn total(adjustments: &[Adjustment]) -> Option<Decimal> {
+  adjustments.iter().try_fold(Decimal::zero(), |sum, a| sum.checked_add(a.amount))
+}
+
+// 2110.  The position is that of its SVC segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ServicePayment {
+  pub procedure: Option<Procedure>,
+  pub charge: Option<Decimal>,
+  pub paid: Option<Decimal>,
+  pub revenue_code: Option<String>,
+  pub units: Option<Decimal>,
+  pub original_procedure: Option<Procedure>,
+  pub adjustments: Vec<Adjustment>,
+  pub dates: Vec<DateReference>,
+  pub references: Vec<Reference>,
+  pub amounts: Vec<Amount>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+// 2100 with its service lines.  The position is that of its CLP segment.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ClaimPayment {
+  pub claim_id: String,
+  pub status_code: Option<String>,
+  pub charge: Option<Decimal>,
+  pub paid: Option<Decimal>,
+  pub patient_responsibility: Option<Decimal>,
+  pub filing_indicator: Option<String>,
+  pub payer_control_number: Option<String>,
+  pub adjustments: Vec<Adjustment>,
+  pub entities: Vec<Entity>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub amounts: Vec<Amount>,
+  pub services: Vec<ServicePayment>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl ClaimPayment {
+  // Claim and service line adjustments together, or `None` if the sum
+  // overflows.
+  pub fn total_adjustments(&self) -> Option<Decimal> {
+    self.services.iter().try_fold(total(&self.adjustments)?, |sum, s| sum.checked_add(total(&s.adjustments)?))
+  }
+}
+
+// One reason and amount pair from PLB.  Positive amounts reduce the payment.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ProviderAdjustment {
+  pub provider_id: String,
+  pub fiscal_period_date: Option<EdiDate>,
+  pub reason_code: String,
+  pub reference: Option<String>,
+  pub amount: Decimal
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RemittanceSummary {
+  pub claims: u64,
+  // The CLP04 payments added up; `None` when they do not fit a Decimal.
+  pub total_paid: Option<Decimal>,
+  pub provider_adjustments: Vec<ProviderAdjustment>
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BalanceErrorKind {
+  // BPR02 against the CLP04 total less the PLB total.
+  Transaction,
+  // CLP03 less all of the claim's CAS amounts against CLP04.
+  Claim,
+  // SVC02 less the line's CAS amounts against SVC03.
+  ServiceLine
+}
+
+// `expected` is the amount that was sent and `computed` the amount it should
+// have been, or `None` if working it out overflowed.  The position is that of
+// the BPR, CLP or SVC segment.
+#[derive(PartialEq, Debug, Clone)]
+pub struct BalanceError {
+  pub kind: BalanceErrorKind,
+  pub claim_id: Option<String>,
+  pub expected: Decimal,
+  pub computed: Option<Decimal>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl fmt::Display for BalanceError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.computed {
+      Some(computed) => write!(f, "line {}, {:?} out of balance: {} sent, {} computed", self.line, self.kind, self.expected, computed),
+      None => write!(f, "line {}, {:?} out of balance: {} sent, the computed amount overflows", self.line, self.kind, self.expected)
+    }
+  }
+}
+
+pub trait RemittanceHandler {
+  fn claim(&mut self, header: &RemittanceHeader, claim: ClaimPayment);
+  fn imbalance(&mut self, header: &RemittanceHeader, error: BalanceError);
+  fn remittance(&mut self, header: &RemittanceHeader, summary: RemittanceSummary);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Loop {
+  Header,
+  Payer,
+  Payee,
+  Claim,
+  Service,
+  Skipped
+}
+
+// Hands over each 2100 claim of an 835 as soon as the next CLP, LX, PLB or
+// the SE is seen, and checks the balancing rules on the way.  Only running
+// totals are kept for the transaction check, which is made at the SE.  Other
+// transaction sets are skipped.
+pub struct RemittanceReader<T: RemittanceHandler> {
+  header: RemittanceHeader,
+  header_position: (u64, u64, u64, u64),
+  claim: Option<ClaimPayment>,
+  claims: u64,
+  total_paid: Option<Decimal>,
+  provider_adjustments: Vec<ProviderAdjustment>,
+  current: Loop,
+  handler: T
+}
+
+pub type RemittanceParser<T> = TransactionParser<RemittanceReader<T>>;
+
+fn position(segment: &Segment) -> (u64, u64, u64, u64) {
+  (segment.segment_index, segment.start_offset, segment.line, segment.column)
+}
+
+impl<T: RemittanceHandler> RemittanceReader<T> {
+  pub fn new(handler: T) -> Self {
+    RemittanceReader {
+      header: RemittanceHeader::default(),
+      header_position: (0, 0, 0, 0),
+      claim: None,
+      claims: 0,
+      total_paid: Some(Decimal::zero()),
+      provider_adjustments: Vec::new(),
+      current: Loop::Skipped,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+
+  fn imbalance(&mut self, kind: BalanceErrorKind, claim_id: Option<String>, expected: Decimal, computed: Option<Decimal>, at: (u64, u64, u64, u64)) {
+    if computed != Some(expected) {
+      self.handler.imbalance(&self.header, BalanceError {
+        kind,
+        claim_id,
+        expected,
+        computed,
+        segment_index: at.0,
+        start_offset: at.1,
+        line: at.2,
+        column: at.3
+      });
+    }
+  }
+
+  fn flush(&mut self) {
+    let claim = match self.claim.take() {
+      None => return,
+      Some(c) => c
+    };
+    for service in claim.services.iter() {
+      if let (Some(charge), Some(paid)) = (service.charge, service.paid) {
+        let at = (service.segment_index, service.start_offset, service.line, service.column);
+        self.imbalance(BalanceErrorKind::ServiceLine, Some(claim.claim_id.clone()), paid, total(&service.adjustments).and_then(|t| charge.checked_sub(t)), at);
+      }
+    }
+    if let (Some(charge), Some(paid)) = (claim.charge, claim.paid) {
+      let at = (claim.segment_index, claim.start_offset, claim.line, claim.column);
+      self.imbalance(BalanceErrorKind::Claim, Some(claim.claim_id.clone()), paid, claim.total_adjustments().and_then(|t| charge.checked_sub(t)), at);
+    }
+    self.claims += 1;
+    self.total_paid = self.total_paid.and_then(|t| t.checked_add(claim.paid.unwrap_or_else(Decimal::zero)));
+    self.handler.claim(&self.header, claim);
+  }
+
+  fn header_segment(&mut self, segment: &Segment) {
+    let header = &mut self.header;
+    let party = match self.current {
+      Loop::Payer => header.payer.as_mut(),
+      Loop::Payee => header.payee.as_mut(),
+      _ => None
+    };
+    if party.map(|p| p.apply(segment)).unwrap_or(false) {
+      return;
+    }
+    match segment.tag.as_slice() {
+      b"BPR" => {
+        header.handling_code = segment.text(1);
+        header.total_payment = segment.element_decimal(2).value();
+        header.credit_debit = segment.text(3);
+        header.payment_method = segment.text(4);
+        header.payment_date = segment.element_date(16).value();
+        self.header_position = position(segment);
+      },
+      b"TRN" => {
+        header.trace_number = segment.text(2);
+        header.originator_id = segment.text(3);
+      },
+      b"REF" => header.references.push(Reference::from_ref(segment)),
+      b"DTM" => header.dates.push(DateReference::from_dtm(segment)),
+      b"N1" => {
+        let party = Party::from_n1(segment);
+        self.current = match party.entity_code.as_str() {
+          "PR" => {
+            header.payer = Some(party);
+            Loop::Payer
+          },
+          "PE" => {
+            header.payee = Some(party);
+            Loop::Payee
+          },
+          _ => Loop::Header
+        };
+      },
+      _ => ()
+    }
+  }
+
+  fn claim_segment(&mut self, segment: &Segment) {
+    let claim = match self.claim.as_mut() {
+      None => return,
+      Some(c) => c
+    };
+    let service = match self.current {
+      Loop::Service => claim.services.last_mut(),
+      _ => None
+    };
+    match (segment.tag.as_slice(), service) {
+      (b"CAS", Some(s)) => s.adjustments.extend(adjustments(segment)),
+      (b"DTM", Some(s)) => s.dates.push(DateReference::from_dtm(segment)),
+      (b"REF", Some(s)) => s.references.push(Reference::from_ref(segment)),
+      (b"AMT", Some(s)) => s.amounts.push(Amount::from_amt(segment)),
+      (b"CAS", None) => claim.adjustments.extend(adjustments(segment)),
+      (b"DTM", None) => claim.dates.push(DateReference::from_dtm(segment)),
+      (b"REF", None) => claim.references.push(Reference::from_ref(segment)),
+      (b"AMT", None) => claim.amounts.push(Amount::from_amt(segment)),
+      (b"NM1", None) => claim.entities.push(Entity::from_nm1(segment)),
+      _ => ()
+    }
+  }
+}
+
+impl<T: RemittanceHandler> TransactionHandler for RemittanceReader<T> {
+  fn transaction_start(&mut self, header: &Segment, _separators: &Separators) {
+    if header.element(1).value() != Some(b"835".as_slice()) {
+      self.current = Loop::Skipped;
+      return;
+    }
+    self.header = RemittanceHeader {
+      control_number: header.text(2).unwrap_or_default(),
+      ..RemittanceHeader::default()
+    };
+    self.header_position = position(header);
+    self.claims = 0;
+    self.total_paid = Some(Decimal::zero());
+    self.provider_adjustments.clear();
+    self.current = Loop::Header;
+  }
+
+  fn segment(&mut self, segment: &Segment, separators: &Separators) {
+    if self.current == Loop::Skipped {
+      return;
+    }
+    match segment.tag.as_slice() {
+      b"CLP" => {
+        self.flush();
+        self.claim = Some(ClaimPayment {
+          claim_id: segment.text(1).unwrap_or_default(),
+          status_code: segment.text(2),
+          charge: segment.element_decimal(3).value(),
+          paid: segment.element_decimal(4).value(),
+          patient_responsibility: segment.element_decimal(5).value(),
+          filing_indicator: segment.text(6),
+          payer_control_number: segment.text(7),
+          segment_index: segment.segment_index,
+          start_offset: segment.start_offset,
+          line: segment.line,
+          column: segment.column,
+          ..ClaimPayment::default()
+        });
+        self.current = Loop::Claim;
+      },
+      b"SVC" => {
+        if let Some(claim) = self.claim.as_mut() {
+          claim.services.push(ServicePayment {
+            procedure: Procedure::from_components(segment.components(1, separators)),
+            charge: segment.element_decimal(2).value(),
+            paid: segment.element_decimal(3).value(),
+            revenue_code: segment.text(4),
+            units: segment.element_decimal(5).value(),
+            original_procedure: Procedure::from_components(segment.components(6, separators)),
+            segment_index: segment.segment_index,
+            start_offset: segment.start_offset,
+            line: segment.line,
+            column: segment.column,
+            ..ServicePayment::default()
+          });
+          self.current = Loop::Service;
+        }
+      },
+      b"LX" => {
+        self.flush();
+        self.current = Loop::Header;
+      },
+      b"PLB" => {
+        self.flush();
+        let provider_id = segment.text(1).unwrap_or_default();
+        let fiscal_period_date = segment.element_date(2).value();
+        for n in (3..segment.fields.len()).step_by(2) {
+          let reason = segment.components(n, separators);
+          if let (Some(reason_code), Some(amount)) = (reason.first().filter(|r| !r.is_empty()), segment.element_decimal(n + 1).value()) {
+            self.provider_adjustments.push(ProviderAdjustment {
+              provider_id: provider_id.clone(),
+              fiscal_period_date,
+              reason_code: reason_code.clone(),
+              reference: reason.get(1).filter(|r| !r.is_empty()).cloned(),
+              amount
+            });
+          }
+        }
+        self.current = Loop::Header;
+      },
+      _ => match self.current {
+        Loop::Claim | Loop::Service => self.claim_segment(segment),
+        _ => self.header_segment(segment)
+      }
+    }
+  }
+
+  fn transaction_end(&mut self, _trailer: Option<&Segment>) {
+    if self.current == Loop::Skipped {
+      return;
+    }
+    self.flush();
+    let adjustments = &self.provider_adjustments;
+    let adjusted = self.total_paid.and_then(|paid| adjustments.iter().try_fold(paid, |sum, a| sum.checked_sub(a.amount)));
+    if let Some(total_payment) = self.header.total_payment {
+      self.imbalance(BalanceErrorKind::Transaction, None, total_payment, adjusted, self.header_position);
+    }
+    let summary = RemittanceSummary {
+      claims: self.claims,
+      total_paid: self.total_paid,
+      provider_adjustments: std::mem::take(&mut self.provider_adjustments)
+    };
+    self.handler.remittance(&self.header, summary);
+    self.current = Loop::Skipped;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{RemittanceHandler, RemittanceHeader, RemittanceReader, RemittanceSummary, ClaimPayment, BalanceError, BalanceErrorKind};
+  use crate::edi_common::testing::read_transactions;
+  use crate::edi_elements::Decimal;
+
+  struct Collector {
+    claims: Vec<ClaimPayment>,
+    errors: Vec<BalanceError>,
+    summaries: Vec<(RemittanceHeader, RemittanceSummary)>
+  }
+
+  impl RemittanceHandler for Collector {
+    fn claim(&mut self, _header: &RemittanceHeader, claim: ClaimPayment) {
+      self.claims.push(claim);
+    }
+
+    fn imbalance(&mut self, _header: &RemittanceHeader, error: BalanceError) {
+      self.errors.push(error);
+    }
+
+    fn remittance(&mut self, header: &RemittanceHeader, summary: RemittanceSummary) {
+      self.summaries.push((header.clone(), summary));
+    }
+  }
+
+  fn read(raw: &str) -> Collector {
+    read_transactions(raw.as_bytes(), RemittanceReader::new(Collector { claims: Vec::new(), errors: Vec::new(), summaries: Vec::new() })).into_handler()
+  }
+
+  const HEADER : &str = "\
+ISA*00*          *00*          *ZZ*PAYER          *ZZ*PROVIDER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HP*PAYER*PROVIDER*20230101*1200*1*X*005010X221A1~
+ST*835*0001~
+";
+
+  #[test]
+  fn reads_balanced_remittance() {
+    let raw = format!("{}{}", HEADER, "\
+BPR*I*132*C*ACH*CCP*01*999999999*DA*123456*1512345678**01*999988880*DA*98765*20230110~
+TRN*1*12345*1512345678~
+DTM*405*20230108~
+N1*PR*INSURANCE COMPANY~
+N3*1 PAYER WAY~
+N4*CITY*ST*12345~
+N1*PE*PROVIDER*XX*1234567890~
+LX*1~
+CLP*7722337*1*226*132*10*12*119932404007801~
+NM1*QC*1*DOE*JOHN~
+DTM*232*20230101~
+SVC*HC:99211*80*72**1~
+DTM*472*20230101~
+CAS*PR*1*8~
+SVC*HC:93000:25*146*60**1~
+CAS*CO*45*84*1*97*2~
+PLB*1234567890*20231231*WO:REF1*-10*72*10~
+SE*17*0001~
+GE*1*1~
+IEA*1*000000001~
+");
+    let collected = read(&raw);
+    assert_eq!(collected.errors, vec![]);
+    assert_eq!(collected.claims.len(), 1);
+    let claim = &collected.claims[0];
+    assert_eq!(claim.paid, Some(Decimal::new(132, 0)));
+    assert_eq!(claim.entities[0].name.last_name, "DOE");
+    assert_eq!(claim.services.len(), 2);
+    assert_eq!(claim.services[1].procedure.as_ref().map(|p| p.modifiers.clone()), Some(vec![String::from("25")]));
+    assert_eq!(claim.services[1].adjustments.len(), 2);
+    assert_eq!(claim.total_adjustments(), Some(Decimal::new(94, 0)));
+    let (header, summary) = &collected.summaries[0];
+    assert_eq!(header.trace_number.as_deref(), Some("12345"));
+    assert_eq!(header.payer.as_ref().and_then(|p| p.address.city.clone()), Some(String::from("CITY")));
+    assert_eq!(header.payee.as_ref().and_then(|p| p.id.clone()), Some(String::from("1234567890")));
+    assert_eq!(summary.provider_adjustments.len(), 2);
+    assert_eq!(summary.provider_adjustments[0].reference.as_deref(), Some("REF1"));
+  }
+
+  #[test]
+  fn reports_imbalances() {
+    let raw = format!("{}{}", HEADER, "\
+BPR*I*150*C*ACH~
+TRN*1*12345*1512345678~
+N1*PR*INSURANCE COMPANY~
+N1*PE*PROVIDER~
+LX*1~
+CLP*A1*1*100*90~
+CAS*CO*45*5~
+SVC*HC:99211*100*90~
+CAS*CO*45*10~
+CLP*A2*1*50*50~
+SE*11*0001~
+GE*1*1~
+IEA*1*000000001~
+");
+    let collected = read(&raw);
+    let kinds : Vec<(BalanceErrorKind, Option<String>, u64)> = collected.errors.iter().map(|e| (e.kind, e.claim_id.clone(), e.line)).collect();
+    assert_eq!(kinds, vec![
+      (BalanceErrorKind::Claim, Some(String::from("A1")), 9),
+      (BalanceErrorKind::Transaction, None, 4)
+    ]);
+    assert_eq!(collected.errors[0].computed, Some(Decimal::new(85, 0)));
+    assert_eq!(collected.errors[1].to_string(), "line 4, Transaction out of balance: 150 sent, 140 computed");
+    assert_eq!(collected.summaries[0].1.claims, 2);
+  }
+
+  #[test]
+  fn reports_overflowing_amounts_as_imbalances() {
+    let big = "99999999999999999999999999999999999999";
+    let raw = format!("{}BPR*I*10*C*ACH~\nLX*1~\nCLP*A1*1*10*10~\nCAS*CO*45*{}**97*{}~\nSE*6*0001~\nGE*1*1~\nIEA*1*000000001~\n", HEADER, big, big);
+    let collected = read(&raw);
+    assert_eq!(collected.claims[0].total_adjustments(), None);
+    assert_eq!(collected.errors.len(), 1);
+    assert_eq!((collected.errors[0].kind, collected.errors[0].computed), (BalanceErrorKind::Claim, None));
+    assert_eq!(collected.errors[0].to_string(), "line 6, Claim out of balance: 10 sent, the computed amount overflows");
+    assert_eq!(collected.summaries[0].1.total_paid, Some(Decimal::new(10, 0)));
+  }
+}
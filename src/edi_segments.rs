@@ -1,66 +1,157 @@
 use std::io::Error;
 use std::io::Read;
-use crate::edi_constants::SEGMENT_STARTERS;
+use crate::edi_recovery::{RecoveryState, recovering_next};
+use crate::edi_tag_rules::SegmentTagRule;
+use crate::edi_limits::{ParserLimits, LimitState};
 
-struct ParserConfig {
-    element_delimiter: Vec<u8>,
+pub(crate) struct ParserConfig {
+    pub(crate) element_delimiter: Vec<u8>,
 //    sub_element_delimiter: Vec<u8>,
-    segment_delimiter: Vec<u8>
+    pub(crate) segment_delimiter: Vec<u8>,
+    pub(crate) tag_rule: SegmentTagRule
 }
 
 #[allow(clippy::upper_case_acronyms)]
-enum PState {
+pub(crate) enum PState {
     InField,
+    // Part way through a multi-byte segment terminator.
     InSegTerm,
+    // After a terminator, collecting bytes until the tag rule finds a tag.
+    InTagCandidate,
     EOF,
     Errored
 }
 
-struct ParserState {
-    byte_index: u64,
-    start_of_last_segment: u64,
-    state: PState,
-    segment_index: u64,
-    current_string: Vec<u8>,
-    current_field: Vec<u8>,
-    current_segment: Vec<Vec<u8>>
+pub(crate) struct ParserState {
+    pub(crate) byte_index: u64,
+    pub(crate) start_of_last_segment: u64,
+    pub(crate) state: PState,
+    pub(crate) segment_index: u64,
+    pub(crate) current_string: Vec<u8>,
+    pub(crate) current_field: Vec<u8>,
+    pub(crate) current_segment: Vec<Vec<u8>>,
+    pub(crate) terminator_matched: usize,
+    pub(crate) pending: Vec<u8>,
+    pub(crate) stray: Vec<u8>,
+    // Lines and columns count from 1.  `line` and `column` are those of the
+    // next byte to be read.
+    pub(crate) line: u64,
+    pub(crate) column: u64,
+    pub(crate) start_line: u64,
+    pub(crate) start_column: u64,
+    pub(crate) pending_line: u64,
+    pub(crate) pending_column: u64
 }
 
 pub struct ParserIterator<'a, T: Read> {
   io_source: &'a mut T,
   parser_state: ParserState,
-  parser_config: ParserConfig
+  parser_config: ParserConfig,
+  recovery: Option<RecoveryState>,
+  limits: Option<LimitState>
 }
 
-type ParserOutput = Option<Segment>;
+pub(crate) type ParserOutput = Option<Segment>;
 
+#[derive(Clone)]
 pub struct Segment {
   pub tag: Vec<u8>,
   pub fields: Vec<Vec<u8>>,
   pub start_offset: u64,
   pub end_offset: u64,
   pub segment_index: u64,
-  pub raw: Vec<u8>
+  pub raw: Vec<u8>,
+  // Bytes after the terminator that did not belong to any segment.
+  pub stray: Vec<u8>,
+  // Where the segment starts, counting from 1.
+  pub line: u64,
+  pub column: u64
 }
 
-pub fn create_segment_iterator<T: Read>(ioish: &mut T, element_delimiter: Vec<u8>, segment_delimiter: Vec<u8>) -> ParserIterator<T> {
-  let pc = ParserConfig {
+pub fn create_segment_iterator<T: Read>(ioish: &mut T, element_delimiter: Vec<u8>, segment_delimiter: Vec<u8>) -> ParserIterator<'_, T> {
+  ParserIterator {
+    io_source: ioish,
+    parser_config: new_parser_config(element_delimiter, segment_delimiter),
+    parser_state: new_parser_state(),
+    recovery: None,
+    limits: None
+  }
+}
+
+pub(crate) fn restore_segment_iterator<T: Read>(ioish: &mut T, parser_config: ParserConfig, parser_state: ParserState, recovery: Option<RecoveryState>, limits: Option<LimitState>) -> ParserIterator<'_, T> {
+  ParserIterator {
+    io_source: ioish,
+    parser_config,
+    parser_state,
+    recovery,
+    limits
+  }
+}
+
+impl<'a, T: Read> ParserIterator<'a, T> {
+  // Skip malformed segments and resynchronize after read errors instead of
+  // stopping.  Skipped regions are reported as errors carrying a
+  // `RecoveryDiagnostic`.
+  pub fn with_recovery(mut self) -> Self {
+    self.recovery = Some(RecoveryState::new());
+    self
+  }
+
+  // Stop with a `LimitExceeded` error rather than buffer or accept more than
+  // `limits` allows.  Limit errors end iteration even in recovery mode.
+  pub fn with_limits(mut self, limits: ParserLimits) -> Self {
+    self.limits = Some(LimitState::new(limits));
+    self
+  }
+
+  pub fn with_tag_rule(mut self, tag_rule: SegmentTagRule) -> Self {
+    self.parser_config.tag_rule = tag_rule;
+    self
+  }
+
+  pub(crate) fn parser_config(&self) -> &ParserConfig {
+    &self.parser_config
+  }
+
+  pub(crate) fn parser_state(&self) -> &ParserState {
+    &self.parser_state
+  }
+
+  pub(crate) fn recovery(&self) -> Option<&RecoveryState> {
+    self.recovery.as_ref()
+  }
+
+  pub(crate) fn limits(&self) -> Option<&LimitState> {
+    self.limits.as_ref()
+  }
+}
+
+pub(crate) fn new_parser_config(element_delimiter: Vec<u8>, segment_delimiter: Vec<u8>) -> ParserConfig {
+  ParserConfig {
     element_delimiter,
-    segment_delimiter
-  };
-  let ps = ParserState {
+    segment_delimiter,
+    tag_rule: SegmentTagRule::default()
+  }
+}
+
+pub(crate) fn new_parser_state() -> ParserState {
+  ParserState {
     byte_index: 0,
     start_of_last_segment: 0,
     segment_index: 0,
     state: PState::InField,
     current_string: Vec::new(),
     current_field: Vec::new(),
-    current_segment: Vec::new()
-  };
-  ParserIterator {
-    io_source: ioish,
-    parser_config: pc,
-    parser_state: ps
+    current_segment: Vec::new(),
+    terminator_matched: 0,
+    pending: Vec::new(),
+    stray: Vec::new(),
+    line: 1,
+    column: 1,
+    start_line: 1,
+    start_column: 1,
+    pending_line: 1,
+    pending_column: 1
   }
 }
 
@@ -73,25 +164,60 @@ impl<'a, T: Read> Iterator for ParserIterator<'a, T> {
 }
 
 fn parser_next<T: Read>(pi: &mut ParserIterator<T>) -> Option<Result<Segment, Error>> {
-  match pi.parser_state.state {
-    PState::Errored => None,
-    PState::EOF => None,
-    _ => {
-      let res = step(&pi.parser_config, &mut pi.parser_state, &mut pi.io_source);
-      match res {
-        Ok(None) => parser_next(pi),
-        Ok(Some(res)) => Some(Ok(res)),
-        Err(e) => {
-          pi.parser_state.state = PState::Errored;
-          Some(Err(e))
+  if let Some(rs) = &mut pi.recovery {
+    return recovering_next(&pi.parser_config, &mut pi.parser_state, rs, &mut pi.limits, &mut pi.io_source);
+  }
+  loop {
+    match pi.parser_state.state {
+      PState::Errored => return None,
+      PState::EOF => return None,
+      _ => {
+        let res = step(&pi.parser_config, &mut pi.parser_state, &mut pi.io_source)
+          .map_err(|e| read_error(&pi.parser_state, e))
+          .and_then(|output| check_limits(&mut pi.limits, &pi.parser_state, output));
+        match res {
+          Ok(None) => (),
+          Ok(Some(res)) => return Some(Ok(res)),
+          Err(e) => {
+            pi.parser_state.state = PState::Errored;
+            return Some(Err(e))
+          }
         }
       }
     }
   }
 }
 
-fn build_segment(fields: Vec<Vec<u8>>, raw: Vec<u8>, start_index: u64, end_index: u64, segment_index: u64) -> Segment {
-  let tag : Vec<u8> = match fields.get(0) {
+// A read error keeps its kind but says where the segment being read began.
+fn read_error(ps: &ParserState, error: Error) -> Error {
+  Error::new(error.kind(), format!("{} in the segment starting at line {}, column {}", error, ps.start_line, ps.start_column))
+}
+
+pub(crate) fn check_limits(limits: &mut Option<LimitState>, ps: &ParserState, output: ParserOutput) -> Result<ParserOutput, Error> {
+  match limits {
+    None => Ok(output),
+    Some(ls) => ls.check(ps, output)
+  }
+}
+
+// Moves a position from one segment's start to be relative to `base`, for
+// segments read from part of a file.
+pub(crate) fn rebase_position(segment: &mut Segment, base: (u64, u64)) {
+  if segment.line == 1 {
+    segment.column += base.1 - 1;
+  }
+  segment.line += base.0 - 1;
+}
+
+pub(crate) fn advance_position(position: (u64, u64), bytes: &[u8]) -> (u64, u64) {
+  bytes.iter().fold(position, |(line, column), b| match b {
+    b'\n' => (line + 1, 1),
+    _ => (line, column + 1)
+  })
+}
+
+fn build_segment(fields: Vec<Vec<u8>>, raw: Vec<u8>, start_index: u64, end_index: u64, segment_index: u64, stray: Vec<u8>, position: (u64, u64)) -> Segment {
+  let tag : Vec<u8> = match fields.first() {
     None => Vec::new(),
     Some(x) => x.clone()
   };
@@ -101,79 +227,175 @@ fn build_segment(fields: Vec<Vec<u8>>, raw: Vec<u8>, start_index: u64, end_index
     start_offset: start_index,
     end_offset: end_index,
     segment_index,
-    raw
+    raw,
+    stray,
+    line: position.0,
+    column: position.1
   }
 }
 
 fn step<T: Read>(pc: &ParserConfig, ps: &mut ParserState, ioish: &mut T) -> Result<ParserOutput, Error> {
   let mut buff = [0; 1];
-  let current_index = ps.byte_index;
-  ps.byte_index += 1;
   match ioish.read(&mut buff) {
-    Ok(size) if size == 1 => (),
-    Ok(_) => {
+    Ok(1) => Ok(step_byte(pc, ps, buff[0])),
+    Ok(_) => Ok(step_eof(pc, ps)),
+    Err(e) => Err(e)
+  }
+}
+
+// Hands back the segment that has been terminated, ending at `end_index`,
+// along with any stray bytes seen after it.
+fn finish_segment(ps: &mut ParserState, end_index: u64) -> Segment {
+  build_segment(
+    std::mem::take(&mut ps.current_segment),
+    std::mem::take(&mut ps.current_string),
+    ps.start_of_last_segment,
+    end_index,
+    ps.segment_index,
+    std::mem::take(&mut ps.stray),
+    (ps.start_line, ps.start_column))
+}
+
+// Starts a new segment whose tag so far is `pending[k..]`, with the last
+// pending byte just before `end_index`.
+fn begin_segment(ps: &mut ParserState, k: usize, end_index: u64) {
+  let tag = ps.pending[k..].to_vec();
+  let (line, column) = match ps.pending.is_empty() {
+    true => (ps.line, ps.column),
+    false => advance_position((ps.pending_line, ps.pending_column), &ps.pending[..k])
+  };
+  ps.start_of_last_segment = end_index - tag.len() as u64;
+  ps.start_line = line;
+  ps.start_column = column;
+  ps.current_string = tag.clone();
+  ps.current_field = tag;
+  ps.current_segment.clear();
+  ps.pending.clear();
+  ps.segment_index += 1;
+  ps.terminator_matched = 0;
+  ps.state = PState::InField;
+}
+
+// The bytes of a candidate region (stray and pending) come straight after the
+// terminator, so the terminator ends just before them.
+fn terminator_end(ps: &ParserState, current_index: u64) -> u64 {
+  current_index - 1 - ps.stray.len() as u64 - ps.pending.len() as u64
+}
+
+pub(crate) fn step_eof(pc: &ParserConfig, ps: &mut ParserState) -> ParserOutput {
+  let current_index = ps.byte_index;
+  match ps.state {
+    PState::InField => {
+      let f = std::mem::take(&mut ps.current_field);
+      ps.current_segment.push(f);
       ps.state = PState::EOF;
-      match ps.state {
-        PState::InSegTerm => (),
-        _ => {
-          ps.current_segment.push(ps.current_field.clone());
-        }
+      Some(finish_segment(ps, current_index))
+    },
+    PState::InSegTerm | PState::InTagCandidate => {
+      // The last field was closed by the terminator, so no empty field is
+      // added for the end of the input.  An unterminated tag at the very end
+      // is a segment of its own, which the next step will hand back.
+      if let Some(k) = pc.tag_rule.split(&ps.pending) {
+        let end = terminator_end(ps, current_index);
+        ps.stray.extend_from_slice(&ps.pending[..k]);
+        let seg = finish_segment(ps, end);
+        begin_segment(ps, k, current_index);
+        return Some(seg);
       }
-      let s = ps.current_segment.clone();
-      let seg = build_segment(s, ps.current_string.clone(), ps.start_of_last_segment, current_index, ps.segment_index);
-      return Ok(Some(seg))
+      let end = terminator_end(ps, current_index);
+      let mut pending = std::mem::take(&mut ps.pending);
+      ps.stray.append(&mut pending);
+      ps.state = PState::EOF;
+      Some(finish_segment(ps, end))
+    },
+    _ => None
+  }
+}
+
+// Used when the input fails part way through.  A segment that has already
+// been terminated is handed back, and anything read after it becomes the
+// start of the next segment.
+pub(crate) fn step_interrupted(ps: &mut ParserState) -> ParserOutput {
+  match ps.state {
+    PState::InSegTerm | PState::InTagCandidate => {
+      let current_index = ps.byte_index;
+      let seg = finish_segment(ps, terminator_end(ps, current_index));
+      begin_segment(ps, 0, current_index);
+      Some(seg)
     },
-    Err(e) => {
-      return Err(e)
+    _ => None
+  }
+}
+
+pub(crate) fn step_byte(pc: &ParserConfig, ps: &mut ParserState, byte: u8) -> ParserOutput {
+  let current_index = ps.byte_index;
+  let position = (ps.line, ps.column);
+  ps.byte_index += 1;
+  (ps.line, ps.column) = advance_position(position, &[byte]);
+  if let PState::InSegTerm = ps.state {
+    if byte == pc.segment_delimiter[ps.terminator_matched] {
+      ps.current_string.push(byte);
+      ps.terminator_matched += 1;
+      if ps.terminator_matched == pc.segment_delimiter.len() {
+        ps.state = PState::InTagCandidate;
+      }
+      return None;
     }
-  };
+    // A short terminator; whatever follows is a candidate for the next tag.
+    ps.terminator_matched = pc.segment_delimiter.len();
+    ps.state = PState::InTagCandidate;
+  }
+  match ps.state {
+    PState::InTagCandidate => step_candidate(pc, ps, byte, current_index, position),
+    _ => {
+      step_field(pc, ps, byte);
+      None
+    }
+  }
+}
 
+fn step_field(pc: &ParserConfig, ps: &mut ParserState, byte: u8) {
   let ed = pc.element_delimiter[0];
   // let sed = pc.sub_element_delimiter[0];
   let sd = pc.segment_delimiter[0];
-  match buff[0] {
+  ps.current_string.push(byte);
+  match byte {
     x if x == ed => {
-        ps.state = PState::InField;
-        ps.current_string.push(x);
-        let f = ps.current_field.clone();
-        ps.current_field.clear();
-        ps.current_segment.push(f);
-        Ok(None)
+      let f = std::mem::take(&mut ps.current_field);
+      ps.current_segment.push(f);
     },
     z if z == sd => {
-      ps.state = PState::InSegTerm;
-      ps.current_string.push(z);
-      let f = ps.current_field.clone();
+      let f = std::mem::take(&mut ps.current_field);
       ps.current_segment.push(f);
-      Ok(None)
+      ps.terminator_matched = 1;
+      ps.state = if pc.segment_delimiter.len() == 1 { PState::InTagCandidate } else { PState::InSegTerm };
     },
-    a => {
-        match ps.state {
-          PState::InSegTerm if SEGMENT_STARTERS.contains(&a) => {
-                let ns = ps.current_segment.clone();
-                let seg = build_segment(ns, ps.current_string.clone(), ps.start_of_last_segment, current_index - 1, ps.segment_index);
-                ps.current_string.clear();
-                ps.current_field.clear();
-                ps.current_segment.clear();
-                ps.current_string.push(a);
-                ps.current_field.push(a);
-                ps.state = PState::InField;
-                ps.start_of_last_segment = current_index;
-                ps.segment_index +=  1;
-                Ok(Some(seg))
-          },
-          PState::InSegTerm => {
-            ps.current_string.push(a);
-            Ok(None)
-          },
-          _ => {
-            ps.current_string.push(a);
-            ps.current_field.push(a);
-            Ok(
-              None
-            )
-          }
-        }
+    a => ps.current_field.push(a)
+  }
+}
+
+fn step_candidate(pc: &ParserConfig, ps: &mut ParserState, byte: u8, current_index: u64, position: (u64, u64)) -> ParserOutput {
+  if byte != pc.element_delimiter[0] && byte != pc.segment_delimiter[0] {
+    if ps.pending.is_empty() {
+      (ps.pending_line, ps.pending_column) = position;
+    }
+    ps.pending.push(byte);
+    return None;
+  }
+  match pc.tag_rule.split(&ps.pending) {
+    None => {
+      let mut pending = std::mem::take(&mut ps.pending);
+      ps.stray.append(&mut pending);
+      ps.stray.push(byte);
+      None
+    },
+    Some(k) => {
+      let end = terminator_end(ps, current_index);
+      ps.stray.extend_from_slice(&ps.pending[..k]);
+      let seg = finish_segment(ps, end);
+      begin_segment(ps, k, current_index);
+      step_field(pc, ps, byte);
+      Some(seg)
     }
   }
 }
@@ -185,6 +407,7 @@ mod test {
     use super::ParserIterator;
     use super::PState;
     use super::step;
+    use crate::edi_tag_rules::SegmentTagRule;
     use std::io::Cursor;
 
     fn vectorize_string_for_compare(vec_string : &str) -> Vec<u8> {
@@ -197,7 +420,8 @@ mod test {
       let config = ParserConfig {
         segment_delimiter: "~\n".bytes().collect(),
         // sub_element_delimiter: "^".bytes().collect(),
-        element_delimiter: "*".bytes().collect()
+        element_delimiter: "*".bytes().collect(),
+        tag_rule: SegmentTagRule::default()
       };
       let mut start = ParserState {
         byte_index: 0,
@@ -206,7 +430,16 @@ mod test {
         state: PState::InField,
         current_string: Vec::new(),
         current_field: Vec::new(),
-        current_segment: Vec::new()
+        current_segment: Vec::new(),
+        terminator_matched: 0,
+        pending: Vec::new(),
+        stray: Vec::new(),
+        line: 1,
+        column: 1,
+        start_line: 1,
+        start_column: 1,
+        pending_line: 1,
+        pending_column: 1
       };
       let expected_vec =  
         Vec::from([vectorize_string_for_compare("ISA")]);
@@ -234,7 +467,8 @@ mod test {
       let config = ParserConfig {
         segment_delimiter: "~\n".bytes().collect(),
         // sub_element_delimiter: "^".bytes().collect(),
-        element_delimiter: "*".bytes().collect()
+        element_delimiter: "*".bytes().collect(),
+        tag_rule: SegmentTagRule::default()
       };
       let start = ParserState {
         byte_index: 0,
@@ -243,12 +477,23 @@ mod test {
         state: PState::InField,
         current_string: Vec::new(),
         current_field: Vec::new(),
-        current_segment: Vec::new()
+        current_segment: Vec::new(),
+        terminator_matched: 0,
+        pending: Vec::new(),
+        stray: Vec::new(),
+        line: 1,
+        column: 1,
+        start_line: 1,
+        start_column: 1,
+        pending_line: 1,
+        pending_column: 1
       };
       let mut pi = ParserIterator {
         parser_config: config,
         parser_state: start,
-        io_source: &mut ioish
+        io_source: &mut ioish,
+        recovery: None,
+        limits: None
       };
       let result = pi.next();
       match result {
@@ -270,4 +515,48 @@ mod test {
         }
       }
     }
+
+    struct FailingReader {
+      data: Cursor<Vec<u8>>,
+      fail_at: u64
+    }
+
+    impl std::io::Read for FailingReader {
+      fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.data.position() >= self.fail_at {
+          return Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset"));
+        }
+        self.data.read(buf)
+      }
+    }
+
+    #[test]
+    fn read_error_position_test() {
+      let mut ioish = FailingReader { data: Cursor::new(b"ISA*A~\nGS*B*C~\nST*1".to_vec()), fail_at: 13 };
+      let pi = super::create_segment_iterator(&mut ioish, "*".bytes().collect(), "~\n".bytes().collect());
+      let results : Vec<_> = pi.collect();
+      assert_eq!(results.len(), 2);
+      assert!(results[0].is_ok());
+      match &results[1] {
+        Ok(_) => panic!("Wrong thing"),
+        Err(e) => {
+          assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset);
+          assert_eq!(e.to_string(), "connection reset in the segment starting at line 2, column 1");
+        }
+      }
+    }
+
+    #[test]
+    fn terminated_last_segment_test() {
+      let mut ioish = Cursor::new("ISA*ABCD~\n".as_bytes());
+      let pi = super::create_segment_iterator(&mut ioish, "*".bytes().collect(), "~\n".bytes().collect());
+      let segments : Vec<_> = pi.map(|r| r.unwrap()).collect();
+      assert_eq!(segments.len(), 1);
+      assert_eq!(segments[0].fields, Vec::from([
+        vectorize_string_for_compare("ISA"),
+        vectorize_string_for_compare("ABCD")
+      ]));
+      assert_eq!(segments[0].raw, b"ISA*ABCD~\n".to_vec());
+      assert_eq!(segments[0].end_offset, 9);
+    }
 }
@@ -0,0 +1,106 @@
+use crate::edi_delimiters::{DelimiterResult, detect_delimiters_in_prefix, DELIMITER_PREFIX_LENGTH};
+use crate::edi_segments::{ParserIterator, create_segment_iterator};
+use crate::edi_tag_rules::SegmentTagRule;
+use std::io::{Read, BufReader, Chain, Cursor, Error};
+
+// A source that can not seek.  Its start is buffered to detect the delimiters
+// and is then read again ahead of the rest of the source.  The rest is
+// buffered too, since the tokenizer reads a byte at a time.
+pub struct EdiSource<R: Read> {
+  inner: Chain<Cursor<Vec<u8>>, BufReader<R>>,
+  element_delimiter: Vec<u8>,
+  segment_delimiter: Vec<u8>
+}
+
+pub fn open_edi_source<R: Read>(source: R) -> Result<EdiSource<R>, Error> {
+  let mut source = BufReader::new(source);
+  let prefix = read_prefix(&mut source, DELIMITER_PREFIX_LENGTH)?;
+  let delimiters = match detect_delimiters_in_prefix(&prefix, &SegmentTagRule::default()) {
+    DelimiterResult::DelimiterReadError(e) => return Err(e),
+    DelimiterResult::DelimitersFound(d) => d
+  };
+  Ok(EdiSource {
+    inner: Cursor::new(prefix).chain(source),
+    element_delimiter: delimiters.element_delimiter,
+    segment_delimiter: delimiters.segment_delimiter
+  })
+}
+
+pub(crate) fn read_prefix<R: Read>(source: &mut R, length: usize) -> Result<Vec<u8>, Error> {
+  let mut prefix = Vec::with_capacity(length);
+  source.by_ref().take(length as u64).read_to_end(&mut prefix)?;
+  Ok(prefix)
+}
+
+impl<R: Read> EdiSource<R> {
+  pub fn streamer(&mut self) -> ParserIterator<'_, Self> {
+    let element_delimiter = self.element_delimiter.clone();
+    let segment_delimiter = self.segment_delimiter.clone();
+    create_segment_iterator(self, element_delimiter, segment_delimiter)
+  }
+}
+
+impl<R: Read> Read for EdiSource<R> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+    self.inner.read(buf)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::open_edi_source;
+  use std::io::Read;
+
+  // Reads one byte at a time and can not seek, like a pipe.
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+      match (self.0.split_first(), buf.is_empty()) {
+        (Some((b, rest)), false) => {
+          buf[0] = *b;
+          self.0 = rest;
+          Ok(1)
+        },
+        _ => Ok(0)
+      }
+    }
+  }
+
+  // Counts the reads that reach the underlying source.
+  struct Counting<'a> {
+    data: &'a [u8],
+    reads: usize
+  }
+
+  impl<'a> Read for Counting<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+      self.reads += 1;
+      self.data.read(buf)
+    }
+  }
+
+  #[test]
+  fn buffers_reads_from_the_source() {
+    let raw = "ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~\r\nGS*HC*S*R*20230101*1200*1*X*005010X222A1~\r\nGE*0*1~\r\nIEA*1*000000001~\r\n";
+    let mut counting = Counting { data: raw.as_bytes(), reads: 0 };
+    let mut source = match open_edi_source(&mut counting) {
+      Ok(s) => s,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    assert_eq!(source.streamer().count(), 4);
+    drop(source);
+    assert!(counting.reads < 10, "{} reads for {} bytes", counting.reads, raw.len());
+  }
+
+  #[test]
+  fn detects_without_seeking() {
+    let raw = "ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~\r\nGS*HC*S*R*20230101*1200*1*X*005010X222A1~\r\nGE*0*1~\r\nIEA*1*000000001~\r\n";
+    let mut source = match open_edi_source(Trickle(raw.as_bytes())) {
+      Ok(s) => s,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let tags : Vec<Vec<u8>> = source.streamer().map(|r| r.unwrap().tag).collect();
+    assert_eq!(tags, vec![b"ISA".to_vec(), b"GS".to_vec(), b"GE".to_vec(), b"IEA".to_vec()]);
+  }
+}
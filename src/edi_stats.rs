@@ -0,0 +1,378 @@
+use crate::edi_parsers::StreamParser;
+use crate::edi_segments::Segment;
+use crate::edi_envelope::EnvelopeState;
+use std::collections::BTreeMap;
+use std::io::{Write, Error};
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TagStats {
+  pub tag: String,
+  pub count: u64,
+  // How many of the segments had element n + 1 filled in.
+  pub filled: Vec<u64>
+}
+
+impl TagStats {
+  pub fn fill_rate(&self, element: usize) -> f64 {
+    match self.filled.get(element.wrapping_sub(1)) {
+      Some(f) if self.count > 0 => *f as f64 / self.count as f64,
+      _ => 0.0
+    }
+  }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TransactionSize {
+  pub set_id: String,
+  pub control_number: String,
+  // ST through SE.
+  pub segments: u64,
+  pub bytes: u64,
+  pub start_offset: u64,
+  pub line: u64
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TradingPartners {
+  // Qualifier and id, such as ZZ:SENDER.
+  pub sender: String,
+  pub receiver: String,
+  pub interchanges: u64
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct StatsReport {
+  pub segments: u64,
+  pub bytes: u64,
+  pub interchanges: u64,
+  pub functional_groups: u64,
+  pub transactions: u64,
+  // Most frequent first.
+  pub tags: Vec<TagStats>,
+  pub transaction_sets: Vec<(String, u64)>,
+  pub partners: Vec<TradingPartners>,
+  // ISA12 and GS08.
+  pub interchange_versions: Vec<(String, u64)>,
+  pub group_versions: Vec<(String, u64)>,
+  pub min_transaction: Option<(u64, u64)>,
+  pub max_transaction: Option<(u64, u64)>,
+  pub total_transaction: (u64, u64),
+  // Most bytes first.
+  pub largest: Vec<TransactionSize>
+}
+
+fn json_string(s: &str) -> String {
+  let mut out = String::from("\"");
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c)
+    }
+  }
+  out.push('"');
+  out
+}
+
+fn json_counts(counts: &[(String, u64)]) -> String {
+  let items : Vec<String> = counts.iter().map(|(k, v)| format!("{}:{}", json_string(k), v)).collect();
+  format!("{{{}}}", items.join(","))
+}
+
+impl StatsReport {
+  // Mean segments and bytes per transaction.
+  pub fn mean_transaction(&self) -> Option<(f64, f64)> {
+    let n = self.transactions as f64;
+    if self.transactions == 0 {
+      return None;
+    }
+    Some((self.total_transaction.0 as f64 / n, self.total_transaction.1 as f64 / n))
+  }
+
+  pub fn write_json<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+    let pair = |p: Option<(u64, u64)>| p.map(|(s, b)| format!("{{\"segments\":{},\"bytes\":{}}}", s, b)).unwrap_or_else(|| String::from("null"));
+    let mean = self.mean_transaction().map(|(s, b)| format!("{{\"segments\":{:.1},\"bytes\":{:.1}}}", s, b)).unwrap_or_else(|| String::from("null"));
+    let tags : Vec<String> = self.tags.iter().map(|t| {
+      let rates : Vec<String> = (1..=t.filled.len()).map(|n| format!("{:.3}", t.fill_rate(n))).collect();
+      format!("{{\"tag\":{},\"count\":{},\"fill_rates\":[{}]}}", json_string(&t.tag), t.count, rates.join(","))
+    }).collect();
+    let partners : Vec<String> = self.partners.iter().map(|p| {
+      format!("{{\"sender\":{},\"receiver\":{},\"interchanges\":{}}}", json_string(&p.sender), json_string(&p.receiver), p.interchanges)
+    }).collect();
+    let largest : Vec<String> = self.largest.iter().map(|t| {
+      format!("{{\"set_id\":{},\"control_number\":{},\"segments\":{},\"bytes\":{},\"start_offset\":{},\"line\":{}}}",
+        json_string(&t.set_id), json_string(&t.control_number), t.segments, t.bytes, t.start_offset, t.line)
+    }).collect();
+    writeln!(out, "{{\"segments\":{},\"bytes\":{},\"interchanges\":{},\"functional_groups\":{},\"transactions\":{},\
+\"transaction_sets\":{},\"segment_tags\":[{}],\"trading_partners\":[{}],\
+\"versions\":{{\"interchange\":{},\"functional_group\":{}}},\
+\"transaction_sizes\":{{\"min\":{},\"max\":{},\"mean\":{}}},\"largest_transactions\":[{}]}}",
+      self.segments, self.bytes, self.interchanges, self.functional_groups, self.transactions,
+      json_counts(&self.transaction_sets), tags.join(","), partners.join(","),
+      json_counts(&self.interchange_versions), json_counts(&self.group_versions),
+      pair(self.min_transaction), pair(self.max_transaction), mean, largest.join(","))
+  }
+
+  pub fn write_text<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+    writeln!(out, "segments\t{}", self.segments)?;
+    writeln!(out, "bytes\t{}", self.bytes)?;
+    writeln!(out, "interchanges\t{}", self.interchanges)?;
+    writeln!(out, "functional groups\t{}", self.functional_groups)?;
+    writeln!(out, "transactions\t{}", self.transactions)?;
+    if let (Some(min), Some(max), Some(mean)) = (self.min_transaction, self.max_transaction, self.mean_transaction()) {
+      writeln!(out, "transaction segments\tmin {}\tmax {}\tmean {:.1}", min.0, max.0, mean.0)?;
+      writeln!(out, "transaction bytes\tmin {}\tmax {}\tmean {:.1}", min.1, max.1, mean.1)?;
+    }
+    writeln!(out, "\ntransaction sets")?;
+    for (set_id, count) in self.transaction_sets.iter() {
+      writeln!(out, "  {}\t{}", set_id, count)?;
+    }
+    writeln!(out, "\nversions")?;
+    for (version, count) in self.interchange_versions.iter().chain(self.group_versions.iter()) {
+      writeln!(out, "  {}\t{}", version, count)?;
+    }
+    writeln!(out, "\ntrading partners")?;
+    for p in self.partners.iter() {
+      writeln!(out, "  {} -> {}\t{}", p.sender, p.receiver, p.interchanges)?;
+    }
+    writeln!(out, "\nsegment tags")?;
+    for t in self.tags.iter() {
+      let rates : Vec<String> = (1..=t.filled.len()).map(|n| format!("{:02}:{:.0}%", n, t.fill_rate(n) * 100.0)).collect();
+      writeln!(out, "  {}\t{}\t{}", t.tag, t.count, rates.join(" "))?;
+    }
+    writeln!(out, "\nlargest transactions")?;
+    for t in self.largest.iter() {
+      writeln!(out, "  {} {}\tline {}\t{} segments\t{} bytes", t.set_id, t.control_number, t.line, t.segments, t.bytes)?;
+    }
+    Ok(())
+  }
+}
+
+// Counts segments, element fill rates, envelopes, trading partners, versions
+// and transaction sizes, keeping the `largest` biggest transactions by bytes.
+pub struct StatsCollector {
+  state: EnvelopeState,
+  largest: usize,
+  report: StatsReport,
+  tags: BTreeMap<Vec<u8>, (u64, Vec<u64>)>,
+  transaction_sets: BTreeMap<String, u64>,
+  partners: BTreeMap<(String, String), u64>,
+  interchange_versions: BTreeMap<String, u64>,
+  group_versions: BTreeMap<String, u64>,
+  current: Option<(TransactionSize, u64)>,
+  error: Option<Error>
+}
+
+fn text(segment: &Segment, n: usize) -> String {
+  segment.fields.get(n).map(|v| String::from_utf8_lossy(v).trim().to_string()).unwrap_or_default()
+}
+
+fn sorted(counts: &BTreeMap<String, u64>) -> Vec<(String, u64)> {
+  let mut counts : Vec<(String, u64)> = counts.iter().map(|(k, v)| (k.clone(), *v)).collect();
+  counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+  counts
+}
+
+impl StatsCollector {
+  pub fn new(largest: usize) -> Self {
+    StatsCollector {
+      state: EnvelopeState::Nothing,
+      largest,
+      report: StatsReport::default(),
+      tags: BTreeMap::new(),
+      transaction_sets: BTreeMap::new(),
+      partners: BTreeMap::new(),
+      interchange_versions: BTreeMap::new(),
+      group_versions: BTreeMap::new(),
+      current: None,
+      error: None
+    }
+  }
+
+  pub fn report(&self) -> StatsReport {
+    let mut report = self.report.clone();
+    report.tags = self.tags.iter().map(|(tag, (count, filled))| TagStats {
+      tag: String::from_utf8_lossy(tag).to_string(),
+      count: *count,
+      filled: filled.clone()
+    }).collect();
+    report.tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+    report.transaction_sets = sorted(&self.transaction_sets);
+    report.interchange_versions = sorted(&self.interchange_versions);
+    report.group_versions = sorted(&self.group_versions);
+    report.partners = self.partners.iter().map(|((sender, receiver), n)| TradingPartners {
+      sender: sender.clone(),
+      receiver: receiver.clone(),
+      interchanges: *n
+    }).collect();
+    report.partners.sort_by_key(|p| std::cmp::Reverse(p.interchanges));
+    report
+  }
+
+  // The report, or the first read error.
+  pub fn finish(mut self) -> Result<StatsReport, Error> {
+    match self.error.take() {
+      Some(e) => Err(e),
+      None => Ok(self.report())
+    }
+  }
+}
+
+impl StreamParser for StatsCollector {
+  fn segment(&mut self, segment: &Segment) {
+    self.report.segments += 1;
+    self.report.bytes += (segment.raw.len() + segment.stray.len()) as u64;
+    let (count, filled) = self.tags.entry(segment.tag.clone()).or_insert((0, Vec::new()));
+    *count += 1;
+    if filled.len() + 1 < segment.fields.len() {
+      filled.resize(segment.fields.len() - 1, 0);
+    }
+    for (n, field) in segment.fields[1..].iter().enumerate() {
+      if !field.is_empty() {
+        filled[n] += 1;
+      }
+    }
+    if let Some((size, end)) = self.current.as_mut().filter(|_| self.state.in_transaction()) {
+      size.segments += 1;
+      *end = segment.end_offset;
+    }
+  }
+
+  fn interchange_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InInterchange;
+    self.report.interchanges += 1;
+    let sender = format!("{}:{}", text(segment, 5), text(segment, 6));
+    let receiver = format!("{}:{}", text(segment, 7), text(segment, 8));
+    *self.partners.entry((sender, receiver)).or_insert(0) += 1;
+    *self.interchange_versions.entry(text(segment, 12)).or_insert(0) += 1;
+  }
+
+  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::Nothing;
+  }
+
+  fn functional_group_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    self.report.functional_groups += 1;
+    *self.group_versions.entry(text(segment, 8)).or_insert(0) += 1;
+  }
+
+  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InInterchange;
+  }
+
+  fn transaction_start(&mut self, segment: &Segment) {
+    self.state = EnvelopeState::InTransaction;
+    self.report.transactions += 1;
+    *self.transaction_sets.entry(text(segment, 1)).or_insert(0) += 1;
+    self.current = Some((TransactionSize {
+      set_id: text(segment, 1),
+      control_number: text(segment, 2),
+      segments: 0,
+      bytes: 0,
+      start_offset: segment.start_offset,
+      line: segment.line
+    }, segment.start_offset));
+  }
+
+  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+    self.state = EnvelopeState::InFunctionalGroup;
+    let (mut size, end) = match self.current.take() {
+      Some(c) => c,
+      None => return
+    };
+    size.bytes = (end + 1).saturating_sub(size.start_offset);
+    let report = &mut self.report;
+    let measure = (size.segments, size.bytes);
+    report.min_transaction = Some(report.min_transaction.map(|(s, b)| (s.min(measure.0), b.min(measure.1))).unwrap_or(measure));
+    report.max_transaction = Some(report.max_transaction.map(|(s, b)| (s.max(measure.0), b.max(measure.1))).unwrap_or(measure));
+    report.total_transaction = (report.total_transaction.0 + measure.0, report.total_transaction.1 + measure.1);
+    if self.largest > 0 {
+      let at = report.largest.iter().position(|t| t.bytes < size.bytes).unwrap_or(report.largest.len());
+      if at < self.largest {
+        report.largest.insert(at, size);
+        report.largest.truncate(self.largest);
+      }
+    }
+  }
+
+  fn stream_end(&mut self) {
+
+  }
+
+  fn error(&mut self, error: Error) {
+    self.error.get_or_insert(error);
+  }
+
+  fn in_interchange(&self) -> bool {
+    self.state.in_interchange()
+  }
+
+  fn in_functional_group(&self) -> bool {
+    self.state.in_functional_group()
+  }
+
+  fn in_transaction(&self) -> bool {
+    self.state.in_transaction()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::StatsCollector;
+  use crate::edi_parsers::{create_edi_streamer, execute_streaming_parser};
+  use std::io::Cursor;
+
+  const RAW : &str = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*HC*SENDER*RECEIVER*20230101*1200*1*X*005010X222A1~
+ST*837*0001*005010X222A1~
+NM1*IL*1*DOE*JOHN~
+NM1*QC*1*DOE~
+SE*4*0001~
+ST*837*0002*005010X222A1~
+SE*2*0002~
+GE*2*1~
+IEA*1*000000001~
+";
+
+  #[test]
+  fn collects_stats() {
+    let mut ioish = Cursor::new(RAW.as_bytes());
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let mut collector = StatsCollector::new(1);
+    execute_streaming_parser(&mut pi, &mut collector);
+    let report = collector.finish().unwrap();
+    assert_eq!((report.segments, report.bytes), (10, RAW.len() as u64));
+    assert_eq!((report.interchanges, report.functional_groups, report.transactions), (1, 1, 2));
+    assert_eq!(report.transaction_sets, vec![(String::from("837"), 2)]);
+    assert_eq!(report.group_versions, vec![(String::from("005010X222A1"), 1)]);
+    assert_eq!((report.partners[0].sender.as_str(), report.partners[0].receiver.as_str()), ("ZZ:SENDER", "ZZ:RECEIVER"));
+    let nm1 = report.tags.iter().find(|t| t.tag == "NM1").unwrap();
+    assert_eq!((nm1.count, nm1.fill_rate(3), nm1.fill_rate(4)), (2, 1.0, 0.5));
+    assert_eq!(report.tags[0].tag, "NM1");
+
+    let first = &RAW[RAW.find("ST*837*0001").unwrap()..RAW.find("ST*837*0002").unwrap()];
+    assert_eq!(report.largest.len(), 1);
+    assert_eq!((report.largest[0].control_number.as_str(), report.largest[0].segments), ("0001", 4));
+    assert_eq!(report.largest[0].bytes, first.len() as u64);
+    assert_eq!(report.min_transaction, Some((2, 37)));
+
+    let mut json = Vec::new();
+    report.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with("{\"segments\":10,"));
+    assert!(json.contains("\"transaction_sets\":{\"837\":2}"));
+    assert!(json.contains("{\"tag\":\"NM1\",\"count\":2,\"fill_rates\":[1.000,1.000,1.000,0.500]}"));
+    let mut text = Vec::new();
+    report.write_text(&mut text).unwrap();
+    assert!(String::from_utf8(text).unwrap().contains("  NM1\t2\t01:100% 02:100% 03:100% 04:50%"));
+  }
+}
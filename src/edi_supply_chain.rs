@@ -0,0 +1,688 @@
+use crate::edi_common::{TransactionHandler, TransactionParser, Separators, Reference, DateReference, Party};
+use crate::edi_elements::{Decimal, EdiDate, EdiTime};
+use crate::edi_segments::Segment;
+use std::fmt;
+
+// ACK under a PO1 of an 855.
+#[derive(PartialEq, Debug, Clone)]
+pub struct LineAcknowledgment {
+  pub status_code: String,
+  pub quantity: Option<Decimal>,
+  pub unit: Option<String>,
+  pub date_qualifier: Option<String>,
+  pub date: Option<EdiDate>
+}
+
+// SAC.  `amount` is SAC05 with its two implied decimal places applied.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Charge {
+  pub indicator: String,
+  pub code: Option<String>,
+  pub amount: Option<Decimal>
+}
+
+impl Charge {
+  fn from_sac(segment: &Segment) -> Self {
+    Charge {
+      indicator: segment.text(1).unwrap_or_default(),
+      code: segment.text(2),
+      amount: segment.element_implied_decimal(5, 2).value()
+    }
+  }
+
+  // Allowances (A) lower the total and charges (C) raise it.  `None` for
+  // an allowance whose amount can not be negated.
+  pub fn signed_amount(&self) -> Option<Decimal> {
+    let amount = self.amount.unwrap_or_else(Decimal::zero);
+    if self.indicator == "A" { amount.checked_neg() } else { Some(amount) }
+  }
+}
+
+// PO1 or IT1 with the segments that follow it.  `product_ids` holds the
+// qualifier and id pairs from the sixth element on.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct LineItem {
+  pub line_number: Option<String>,
+  pub quantity: Option<Decimal>,
+  pub unit: Option<String>,
+  pub unit_price: Option<Decimal>,
+  pub product_ids: Vec<(String, String)>,
+  pub description: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub charges: Vec<Charge>,
+  pub acknowledgments: Vec<LineAcknowledgment>
+}
+
+impl LineItem {
+  fn from_segment(segment: &Segment) -> Self {
+    LineItem {
+      line_number: segment.text(1),
+      quantity: segment.element_decimal(2).value(),
+      unit: segment.text(3),
+      unit_price: segment.element_decimal(4).value(),
+      product_ids: product_ids(segment, 6),
+      ..LineItem::default()
+    }
+  }
+
+  // Quantity times unit price, or `None` if either is missing or the product
+  // overflows.
+  pub fn extended_amount(&self) -> Option<Decimal> {
+    self.quantity?.checked_mul(self.unit_price?)
+  }
+}
+
+fn product_ids(segment: &Segment, first: usize) -> Vec<(String, String)> {
+  (first..segment.fields.len()).step_by(2)
+    .filter_map(|n| Some((segment.text(n)?, segment.text(n + 1)?)))
+    .collect()
+}
+
+// CTT
+#[derive(PartialEq, Debug, Clone)]
+pub struct TransactionTotals {
+  pub line_count: Option<i64>,
+  pub hash_total: Option<Decimal>
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TotalsErrorKind {
+  // CTT01 against the number of PO1 or IT1 segments, or HL segments in an 856.
+  LineCount,
+  // CTT02 against the sum of PO102, IT102 or SN102.
+  HashTotal,
+  // TDS01 against the extended line amounts plus charges less allowances.
+  InvoiceTotal
+}
+
+// A CTT or TDS control total that disagrees with the transaction.
+// `expected` is the CTT01, CTT02 or TDS01 value and `computed` what the lines
+// add up to, or `None` when their amounts are too large to add up.  The
+// position is that of the CTT or TDS segment.
+#[derive(PartialEq, Debug, Clone)]
+pub struct TotalsError {
+  pub kind: TotalsErrorKind,
+  pub expected: Decimal,
+  pub computed: Option<Decimal>,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64
+}
+
+impl fmt::Display for TotalsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let (total, counted) = match self.kind {
+      TotalsErrorKind::LineCount => ("CTT01 line count", "the transaction has"),
+      TotalsErrorKind::HashTotal => ("CTT02 hash total", "the quantities sum to"),
+      TotalsErrorKind::InvoiceTotal => ("TDS01 invoice total", "the lines, charges and allowances come to")
+    };
+    match self.computed {
+      Some(computed) => write!(f, "line {}: {} is {} but {} {}", self.line, total, self.expected, counted, computed),
+      None => write!(f, "line {}: {} is {} but the amounts are too large to total", self.line, total, self.expected)
+    }
+  }
+}
+
+// 850
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PurchaseOrder {
+  pub control_number: String,
+  pub purpose_code: Option<String>,
+  pub order_type: Option<String>,
+  pub po_number: String,
+  pub release_number: Option<String>,
+  pub date: Option<EdiDate>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub parties: Vec<Party>,
+  pub lines: Vec<LineItem>,
+  pub totals: Option<TransactionTotals>,
+  pub errors: Vec<TotalsError>
+}
+
+// 855
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct PurchaseOrderAck {
+  pub control_number: String,
+  pub purpose_code: Option<String>,
+  pub ack_type: Option<String>,
+  pub po_number: String,
+  pub date: Option<EdiDate>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub parties: Vec<Party>,
+  pub lines: Vec<LineItem>,
+  pub totals: Option<TransactionTotals>,
+  pub errors: Vec<TotalsError>
+}
+
+// One HL of an 856.  `level_code` is S (shipment), O (order), T (tare),
+// P (pack) or I (item).
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ShipmentLevel {
+  pub hl_id: String,
+  pub parent_id: Option<String>,
+  pub level_code: String,
+  pub purchase_order: Option<String>,
+  pub marks: Vec<(String, String)>,
+  pub product_ids: Vec<(String, String)>,
+  pub quantity_shipped: Option<Decimal>,
+  pub unit: Option<String>,
+  pub carrier: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub parties: Vec<Party>
+}
+
+// 856
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ShipNotice {
+  pub control_number: String,
+  pub purpose_code: Option<String>,
+  pub shipment_id: String,
+  pub date: Option<EdiDate>,
+  pub time: Option<EdiTime>,
+  pub levels: Vec<ShipmentLevel>,
+  pub totals: Option<TransactionTotals>,
+  pub errors: Vec<TotalsError>
+}
+
+impl ShipNotice {
+  pub fn children<'a>(&'a self, level: &'a ShipmentLevel) -> impl Iterator<Item = &'a ShipmentLevel> {
+    self.levels.iter().filter(move |l| l.parent_id.as_deref() == Some(level.hl_id.as_str()))
+  }
+}
+
+// 810.  `total_amount` is TDS01 with its two implied decimal places applied.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Invoice {
+  pub control_number: String,
+  pub date: Option<EdiDate>,
+  pub invoice_number: String,
+  pub po_date: Option<EdiDate>,
+  pub po_number: Option<String>,
+  pub references: Vec<Reference>,
+  pub dates: Vec<DateReference>,
+  pub parties: Vec<Party>,
+  pub lines: Vec<LineItem>,
+  pub charges: Vec<Charge>,
+  pub total_amount: Option<Decimal>,
+  pub totals: Option<TransactionTotals>,
+  pub errors: Vec<TotalsError>
+}
+
+pub enum SupplyChainDocument {
+  PurchaseOrder(PurchaseOrder),
+  PurchaseOrderAck(PurchaseOrderAck),
+  ShipNotice(ShipNotice),
+  Invoice(Invoice)
+}
+
+pub trait SupplyChainHandler {
+  fn document(&mut self, document: SupplyChainDocument);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Loop {
+  Header,
+  Party,
+  Line,
+  Summary
+}
+
+// What has been read of the current transaction set.  It is turned into a
+// typed document, and checked, at the SE.
+#[derive(Default)]
+struct Draft {
+  set_id: String,
+  control_number: String,
+  beginning: Option<Segment>,
+  references: Vec<Reference>,
+  dates: Vec<DateReference>,
+  parties: Vec<Party>,
+  lines: Vec<LineItem>,
+  levels: Vec<ShipmentLevel>,
+  charges: Vec<Charge>,
+  totals: Option<(TransactionTotals, Segment)>,
+  total_amount: Option<(Decimal, Segment)>
+}
+
+fn totals_error(kind: TotalsErrorKind, expected: Decimal, computed: Option<Decimal>, at: &Segment) -> Option<TotalsError> {
+  if computed == Some(expected) {
+    return None;
+  }
+  Some(TotalsError {
+    kind,
+    expected,
+    computed,
+    segment_index: at.segment_index,
+    start_offset: at.start_offset,
+    line: at.line,
+    column: at.column
+  })
+}
+
+// CTT02 hash totals and invoice amounts are summed from partner data, so a
+// running total that no longer fits ends the sum with `None`.
+fn sum<I: Iterator<Item = Option<Decimal>>>(mut amounts: I) -> Option<Decimal> {
+  amounts.try_fold(Decimal::zero(), |sum, a| sum.checked_add(a?))
+}
+
+impl Draft {
+  fn check_totals(&self, count: usize, hash_total: Option<Decimal>) -> Vec<TotalsError> {
+    let mut errors = Vec::new();
+    if let Some((totals, at)) = &self.totals {
+      if let Some(line_count) = totals.line_count {
+        errors.extend(totals_error(TotalsErrorKind::LineCount, Decimal::new(line_count as i128, 0), Some(Decimal::new(count as i128, 0)), at));
+      }
+      if let Some(expected) = totals.hash_total {
+        errors.extend(totals_error(TotalsErrorKind::HashTotal, expected, hash_total, at));
+      }
+    }
+    errors
+  }
+
+  fn line_totals(&self) -> Vec<TotalsError> {
+    let hash_total = sum(self.lines.iter().filter_map(|l| l.quantity).map(Some));
+    self.check_totals(self.lines.len(), hash_total)
+  }
+
+  fn finish(mut self) -> Option<SupplyChainDocument> {
+    let beginning = self.beginning.take();
+    let text = |n: usize| beginning.as_ref().and_then(|b| b.text(n));
+    let date = |n: usize| beginning.as_ref().and_then(|b| b.element_date(n).value());
+    let totals = self.totals.as_ref().map(|(t, _)| t.clone());
+    match self.set_id.as_str() {
+      "850" => Some(SupplyChainDocument::PurchaseOrder(PurchaseOrder {
+        errors: self.line_totals(),
+        control_number: self.control_number,
+        purpose_code: text(1),
+        order_type: text(2),
+        po_number: text(3).unwrap_or_default(),
+        release_number: text(4),
+        date: date(5),
+        references: self.references,
+        dates: self.dates,
+        parties: self.parties,
+        lines: self.lines,
+        totals
+      })),
+      "855" => Some(SupplyChainDocument::PurchaseOrderAck(PurchaseOrderAck {
+        errors: self.line_totals(),
+        control_number: self.control_number,
+        purpose_code: text(1),
+        ack_type: text(2),
+        po_number: text(3).unwrap_or_default(),
+        date: date(4),
+        references: self.references,
+        dates: self.dates,
+        parties: self.parties,
+        lines: self.lines,
+        totals
+      })),
+      "856" => {
+        let hash_total = sum(self.levels.iter().filter_map(|l| l.quantity_shipped).map(Some));
+        Some(SupplyChainDocument::ShipNotice(ShipNotice {
+          errors: self.check_totals(self.levels.len(), hash_total),
+          control_number: self.control_number,
+          purpose_code: text(1),
+          shipment_id: text(2).unwrap_or_default(),
+          date: date(3),
+          time: beginning.as_ref().and_then(|b| b.element_time(4).value()),
+          levels: self.levels,
+          totals
+        }))
+      },
+      "810" => {
+        let mut errors = self.line_totals();
+        if let Some((expected, at)) = &self.total_amount {
+          let lines = self.lines.iter().map(|l| {
+            let extended = match (l.quantity, l.unit_price) {
+              (Some(_), Some(_)) => l.extended_amount()?,
+              _ => Decimal::zero()
+            };
+            extended.checked_add(sum(l.charges.iter().map(|c| c.signed_amount()))?)
+          });
+          let computed = sum(lines.chain(self.charges.iter().map(|c| c.signed_amount())));
+          errors.extend(totals_error(TotalsErrorKind::InvoiceTotal, *expected, computed.map(|c| c.round(2)), at));
+        }
+        Some(SupplyChainDocument::Invoice(Invoice {
+          errors,
+          control_number: self.control_number,
+          date: date(1),
+          invoice_number: text(2).unwrap_or_default(),
+          po_date: date(3),
+          po_number: text(4),
+          references: self.references,
+          dates: self.dates,
+          parties: self.parties,
+          lines: self.lines,
+          charges: self.charges,
+          total_amount: self.total_amount.map(|(t, _)| t),
+          totals
+        }))
+      },
+      _ => None
+    }
+  }
+}
+
+// Reads 850, 855, 856 and 810 transaction sets and hands each one over as a
+// typed document at its SE, with its CTT and TDS totals checked.  Other
+// transaction sets are skipped.
+pub struct SupplyChainReader<T: SupplyChainHandler> {
+  draft: Option<Draft>,
+  current: Loop,
+  handler: T
+}
+
+pub type SupplyChainParser<T> = TransactionParser<SupplyChainReader<T>>;
+
+impl<T: SupplyChainHandler> SupplyChainReader<T> {
+  pub fn new(handler: T) -> Self {
+    SupplyChainReader {
+      draft: None,
+      current: Loop::Header,
+      handler
+    }
+  }
+
+  pub fn handler(&self) -> &T {
+    &self.handler
+  }
+
+  pub fn into_handler(self) -> T {
+    self.handler
+  }
+}
+
+// Segments of an 856 go to the HL they follow.
+fn level_segment(level: &mut ShipmentLevel, current: Loop, segment: &Segment) -> Loop {
+  if current == Loop::Party {
+    if let Some(party) = level.parties.last_mut() {
+      if party.apply(segment) {
+        return Loop::Party;
+      }
+    }
+  }
+  match segment.tag.as_slice() {
+    b"PRF" => level.purchase_order = segment.text(1),
+    b"MAN" => level.marks.extend(product_ids(segment, 1)),
+    b"LIN" => level.product_ids = product_ids(segment, 2),
+    b"SN1" => {
+      level.quantity_shipped = segment.element_decimal(2).value();
+      level.unit = segment.text(3);
+    },
+    b"TD5" => level.carrier = segment.text(3),
+    b"REF" => level.references.push(Reference::from_ref(segment)),
+    b"DTM" => level.dates.push(DateReference::from_dtm(segment)),
+    b"N1" => {
+      level.parties.push(Party::from_n1(segment));
+      return Loop::Party;
+    },
+    _ => ()
+  }
+  Loop::Header
+}
+
+fn draft_segment(draft: &mut Draft, current: Loop, segment: &Segment) -> Loop {
+  let tag = segment.tag.as_slice();
+  if current == Loop::Party {
+    if let Some(party) = draft.parties.last_mut() {
+      if party.apply(segment) {
+        return Loop::Party;
+      }
+    }
+  }
+  match (tag, current) {
+    (b"BEG", _) | (b"BAK", _) | (b"BSN", _) | (b"BIG", _) => {
+      draft.beginning = Some(segment.clone());
+      Loop::Header
+    },
+    (b"PO1", _) | (b"IT1", _) => {
+      draft.lines.push(LineItem::from_segment(segment));
+      Loop::Line
+    },
+    (b"CTT", _) => {
+      draft.totals = Some((TransactionTotals {
+        line_count: segment.element_int(1).value(),
+        hash_total: segment.element_decimal(2).value()
+      }, segment.clone()));
+      Loop::Summary
+    },
+    (b"TDS", _) => {
+      if let Some(amount) = segment.element_implied_decimal(1, 2).value() {
+        draft.total_amount = Some((amount, segment.clone()));
+      }
+      Loop::Summary
+    },
+    (b"SAC", Loop::Summary) => {
+      draft.charges.push(Charge::from_sac(segment));
+      Loop::Summary
+    },
+    (_, Loop::Line) => {
+      if let Some(line) = draft.lines.last_mut() {
+        match tag {
+          b"PID" => line.description = segment.text(5),
+          b"REF" => line.references.push(Reference::from_ref(segment)),
+          b"DTM" => line.dates.push(DateReference::from_dtm(segment)),
+          b"SAC" => line.charges.push(Charge::from_sac(segment)),
+          b"ACK" => line.acknowledgments.push(LineAcknowledgment {
+            status_code: segment.text(1).unwrap_or_default(),
+            quantity: segment.element_decimal(2).value(),
+            unit: segment.text(3),
+            date_qualifier: segment.text(4),
+            date: segment.element_date(5).value()
+          }),
+          _ => ()
+        }
+      }
+      Loop::Line
+    },
+    (b"N1", _) => {
+      draft.parties.push(Party::from_n1(segment));
+      Loop::Party
+    },
+    (b"REF", _) => {
+      draft.references.push(Reference::from_ref(segment));
+      current
+    },
+    (b"DTM", _) => {
+      draft.dates.push(DateReference::from_dtm(segment));
+      current
+    },
+    (b"SAC", _) => {
+      draft.charges.push(Charge::from_sac(segment));
+      current
+    },
+    _ => current
+  }
+}
+
+impl<T: SupplyChainHandler> TransactionHandler for SupplyChainReader<T> {
+  fn transaction_start(&mut self, header: &Segment, _separators: &Separators) {
+    let set_id = header.text(1).unwrap_or_default();
+    self.draft = match set_id.as_str() {
+      "850" | "855" | "856" | "810" => Some(Draft {
+        set_id,
+        control_number: header.text(2).unwrap_or_default(),
+        ..Draft::default()
+      }),
+      _ => None
+    };
+    self.current = Loop::Header;
+  }
+
+  fn segment(&mut self, segment: &Segment, _separators: &Separators) {
+    let draft = match self.draft.as_mut() {
+      None => return,
+      Some(d) => d
+    };
+    let tag = segment.tag.as_slice();
+    self.current = if draft.set_id != "856" || tag == b"BSN" || tag == b"CTT" {
+      draft_segment(draft, self.current, segment)
+    } else if tag == b"HL" {
+      draft.levels.push(ShipmentLevel {
+        hl_id: segment.text(1).unwrap_or_default(),
+        parent_id: segment.text(2),
+        level_code: segment.text(3).unwrap_or_default(),
+        ..ShipmentLevel::default()
+      });
+      Loop::Header
+    } else {
+      match draft.levels.last_mut() {
+        Some(level) => level_segment(level, self.current, segment),
+        None => draft_segment(draft, self.current, segment)
+      }
+    };
+  }
+
+  fn transaction_end(&mut self, _trailer: Option<&Segment>) {
+    if let Some(document) = self.draft.take().and_then(|d| d.finish()) {
+      self.handler.document(document);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{SupplyChainHandler, SupplyChainDocument, SupplyChainReader, TotalsErrorKind};
+  use crate::edi_common::testing::{Collector, read_transactions};
+  use crate::edi_elements::Decimal;
+
+  impl SupplyChainHandler for Collector<SupplyChainDocument> {
+    fn document(&mut self, document: SupplyChainDocument) {
+      self.items.push(document);
+    }
+  }
+
+  fn read(body: &str) -> Vec<SupplyChainDocument> {
+    let raw = format!("\
+ISA*00*          *00*          *ZZ*BUYER          *ZZ*SELLER         *230101*1200*U*00401*000000001*0*P*>~
+GS*PO*BUYER*SELLER*20230101*1200*1*X*004010~
+{}GE*1*1~
+IEA*1*000000001~
+", body);
+    read_transactions(raw.as_bytes(), SupplyChainReader::new(Collector::new())).into_handler().items
+  }
+
+  #[test]
+  fn reads_orders_and_acknowledgments() {
+    let documents = read("\
+ST*850*0001~
+BEG*00*SA*PO1001**20230101~
+REF*DP*038~
+N1*ST*WAREHOUSE*92*0001~
+N3*100 DOCK RD~
+N4*DAYTON*OH*45401~
+PO1*1*10*EA*2.50**UP*012345678905*VN*ABC1~
+PID*F****WIDGET~
+PO1*2*5*EA*4**VN*ABC2~
+CTT*2*15~
+SE*11*0001~
+ST*855*0002~
+BAK*00*AC*PO1001*20230102~
+PO1*1*10*EA*2.50**VN*ABC1~
+ACK*IA*10*EA*068*20230110~
+CTT*2*10~
+SE*6*0002~
+");
+    assert_eq!(documents.len(), 2);
+    let order = match &documents[0] {
+      SupplyChainDocument::PurchaseOrder(o) => o,
+      _ => panic!("expected a purchase order")
+    };
+    assert_eq!(order.po_number, "PO1001");
+    assert_eq!(order.parties[0].address.city.as_deref(), Some("DAYTON"));
+    assert_eq!(order.lines.len(), 2);
+    assert_eq!(order.lines[0].product_ids[1], (String::from("VN"), String::from("ABC1")));
+    assert_eq!(order.lines[0].description.as_deref(), Some("WIDGET"));
+    assert!(order.errors.is_empty());
+    let ack = match &documents[1] {
+      SupplyChainDocument::PurchaseOrderAck(a) => a,
+      _ => panic!("expected an acknowledgment")
+    };
+    assert_eq!(ack.lines[0].acknowledgments[0].status_code, "IA");
+    let kinds : Vec<TotalsErrorKind> = ack.errors.iter().map(|e| e.kind).collect();
+    assert_eq!(kinds, vec![TotalsErrorKind::LineCount]);
+    assert_eq!(ack.errors[0].line, 18);
+  }
+
+  #[test]
+  fn reads_ship_notices_and_invoices() {
+    let documents = read("\
+ST*856*0001~
+BSN*00*SHIP1*20230105*1300~
+HL*1**S~
+TD5**2*UPSN~
+N1*SF*SELLER~
+N4*AKRON*OH*44301~
+HL*2*1*O~
+PRF*PO1001~
+HL*3*2*P~
+MAN*GM*00012345678901234567~
+HL*4*3*I~
+LIN**VN*ABC1~
+SN1**10*EA~
+HL*5*3*I~
+LIN**VN*ABC2~
+SN1**5*EA~
+CTT*5*15~
+SE*17*0001~
+ST*810*0002~
+BIG*20230106*INV1*20230101*PO1001~
+IT1*1*10*EA*2.50**VN*ABC1~
+IT1*2*5*EA*4**VN*ABC2~
+SAC*C*D240***1000~
+TDS*5500~
+SAC*A*C310***500~
+CTT*2*15~
+SE*9*0002~
+");
+    let notice = match &documents[0] {
+      SupplyChainDocument::ShipNotice(n) => n,
+      _ => panic!("expected a ship notice")
+    };
+    assert_eq!(notice.shipment_id, "SHIP1");
+    assert_eq!(notice.levels.len(), 5);
+    assert_eq!(notice.levels[0].carrier.as_deref(), Some("UPSN"));
+    assert_eq!(notice.levels[0].parties[0].address.city.as_deref(), Some("AKRON"));
+    assert_eq!(notice.levels[1].purchase_order.as_deref(), Some("PO1001"));
+    let items : Vec<&str> = notice.children(&notice.levels[2]).map(|l| l.hl_id.as_str()).collect();
+    assert_eq!(items, vec!["4", "5"]);
+    assert!(notice.errors.is_empty());
+    let invoice = match &documents[1] {
+      SupplyChainDocument::Invoice(i) => i,
+      _ => panic!("expected an invoice")
+    };
+    assert_eq!(invoice.invoice_number, "INV1");
+    assert_eq!(invoice.total_amount, Some(Decimal::new(5500, 2)));
+    assert_eq!(invoice.lines[1].charges.len(), 1);
+    assert_eq!(invoice.charges.len(), 1);
+    let errors : Vec<(TotalsErrorKind, Option<Decimal>)> = invoice.errors.iter().map(|e| (e.kind, e.computed)).collect();
+    assert_eq!(errors, vec![(TotalsErrorKind::InvoiceTotal, Some(Decimal::new(5000, 2)))]);
+  }
+
+  #[test]
+  fn reports_overflowing_totals() {
+    let documents = read("\
+ST*810*0001~
+BIG*20230106*INV1*20230101*PO1001~
+IT1*1*99999999999999999999999999999999999999*EA*99999999999999999999999999999999999999**VN*ABC1~
+TDS*5500~
+CTT*1~
+SE*6*0001~
+");
+    let invoice = match &documents[0] {
+      SupplyChainDocument::Invoice(i) => i,
+      _ => panic!("expected an invoice")
+    };
+    assert_eq!(invoice.lines[0].extended_amount(), None);
+    assert_eq!(invoice.errors.len(), 1);
+    assert_eq!(invoice.errors[0].kind, TotalsErrorKind::InvoiceTotal);
+    assert_eq!(invoice.errors[0].computed, None);
+    assert!(invoice.errors[0].to_string().contains("TDS01 invoice total is 55.00 but the amounts are too large to total"));
+  }
+}
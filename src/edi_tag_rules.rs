@@ -0,0 +1,96 @@
+// Decides where a new segment starts in the bytes that follow a segment
+// terminator.  Candidates are the bytes up to the next element or segment
+// delimiter.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum SegmentTagRule {
+  // A letter followed by one or two letters or digits, after any bytes that
+  // are not letters or digits.
+  #[default]
+  X12TagGrammar,
+  // Whatever follows the terminator is the tag.
+  AnyByte,
+  // Given the candidate, returns where the tag starts.
+  Custom(fn(&[u8]) -> Option<usize>)
+}
+
+// Custom rules are equal when they are the same function.
+impl PartialEq for SegmentTagRule {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (SegmentTagRule::X12TagGrammar, SegmentTagRule::X12TagGrammar) => true,
+      (SegmentTagRule::AnyByte, SegmentTagRule::AnyByte) => true,
+      (SegmentTagRule::Custom(a), SegmentTagRule::Custom(b)) => std::ptr::fn_addr_eq(*a, *b),
+      _ => false
+    }
+  }
+}
+
+impl SegmentTagRule {
+  // Returns how many leading bytes of `candidate` are stray, with the rest
+  // being the tag, or `None` if no tag can be found in it.
+  pub fn split(&self, candidate: &[u8]) -> Option<usize> {
+    match self {
+      SegmentTagRule::X12TagGrammar => {
+        let k = candidate.iter().rposition(|b| !b.is_ascii_alphanumeric()).map_or(0, |p| p + 1);
+        if is_x12_tag(&candidate[k..]) { Some(k) } else { None }
+      },
+      SegmentTagRule::AnyByte => if candidate.is_empty() { None } else { Some(0) },
+      SegmentTagRule::Custom(f) => f(candidate).filter(|k| *k < candidate.len())
+    }
+  }
+}
+
+pub fn is_x12_tag(tag: &[u8]) -> bool {
+  (2..=3).contains(&tag.len()) &&
+    tag[0].is_ascii_alphabetic() &&
+    tag[1..].iter().all(|b| b.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod test {
+  use super::SegmentTagRule;
+  use crate::edi_segments::create_segment_iterator;
+  use std::io::Cursor;
+
+  #[test]
+  fn splits_candidates() {
+    assert_eq!(SegmentTagRule::X12TagGrammar.split(b"PO1"), Some(0));
+    assert_eq!(SegmentTagRule::X12TagGrammar.split(b"\r\nN4"), Some(2));
+    assert_eq!(SegmentTagRule::X12TagGrammar.split(b"\r\n"), None);
+    assert_eq!(SegmentTagRule::X12TagGrammar.split(b"1AB"), None);
+    assert_eq!(SegmentTagRule::X12TagGrammar.split(b"junk"), None);
+    assert_eq!(SegmentTagRule::AnyByte.split(b"\n1"), Some(0));
+    assert_eq!(SegmentTagRule::Custom(|c| c.iter().position(|b| *b == b'Z')).split(b"xZZ"), Some(1));
+  }
+
+  #[test]
+  fn reports_stray_bytes() {
+    let mut ioish = Cursor::new("ST*1~\r\n  N1*X~junk~SE*2~".as_bytes());
+    let pi = create_segment_iterator(&mut ioish, b"*".to_vec(), b"~".to_vec());
+    let segments : Vec<_> = pi.map(|r| r.unwrap()).collect();
+    let tags : Vec<Vec<u8>> = segments.iter().map(|s| s.tag.clone()).collect();
+    assert_eq!(tags, vec![b"ST".to_vec(), b"N1".to_vec(), b"SE".to_vec()]);
+    assert_eq!(segments[0].raw, b"ST*1~".to_vec());
+    assert_eq!(segments[0].stray, b"\r\n  ".to_vec());
+    assert_eq!(segments[0].end_offset, 4);
+    assert_eq!(segments[1].start_offset, 9);
+    assert_eq!(segments[1].stray, b"junk~".to_vec());
+    assert_eq!(segments[2].start_offset, 19);
+    assert_eq!((segments[1].line, segments[1].column), (2, 3));
+    assert_eq!((segments[2].line, segments[2].column), (2, 13));
+  }
+
+  #[test]
+  fn multibyte_terminator_with_letters() {
+    let mut ioish = Cursor::new("ST*1~X\nN1*A~X\nSE*2~X\n".as_bytes());
+    let pi = create_segment_iterator(&mut ioish, b"*".to_vec(), b"~X\n".to_vec()).with_tag_rule(SegmentTagRule::AnyByte);
+    let segments : Vec<_> = pi.map(|r| r.unwrap()).collect();
+    let fields : Vec<Vec<Vec<u8>>> = segments.iter().map(|s| s.fields.clone()).collect();
+    assert_eq!(fields, vec![
+      vec![b"ST".to_vec(), b"1".to_vec()],
+      vec![b"N1".to_vec(), b"A".to_vec()],
+      vec![b"SE".to_vec(), b"2".to_vec()]
+    ]);
+    assert_eq!(segments[1].raw, b"N1*A~X\n".to_vec());
+  }
+}
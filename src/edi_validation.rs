@@ -0,0 +1,395 @@
+use crate::edi_segments::Segment;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DataType {
+  AN,
+  ID,
+  N(u8),
+  R,
+  DT,
+  TM
+}
+
+pub struct ElementDefinition {
+  pub data_type: DataType,
+  pub min_length: usize,
+  pub max_length: usize,
+  pub mandatory: bool,
+  pub code_list: Option<String>
+}
+
+impl ElementDefinition {
+  pub fn new(data_type: DataType, min_length: usize, max_length: usize) -> Self {
+    ElementDefinition {
+      data_type,
+      min_length,
+      max_length,
+      mandatory: false,
+      code_list: None
+    }
+  }
+
+  pub fn mandatory(mut self) -> Self {
+    self.mandatory = true;
+    self
+  }
+
+  pub fn with_code_list(mut self, name: &str) -> Self {
+    self.code_list = Some(String::from(name));
+    self
+  }
+}
+
+pub struct SegmentDefinition {
+  pub tag: Vec<u8>,
+  pub elements: Vec<ElementDefinition>
+}
+
+impl SegmentDefinition {
+  pub fn new(tag: &str, elements: Vec<ElementDefinition>) -> Self {
+    SegmentDefinition {
+      tag: Vec::from(tag.as_bytes()),
+      elements
+    }
+  }
+}
+
+pub trait CodeList {
+  fn contains(&self, value: &[u8]) -> bool;
+}
+
+impl CodeList for HashSet<Vec<u8>> {
+  fn contains(&self, value: &[u8]) -> bool {
+    HashSet::contains(self, value)
+  }
+}
+
+impl CodeList for Vec<&'static str> {
+  fn contains(&self, value: &[u8]) -> bool {
+    self.iter().any(|c| c.as_bytes() == value)
+  }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ViolationKind {
+  MandatoryElementMissing,
+  TooManyElements,
+  TooShort,
+  TooLong,
+  InvalidCharacter,
+  InvalidCodeValue,
+  InvalidDate,
+  InvalidTime
+}
+
+impl ViolationKind {
+  // Element syntax error codes as used by IK403 (999) and AK403 (997).
+  pub fn syntax_error_code(&self) -> &'static str {
+    match self {
+      ViolationKind::MandatoryElementMissing => "1",
+      ViolationKind::TooManyElements => "3",
+      ViolationKind::TooShort => "4",
+      ViolationKind::TooLong => "5",
+      ViolationKind::InvalidCharacter => "6",
+      ViolationKind::InvalidCodeValue => "7",
+      ViolationKind::InvalidDate => "8",
+      ViolationKind::InvalidTime => "9"
+    }
+  }
+}
+
+pub struct Violation {
+  pub kind: ViolationKind,
+  pub segment_tag: Vec<u8>,
+  pub element_position: usize,
+  pub segment_index: u64,
+  pub start_offset: u64,
+  pub line: u64,
+  pub column: u64,
+  pub value: Vec<u8>
+}
+
+impl Violation {
+  pub fn reference_designator(&self) -> String {
+    format!("{}-{:02}", String::from_utf8_lossy(&self.segment_tag), self.element_position)
+  }
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}, element {}: {:?}", self.line, self.reference_designator(), self.kind)
+  }
+}
+
+pub struct ElementValidator {
+  segments: HashMap<Vec<u8>, SegmentDefinition>,
+  code_lists: HashMap<String, Box<dyn CodeList>>
+}
+
+#[allow(clippy::new_without_default)]
+impl ElementValidator {
+  pub fn new() -> Self {
+    ElementValidator {
+      segments: HashMap::new(),
+      code_lists: HashMap::new()
+    }
+  }
+
+  pub fn add_segment(&mut self, definition: SegmentDefinition) {
+    self.segments.insert(definition.tag.clone(), definition);
+  }
+
+  pub fn add_code_list<T: CodeList + 'static>(&mut self, name: &str, code_list: T) {
+    self.code_lists.insert(String::from(name), Box::new(code_list));
+  }
+
+  pub fn validate(&self, segment: &Segment) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let definition = match self.segments.get(&segment.tag) {
+      None => return violations,
+      Some(d) => d
+    };
+    let mut last_used = 0;
+    for (idx, value) in segment.fields.iter().enumerate().skip(1) {
+      if !value.is_empty() {
+        last_used = idx;
+      }
+    }
+    if last_used > definition.elements.len() {
+      violations.push(build_violation(ViolationKind::TooManyElements, segment, last_used));
+    }
+    for (idx, element) in definition.elements.iter().enumerate() {
+      let position = idx + 1;
+      let value = match segment.fields.get(position) {
+        Some(v) if !v.is_empty() => v,
+        _ => {
+          if element.mandatory {
+            violations.push(build_violation(ViolationKind::MandatoryElementMissing, segment, position));
+          }
+          continue;
+        }
+      };
+      if let Some(kind) = self.check_element(element, value) {
+        violations.push(build_violation(kind, segment, position));
+      }
+    }
+    violations
+  }
+
+  fn check_element(&self, element: &ElementDefinition, value: &[u8]) -> Option<ViolationKind> {
+    if let Some(kind) = check_data_type(element.data_type, value) {
+      return Some(kind);
+    }
+    let length = element_length(element.data_type, value);
+    if length < element.min_length {
+      return Some(ViolationKind::TooShort);
+    }
+    if length > element.max_length {
+      return Some(ViolationKind::TooLong);
+    }
+    match (&element.code_list, element.data_type) {
+      (Some(name), DataType::ID) => match self.code_lists.get(name) {
+        Some(cl) if !cl.contains(value) => Some(ViolationKind::InvalidCodeValue),
+        _ => None
+      },
+      _ => None
+    }
+  }
+}
+
+fn build_violation(kind: ViolationKind, segment: &Segment, position: usize) -> Violation {
+  Violation {
+    kind,
+    segment_tag: segment.tag.clone(),
+    element_position: position,
+    segment_index: segment.segment_index,
+    start_offset: segment.start_offset,
+    line: segment.line,
+    column: segment.column,
+    value: segment.fields.get(position).cloned().unwrap_or_default()
+  }
+}
+
+fn check_data_type(data_type: DataType, value: &[u8]) -> Option<ViolationKind> {
+  match data_type {
+    DataType::AN | DataType::ID => {
+      if value.iter().all(|b| *b >= 0x20 && *b != 0x7F) {
+        None
+      } else {
+        Some(ViolationKind::InvalidCharacter)
+      }
+    },
+    DataType::N(_) => {
+      if is_numeric(value) { None } else { Some(ViolationKind::InvalidCharacter) }
+    },
+    DataType::R => {
+      if is_decimal(value) { None } else { Some(ViolationKind::InvalidCharacter) }
+    },
+    DataType::DT => {
+      if parse_date(value).is_some() { None } else { Some(ViolationKind::InvalidDate) }
+    },
+    DataType::TM => {
+      if parse_time(value).is_some() { None } else { Some(ViolationKind::InvalidTime) }
+    }
+  }
+}
+
+// Signs and decimal points do not count towards the length of numeric elements.
+fn element_length(data_type: DataType, value: &[u8]) -> usize {
+  match data_type {
+    DataType::N(_) | DataType::R => value.iter().filter(|b| b.is_ascii_digit()).count(),
+    _ => value.len()
+  }
+}
+
+pub(crate) fn is_numeric(value: &[u8]) -> bool {
+  let digits = match value.first() {
+    Some(b'-') => &value[1..],
+    _ => value
+  };
+  !digits.is_empty() && digits.iter().all(|b| b.is_ascii_digit())
+}
+
+pub(crate) fn is_decimal(value: &[u8]) -> bool {
+  let digits = match value.first() {
+    Some(b'-') => &value[1..],
+    _ => value
+  };
+  let mut seen_point = false;
+  let mut seen_digit = false;
+  for b in digits {
+    match b {
+      b'.' if !seen_point => seen_point = true,
+      d if d.is_ascii_digit() => seen_digit = true,
+      _ => return false
+    }
+  }
+  seen_digit
+}
+
+fn parse_digits(value: &[u8]) -> Option<u32> {
+  if value.is_empty() || !value.iter().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  Some(value.iter().fold(0, |acc, b| acc * 10 + (b - b'0') as u32))
+}
+
+// Accepts CCYYMMDD and YYMMDD, returning the (possibly two digit) year, month and day.
+pub(crate) fn parse_date(value: &[u8]) -> Option<(u32, u32, u32)> {
+  let (year, rest, full_year) = match value.len() {
+    8 => (parse_digits(&value[0..4])?, &value[4..], true),
+    6 => (parse_digits(&value[0..2])?, &value[2..], false),
+    _ => return None
+  };
+  let month = parse_digits(&rest[0..2])?;
+  let day = parse_digits(&rest[2..4])?;
+  let leap = if full_year {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+  } else {
+    year % 4 == 0
+  };
+  let days_in_month = match month {
+    1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+    4 | 6 | 9 | 11 => 30,
+    2 if leap => 29,
+    2 => 28,
+    _ => return None
+  };
+  if day == 0 || day > days_in_month {
+    return None;
+  }
+  Some((year, month, day))
+}
+
+// Accepts HHMM, HHMMSS, HHMMSSd and HHMMSSdd, returning hours, minutes, seconds and hundredths.
+pub(crate) fn parse_time(value: &[u8]) -> Option<(u32, u32, u32, u32)> {
+  if !matches!(value.len(), 4 | 6 | 7 | 8) {
+    return None;
+  }
+  let hour = parse_digits(&value[0..2])?;
+  let minute = parse_digits(&value[2..4])?;
+  let second = if value.len() >= 6 { parse_digits(&value[4..6])? } else { 0 };
+  let fraction = match value.len() {
+    7 => parse_digits(&value[6..7])? * 10,
+    8 => parse_digits(&value[6..8])?,
+    _ => 0
+  };
+  if hour > 23 || minute > 59 || second > 59 {
+    return None;
+  }
+  Some((hour, minute, second, fraction))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{ElementValidator, SegmentDefinition, ElementDefinition, DataType, ViolationKind};
+  use crate::edi_segments::Segment;
+
+  fn segment(raw: &str) -> Segment {
+    let fields : Vec<Vec<u8>> = raw.split('*').map(|f| Vec::from(f.as_bytes())).collect();
+    Segment {
+      tag: fields[0].clone(),
+      fields,
+      start_offset: 120,
+      end_offset: 120 + raw.len() as u64,
+      segment_index: 4,
+      raw: Vec::from(raw.as_bytes()),
+      stray: Vec::new(),
+      line: 4812,
+      column: 1
+    }
+  }
+
+  fn validator() -> ElementValidator {
+    let mut v = ElementValidator::new();
+    v.add_segment(SegmentDefinition::new("DTP", vec![
+      ElementDefinition::new(DataType::ID, 3, 3).mandatory().with_code_list("374"),
+      ElementDefinition::new(DataType::ID, 2, 3).mandatory(),
+      ElementDefinition::new(DataType::DT, 6, 8).mandatory()
+    ]));
+    v.add_segment(SegmentDefinition::new("AMT", vec![
+      ElementDefinition::new(DataType::ID, 1, 3).mandatory(),
+      ElementDefinition::new(DataType::R, 1, 18).mandatory(),
+      ElementDefinition::new(DataType::N(2), 1, 4)
+    ]));
+    v.add_code_list("374", vec!["348", "349", "356"]);
+    v
+  }
+
+  #[test]
+  fn accepts_valid_segments() {
+    let v = validator();
+    assert!(v.validate(&segment("DTP*348*D8*20240229")).is_empty());
+    assert!(v.validate(&segment("AMT*R*-123.45*0150")).is_empty());
+  }
+
+  #[test]
+  fn reports_violations_with_positions() {
+    let v = validator();
+    let violations = v.validate(&segment("DTP*999**20230229*X"));
+    let kinds : Vec<ViolationKind> = violations.iter().map(|x| x.kind).collect();
+    assert_eq!(kinds, vec![
+      ViolationKind::TooManyElements,
+      ViolationKind::InvalidCodeValue,
+      ViolationKind::MandatoryElementMissing,
+      ViolationKind::InvalidDate
+    ]);
+    assert_eq!(violations[1].reference_designator(), "DTP-01");
+    assert_eq!(violations[1].segment_index, 4);
+    assert_eq!(violations[1].start_offset, 120);
+    assert_eq!(violations[1].to_string(), "line 4812, element DTP-01: InvalidCodeValue");
+    assert_eq!(violations[3].kind.syntax_error_code(), "8");
+  }
+
+  #[test]
+  fn checks_numeric_lengths_without_sign() {
+    let v = validator();
+    let violations = v.validate(&segment("AMT*R*1.2.3*-12345"));
+    let kinds : Vec<ViolationKind> = violations.iter().map(|x| x.kind).collect();
+    assert_eq!(kinds, vec![ViolationKind::InvalidCharacter, ViolationKind::TooLong]);
+  }
+}
@@ -0,0 +1,305 @@
+use crate::edi_common::{Separators, Name, Demographics};
+use crate::edi_elements::{EdiDate, EdiTime};
+use std::io::{Write, Error, ErrorKind};
+
+pub struct InterchangeHeader {
+  pub sender_qualifier: String,
+  pub sender_id: String,
+  pub receiver_qualifier: String,
+  pub receiver_id: String,
+  pub date: EdiDate,
+  pub time: EdiTime,
+  pub control_number: u64,
+  pub version: String,
+  pub acknowledgment_requested: bool,
+  pub test: bool
+}
+
+pub struct GroupHeader {
+  pub functional_id: String,
+  pub sender: String,
+  pub receiver: String,
+  pub date: EdiDate,
+  pub time: EdiTime,
+  pub control_number: u64,
+  pub version: String
+}
+
+fn format_date(date: &EdiDate) -> String {
+  format!("{:04}{:02}{:02}", date.year, date.month, date.day)
+}
+
+fn format_time(time: &EdiTime) -> String {
+  format!("{:02}{:02}", time.hour, time.minute)
+}
+
+// Writes X12 segments and keeps the envelope counts, so that SE, GE and IEA
+// come out right.  Trailing empty elements and components are dropped.
+pub struct X12Writer<W: Write> {
+  out: W,
+  element_delimiter: Vec<u8>,
+  segment_delimiter: Vec<u8>,
+  separators: Separators,
+  interchange: Option<u64>,
+  groups: u64,
+  group: Option<u64>,
+  transactions: u64,
+  transaction: Option<String>,
+  segments: u64
+}
+
+impl<W: Write> X12Writer<W> {
+  // `*` between elements and `~` and a line feed after each segment.
+  pub fn new(out: W) -> Self {
+    X12Writer::build(out, b"*".to_vec(), b"~\n".to_vec(), Separators::default())
+  }
+
+  // Both delimiters need at least one byte.
+  pub fn with_delimiters(out: W, element_delimiter: Vec<u8>, segment_delimiter: Vec<u8>, separators: Separators) -> Result<Self, Error> {
+    if element_delimiter.is_empty() || segment_delimiter.is_empty() {
+      return Err(Error::new(ErrorKind::InvalidInput, "the element and segment delimiters can not be empty"));
+    }
+    Ok(X12Writer::build(out, element_delimiter, segment_delimiter, separators))
+  }
+
+  fn build(out: W, element_delimiter: Vec<u8>, segment_delimiter: Vec<u8>, separators: Separators) -> Self {
+    X12Writer {
+      out,
+      element_delimiter,
+      segment_delimiter,
+      separators,
+      interchange: None,
+      groups: 0,
+      group: None,
+      transactions: 0,
+      transaction: None,
+      segments: 0
+    }
+  }
+
+  pub fn separators(&self) -> &Separators {
+    &self.separators
+  }
+
+  pub fn composite(&self, components: &[&str]) -> String {
+    let used = components.iter().rposition(|c| !c.is_empty()).map(|p| p + 1).unwrap_or(0);
+    components[..used].join(&char::from(self.separators.component).to_string())
+  }
+
+  // Without a repetition separator only the first value is kept.
+  pub fn repeated(&self, values: &[String]) -> String {
+    match self.separators.repetition {
+      Some(r) => values.join(&char::from(r).to_string()),
+      None => values.first().cloned().unwrap_or_default()
+    }
+  }
+
+  pub fn segment(&mut self, tag: &str, elements: &[&str]) -> Result<(), Error> {
+    let used = elements.iter().rposition(|e| !e.is_empty()).map(|p| p + 1).unwrap_or(0);
+    let mut bytes = tag.as_bytes().to_vec();
+    for element in elements[..used].iter() {
+      if contains(element.as_bytes(), &self.element_delimiter) || contains(element.as_bytes(), &self.segment_delimiter[..1]) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} element holds a delimiter: {}", tag, element)));
+      }
+      bytes.extend_from_slice(&self.element_delimiter);
+      bytes.extend_from_slice(element.as_bytes());
+    }
+    bytes.extend_from_slice(&self.segment_delimiter);
+    self.out.write_all(&bytes)?;
+    if self.transaction.is_some() {
+      self.segments += 1;
+    }
+    Ok(())
+  }
+
+  pub fn nm1(&mut self, name: &Name) -> Result<(), Error> {
+    let text = |v: &Option<String>| v.clone().unwrap_or_default();
+    self.segment("NM1", &[
+      &name.entity_code, &name.entity_type, &name.last_name,
+      &text(&name.first_name), &text(&name.middle_name), &text(&name.prefix), &text(&name.suffix),
+      &text(&name.id_qualifier), &text(&name.id)
+    ])
+  }
+
+  pub fn dmg(&mut self, demographics: &Demographics) -> Result<(), Error> {
+    let birth_date = demographics.birth_date.as_ref().map(format_date).unwrap_or_default();
+    self.segment("DMG", &[
+      if birth_date.is_empty() { "" } else { "D8" }, &birth_date,
+      demographics.gender.as_deref().unwrap_or(""), demographics.marital_status.as_deref().unwrap_or("")
+    ])
+  }
+
+  // ISA06 and ISA08 are fixed at 15 characters, so longer ids are rejected.
+  pub fn begin_interchange(&mut self, header: &InterchangeHeader) -> Result<(), Error> {
+    if self.interchange.is_some() {
+      return Err(out_of_order("ISA"));
+    }
+    for id in [&header.sender_id, &header.receiver_id] {
+      if id.chars().count() > 15 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("ISA id is longer than 15 characters: {}", id)));
+      }
+    }
+    let date = format_date(&header.date);
+    let repetition = self.separators.repetition.map(|r| char::from(r).to_string()).unwrap_or_else(|| String::from("U"));
+    let component = char::from(self.separators.component).to_string();
+    let control_number = format!("{:09}", header.control_number);
+    let sender = format!("{:<15}", header.sender_id);
+    let receiver = format!("{:<15}", header.receiver_id);
+    self.segment("ISA", &[
+      "00", "          ", "00", "          ",
+      &header.sender_qualifier, &sender, &header.receiver_qualifier, &receiver,
+      &date[2..], &format_time(&header.time), &repetition, &header.version, &control_number,
+      if header.acknowledgment_requested { "1" } else { "0" },
+      if header.test { "T" } else { "P" },
+      &component
+    ])?;
+    self.interchange = Some(header.control_number);
+    self.groups = 0;
+    Ok(())
+  }
+
+  pub fn end_interchange(&mut self) -> Result<(), Error> {
+    let control_number = match (self.interchange, self.group) {
+      (Some(c), None) => c,
+      _ => return Err(out_of_order("IEA"))
+    };
+    self.segment("IEA", &[&self.groups.to_string(), &format!("{:09}", control_number)])?;
+    self.interchange = None;
+    Ok(())
+  }
+
+  pub fn begin_group(&mut self, header: &GroupHeader) -> Result<(), Error> {
+    if self.interchange.is_none() || self.group.is_some() {
+      return Err(out_of_order("GS"));
+    }
+    self.segment("GS", &[
+      &header.functional_id, &header.sender, &header.receiver,
+      &format_date(&header.date), &format_time(&header.time),
+      &header.control_number.to_string(), "X", &header.version
+    ])?;
+    self.group = Some(header.control_number);
+    self.groups += 1;
+    self.transactions = 0;
+    Ok(())
+  }
+
+  pub fn end_group(&mut self) -> Result<(), Error> {
+    let control_number = match (self.group, &self.transaction) {
+      (Some(c), None) => c,
+      _ => return Err(out_of_order("GE"))
+    };
+    self.segment("GE", &[&self.transactions.to_string(), &control_number.to_string()])?;
+    self.group = None;
+    Ok(())
+  }
+
+  pub fn begin_transaction(&mut self, set_id: &str, control_number: &str, implementation: Option<&str>) -> Result<(), Error> {
+    if self.group.is_none() || self.transaction.is_some() {
+      return Err(out_of_order("ST"));
+    }
+    self.transaction = Some(control_number.to_string());
+    self.segments = 0;
+    self.transactions += 1;
+    self.segment("ST", &[set_id, control_number, implementation.unwrap_or("")])
+  }
+
+  pub fn end_transaction(&mut self) -> Result<(), Error> {
+    let control_number = match self.transaction.clone() {
+      None => return Err(out_of_order("SE")),
+      Some(c) => c
+    };
+    self.segment("SE", &[&(self.segments + 1).to_string(), &control_number])?;
+    self.transaction = None;
+    Ok(())
+  }
+
+  pub fn into_inner(self) -> W {
+    self.out
+  }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+  !needle.is_empty() && haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn out_of_order(tag: &str) -> Error {
+  Error::new(ErrorKind::InvalidInput, format!("{} is out of order in the envelope", tag))
+}
+
+#[cfg(test)]
+mod test {
+  use super::{X12Writer, InterchangeHeader, GroupHeader};
+  use crate::edi_common::Separators;
+  use crate::edi_elements::{EdiDate, EdiTime};
+  use crate::parser_impls::DefaultParser;
+  use crate::edi_parsers::{create_edi_streamer, execute_streaming_parser};
+  use std::io::{Cursor, ErrorKind};
+
+  fn interchange_header(sender_id: &str) -> InterchangeHeader {
+    InterchangeHeader {
+      sender_qualifier: String::from("ZZ"),
+      sender_id: String::from(sender_id),
+      receiver_qualifier: String::from("ZZ"),
+      receiver_id: String::from("RECEIVER"),
+      date: EdiDate { year: 2023, month: 1, day: 2 },
+      time: EdiTime { hour: 9, minute: 5, second: 0, hundredths: 0 },
+      control_number: 17,
+      version: String::from("00501"),
+      acknowledgment_requested: false,
+      test: true
+    }
+  }
+
+  #[test]
+  fn writes_envelopes() {
+    let mut writer = X12Writer::new(Vec::new());
+    let date = EdiDate { year: 2023, month: 1, day: 2 };
+    let time = EdiTime { hour: 9, minute: 5, second: 0, hundredths: 0 };
+    writer.begin_interchange(&interchange_header("SENDER")).unwrap();
+    writer.begin_group(&GroupHeader {
+      functional_id: String::from("HS"),
+      sender: String::from("SENDER"),
+      receiver: String::from("RECEIVER"),
+      date,
+      time,
+      control_number: 1,
+      version: String::from("005010X279A1")
+    }).unwrap();
+    writer.begin_transaction("270", "0001", Some("005010X279A1")).unwrap();
+    let eq = writer.composite(&["HC", "99213", "", ""]);
+    writer.segment("EQ", &["", &eq, "", ""]).unwrap();
+    assert_eq!(writer.segment("REF", &["EJ", "A*B"]).map_err(|e| e.kind()), Err(ErrorKind::InvalidInput));
+    writer.end_transaction().unwrap();
+    assert!(writer.end_interchange().is_err());
+    writer.end_group().unwrap();
+    writer.end_interchange().unwrap();
+    let raw = String::from_utf8(writer.into_inner()).unwrap();
+    assert_eq!(raw, "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230102*0905*^*00501*000000017*0*T*:~
+GS*HS*SENDER*RECEIVER*20230102*0905*1*X*005010X279A1~
+ST*270*0001*005010X279A1~
+EQ**HC:99213~
+SE*3*0001~
+GE*1*1~
+IEA*1*000000017~
+");
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let mut pi = match create_edi_streamer(&mut ioish) {
+      Ok(p) => p,
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    };
+    let mut parser = DefaultParser::new();
+    execute_streaming_parser(&mut pi, &mut parser);
+    assert_eq!(parser.transactions().count(), 1);
+  }
+
+  #[test]
+  fn rejects_what_can_not_be_written() {
+    let empty = X12Writer::with_delimiters(Vec::new(), b"*".to_vec(), Vec::new(), Separators::default());
+    assert_eq!(empty.err().map(|e| e.kind()), Some(ErrorKind::InvalidInput));
+    let mut writer = X12Writer::new(Vec::new());
+    let res = writer.begin_interchange(&interchange_header("A_SENDER_ID_TOO_LONG"));
+    assert_eq!(res.map_err(|e| e.kind()), Err(ErrorKind::InvalidInput));
+    assert!(writer.into_inner().is_empty());
+  }
+}
@@ -1,12 +1,69 @@
 pub use crate::edi_segments::ParserIterator;
 pub use crate::edi_segments::Segment;
-pub use crate::edi_parsers::create_edi_streamer;
+pub use crate::edi_parsers::{create_edi_streamer, create_edi_streamer_with_tag_rule};
 pub use crate::edi_parsers::StreamParser;
 pub use crate::edi_parsers::execute_streaming_parser;
-pub use crate::parser_impls::DefaultParser;
+pub use crate::edi_parsers::execute_lenient_streaming_parser;
+pub use crate::edi_recovery::{RecoveryDiagnostic, RecoveryKind, RecoveryCheckpoint, MAX_CONSECUTIVE_READ_ERRORS};
+pub use crate::parser_impls::{DefaultParser, SegmentRef, Interchange, FunctionalGroup, Transaction};
+pub use crate::edi_hierarchy::{HierarchyBuilder, HierarchyParser, HierarchyHandler, HierarchyTree, HierarchicalLevel, HierarchyError, HierarchyErrorKind};
+pub use crate::edi_validation::{ElementValidator, SegmentDefinition, ElementDefinition, DataType, CodeList, Violation, ViolationKind};
+pub use crate::edi_elements::{ElementValue, Decimal, EdiDate, EdiTime};
+pub use crate::edi_index::{EdiIndexer, EdiIndex, IndexedReader, InterchangeEntry, FunctionalGroupEntry, TransactionEntry, KeyElement};
+pub use crate::edi_parallel::{execute_parallel_parser, ParallelOptions, TransactionSet};
+pub use crate::edi_envelope::EnvelopeState;
+pub use crate::edi_tag_rules::{SegmentTagRule, is_x12_tag};
+pub use crate::edi_limits::{ParserLimits, LimitKind, LimitExceeded, LimitCounts};
+pub use crate::edi_source::{EdiSource, open_edi_source};
+pub use crate::edi_compression::{Compression, detect_compression, open_decompressed};
+#[cfg(feature = "zip")]
+pub use crate::edi_compression::for_each_zip_member;
+pub use crate::edi_batch::{BatchOptions, BatchReport, FileReport, collect_directory, collect_glob, execute_batch_parser};
+pub use crate::edi_common::{Separators, TransactionHandler, TransactionParser, Reference, DateReference, Amount, Name, Address, Contact, Party, Entity, Demographics};
+pub use crate::edi_enrollment::{EnrollmentParser, EnrollmentReader, EnrollmentHandler, EnrollmentHeader, Member, MemberName, Disability, Coverage, CoverageProvider, CoordinationOfBenefits};
+pub use crate::edi_claims::{ClaimParser, ClaimReader, ClaimHandler, ClaimParties, ClaimKind, Claim, BillingProvider, Subscriber, Patient, Diagnosis, ClaimCode, Procedure, Admission, OtherSubscriber, ServiceLine};
+pub use crate::edi_remittance::{RemittanceParser, RemittanceReader, RemittanceHandler, RemittanceHeader, RemittanceSummary, ClaimPayment, ServicePayment, Adjustment, ProviderAdjustment, BalanceError, BalanceErrorKind};
+pub use crate::edi_eligibility::{EligibilityParser, EligibilityReader, EligibilityHandler, EligibilityParties, EligibilityMember, EligibilityLevel, Benefit, ServiceInquiry, Rejection, EligibilityRequest, InquiryMember, write_eligibility_request};
+pub use crate::edi_claim_status::{ClaimStatusParser, ClaimStatusReader, ClaimStatusHandler, StatusParties, StatusClaim, ClaimStatus, StatusCode, ServiceStatus, ClaimStatusRequest, StatusMember, StatusInquiry, write_claim_status_request};
+pub use crate::edi_supply_chain::{SupplyChainParser, SupplyChainReader, SupplyChainHandler, SupplyChainDocument, PurchaseOrder, PurchaseOrderAck, ShipNotice, ShipmentLevel, Invoice, LineItem, LineAcknowledgment, Charge, TransactionTotals, TotalsError, TotalsErrorKind};
+pub use crate::edi_writer::{X12Writer, InterchangeHeader, GroupHeader};
+pub use crate::edi_redaction::{Redactor, RedactionRules, RedactionRule, Replacement};
+pub use crate::edi_diff::{DiffCollector, DiffDocument, DiffOptions, Difference, DifferenceKind, ElementChange, SegmentLocation, diff_documents};
+pub use crate::edi_query::{Query, QueryParser, QueryHandler, QueryMatch};
+pub use crate::edi_stats::{StatsCollector, StatsReport, TagStats, TransactionSize, TradingPartners};
+pub use crate::edi_checkpoint::{Checkpoint, TokenizerCheckpoint, EnvelopeCheckpoint, CheckpointingParser, execute_checkpointed_parser, resume_edi_streamer};
+#[cfg(feature = "async")]
+pub use crate::edi_async::{AsyncSegmentStream, AsyncStreamParser, create_async_edi_streamer, create_async_edi_streamer_with_tag_rule, execute_async_streaming_parser};
 
 mod edi_segments;
 mod edi_delimiters;
 mod edi_constants;
 mod edi_parsers;
-mod parser_impls;
\ No newline at end of file
+mod parser_impls;
+mod edi_envelope;
+mod edi_hierarchy;
+mod edi_validation;
+mod edi_elements;
+mod edi_index;
+mod edi_parallel;
+mod edi_checkpoint;
+mod edi_recovery;
+mod edi_tag_rules;
+mod edi_limits;
+mod edi_source;
+mod edi_compression;
+mod edi_batch;
+mod edi_common;
+mod edi_enrollment;
+mod edi_claims;
+mod edi_remittance;
+mod edi_eligibility;
+mod edi_claim_status;
+mod edi_supply_chain;
+mod edi_writer;
+mod edi_redaction;
+mod edi_diff;
+mod edi_query;
+mod edi_stats;
+#[cfg(feature = "async")]
+mod edi_async;
\ No newline at end of file
@@ -1,7 +1,6 @@
 use crate::edi_parsers::StreamParser;
 use crate::edi_segments::Segment;
-use std::sync::Arc;
-use core::cell::RefCell;
+use std::ops::Range;
 
 #[derive(PartialEq)]
 enum ParserState {
@@ -11,27 +10,263 @@ enum ParserState {
   InTransaction
 }
 
-struct Interchange {
-  functional_groups: Vec<Arc<FunctionalGroup>>,
-  segments: Vec<Arc<Segment>>
+// Every segment is stored exactly once, in `DefaultParser::segments`.  The
+// envelope structures only record which slice of that arena they cover.
+// The bytes of all segments live in one buffer, `DefaultParser::bytes`, and
+// a segment only records where its parts are in it.
+struct SegmentSlot {
+  tag: Range<usize>,
+  // Into `DefaultParser::field_ranges`.
+  fields: Range<usize>,
+  raw: Range<usize>,
+  stray: Range<usize>,
+  start_offset: u64,
+  end_offset: u64,
+  segment_index: u64,
+  line: u64,
+  column: u64
 }
 
-struct FunctionalGroup {
-  transactions: Vec<Arc<Transaction>>,
-  segments: Vec<Arc<Segment>>
+struct InterchangeRange {
+  segments: Range<usize>,
+  functional_groups: Range<usize>,
+  closed: bool
 }
 
-struct Transaction {
-  segments: Vec<Arc<Segment>>
+struct FunctionalGroupRange {
+  segments: Range<usize>,
+  transactions: Range<usize>,
+  closed: bool
+}
+
+struct TransactionRange {
+  segments: Range<usize>,
+  closed: bool
+}
+
+#[derive(Clone, Copy)]
+pub struct SegmentRef<'a> {
+  parser: &'a DefaultParser,
+  slot: &'a SegmentSlot
+}
+
+#[derive(Clone, Copy)]
+pub struct Interchange<'a> {
+  parser: &'a DefaultParser,
+  range: &'a InterchangeRange
+}
+
+#[derive(Clone, Copy)]
+pub struct FunctionalGroup<'a> {
+  parser: &'a DefaultParser,
+  range: &'a FunctionalGroupRange
+}
+
+#[derive(Clone, Copy)]
+pub struct Transaction<'a> {
+  parser: &'a DefaultParser,
+  range: &'a TransactionRange
+}
+
+impl<'a> SegmentRef<'a> {
+  pub fn tag(&self) -> &'a [u8] {
+    &self.parser.bytes[self.slot.tag.clone()]
+  }
+
+  // Field 0 is the tag, as in `Segment::fields`.
+  pub fn field(&self, index: usize) -> Option<&'a [u8]> {
+    self.fields().nth(index)
+  }
+
+  pub fn fields(&self) -> impl Iterator<Item = &'a [u8]> {
+    let bytes = &self.parser.bytes;
+    self.parser.field_ranges[self.slot.fields.clone()].iter().map(move |r| &bytes[r.clone()])
+  }
+
+  pub fn field_count(&self) -> usize {
+    self.slot.fields.len()
+  }
+
+  pub fn raw(&self) -> &'a [u8] {
+    &self.parser.bytes[self.slot.raw.clone()]
+  }
+
+  pub fn stray(&self) -> &'a [u8] {
+    &self.parser.bytes[self.slot.stray.clone()]
+  }
+
+  pub fn start_offset(&self) -> u64 {
+    self.slot.start_offset
+  }
+
+  pub fn end_offset(&self) -> u64 {
+    self.slot.end_offset
+  }
+
+  pub fn segment_index(&self) -> u64 {
+    self.slot.segment_index
+  }
+
+  pub fn line(&self) -> u64 {
+    self.slot.line
+  }
+
+  pub fn column(&self) -> u64 {
+    self.slot.column
+  }
+
+  // An owned copy, for the element accessors of `Segment`.
+  pub fn to_segment(&self) -> Segment {
+    Segment {
+      tag: self.tag().to_vec(),
+      fields: self.fields().map(|f| f.to_vec()).collect(),
+      start_offset: self.slot.start_offset,
+      end_offset: self.slot.end_offset,
+      segment_index: self.slot.segment_index,
+      raw: self.raw().to_vec(),
+      stray: self.stray().to_vec(),
+      line: self.slot.line,
+      column: self.slot.column
+    }
+  }
+}
+
+impl<'a> Interchange<'a> {
+  pub fn header(&self) -> Option<SegmentRef<'a>> {
+    self.parser.segments_in(&self.range.segments).next()
+  }
+
+  pub fn trailer(&self) -> Option<SegmentRef<'a>> {
+    closing_segment(self.parser, &self.range.segments, self.range.closed)
+  }
+
+  pub fn segments(&self) -> impl Iterator<Item = SegmentRef<'a>> {
+    self.parser.segments_in(&self.range.segments)
+  }
+
+  pub fn body(&self) -> impl Iterator<Item = SegmentRef<'a>> {
+    let nested : Vec<Range<usize>> = self.functional_groups().map(|g| g.range.segments.clone()).collect();
+    body_segments(self.parser, &self.range.segments, self.range.closed, nested)
+  }
+
+  pub fn functional_groups(&self) -> impl Iterator<Item = FunctionalGroup<'a>> {
+    let parser = self.parser;
+    parser.functional_groups[self.range.functional_groups.clone()].iter().map(move |range| FunctionalGroup { parser, range })
+  }
+
+  pub fn transactions(&self) -> impl Iterator<Item = Transaction<'a>> {
+    self.functional_groups().flat_map(|g| g.transactions())
+  }
+
+  pub fn sender_id(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 6)
+  }
+
+  pub fn receiver_id(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 8)
+  }
+
+  pub fn control_number(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 13)
+  }
+}
+
+impl<'a> FunctionalGroup<'a> {
+  pub fn header(&self) -> Option<SegmentRef<'a>> {
+    self.parser.segments_in(&self.range.segments).next()
+  }
+
+  pub fn trailer(&self) -> Option<SegmentRef<'a>> {
+    closing_segment(self.parser, &self.range.segments, self.range.closed)
+  }
+
+  pub fn segments(&self) -> impl Iterator<Item = SegmentRef<'a>> {
+    self.parser.segments_in(&self.range.segments)
+  }
+
+  pub fn body(&self) -> impl Iterator<Item = SegmentRef<'a>> {
+    let nested : Vec<Range<usize>> = self.transactions().map(|t| t.range.segments.clone()).collect();
+    body_segments(self.parser, &self.range.segments, self.range.closed, nested)
+  }
+
+  pub fn transactions(&self) -> impl Iterator<Item = Transaction<'a>> {
+    let parser = self.parser;
+    parser.transactions[self.range.transactions.clone()].iter().map(move |range| Transaction { parser, range })
+  }
+
+  pub fn functional_identifier(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 1)
+  }
+
+  pub fn control_number(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 6)
+  }
+
+  pub fn version(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 8)
+  }
+}
+
+impl<'a> Transaction<'a> {
+  pub fn header(&self) -> Option<SegmentRef<'a>> {
+    self.parser.segments_in(&self.range.segments).next()
+  }
+
+  pub fn trailer(&self) -> Option<SegmentRef<'a>> {
+    closing_segment(self.parser, &self.range.segments, self.range.closed)
+  }
+
+  pub fn segments(&self) -> impl Iterator<Item = SegmentRef<'a>> {
+    self.parser.segments_in(&self.range.segments)
+  }
+
+  pub fn body(&self) -> impl Iterator<Item = SegmentRef<'a>> {
+    body_segments(self.parser, &self.range.segments, self.range.closed, Vec::new())
+  }
+
+  pub fn transaction_set_code(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 1)
+  }
+
+  pub fn control_number(&self) -> Option<&'a [u8]> {
+    header_element(self.header(), 2)
+  }
+}
+
+fn closing_segment<'a>(parser: &'a DefaultParser, range: &Range<usize>, closed: bool) -> Option<SegmentRef<'a>> {
+  if closed {
+    parser.segments_in(range).last()
+  } else {
+    None
+  }
+}
+
+fn header_element(header: Option<SegmentRef<'_>>, index: usize) -> Option<&[u8]> {
+  header.and_then(|h| h.field(index))
+}
+
+fn body_segments<'a>(parser: &'a DefaultParser, range: &Range<usize>, closed: bool, nested: Vec<Range<usize>>) -> impl Iterator<Item = SegmentRef<'a>> {
+  let end = if closed { range.end.saturating_sub(1).max(range.start) } else { range.end };
+  let start = (range.start + 1).min(end);
+  // The nested ranges are in stream order and do not overlap, so one cursor
+  // walks them alongside the segments.
+  let mut nested = nested.into_iter().peekable();
+  (start..end)
+    .filter(move |idx| {
+      while nested.next_if(|n| n.end <= *idx).is_some() {}
+      !nested.peek().is_some_and(|n| n.contains(idx))
+    })
+    .map(move |idx| SegmentRef { parser, slot: &parser.segments[idx] })
 }
 
 pub struct DefaultParser {
   state: ParserState,
-  interchanges: Vec<Arc<Interchange>>,
-  current_interchange: Option<RefCell<Interchange>>,
-  current_functional_group: Option<RefCell<FunctionalGroup>>,
-  current_transaction: Option<RefCell<Transaction>>,
-  segments: Vec<Arc<Segment>>
+  interchanges: Vec<InterchangeRange>,
+  functional_groups: Vec<FunctionalGroupRange>,
+  transactions: Vec<TransactionRange>,
+  segments: Vec<SegmentSlot>,
+  field_ranges: Vec<Range<usize>>,
+  bytes: Vec<u8>
 }
 
 #[allow(clippy::new_without_default)]
@@ -40,108 +275,143 @@ impl DefaultParser {
     DefaultParser {
       state: ParserState::Nothing,
       interchanges: Vec::new(),
-      current_interchange: None,
-      current_functional_group: None,
-      current_transaction: None,
-      segments: Vec::new()
+      functional_groups: Vec::new(),
+      transactions: Vec::new(),
+      segments: Vec::new(),
+      field_ranges: Vec::new(),
+      bytes: Vec::new()
     }
   }
+
+  pub fn interchanges(&self) -> impl Iterator<Item = Interchange<'_>> {
+    self.interchanges.iter().map(move |range| Interchange { parser: self, range })
+  }
+
+  pub fn functional_groups(&self) -> impl Iterator<Item = FunctionalGroup<'_>> {
+    self.functional_groups.iter().map(move |range| FunctionalGroup { parser: self, range })
+  }
+
+  pub fn transactions(&self) -> impl Iterator<Item = Transaction<'_>> {
+    self.transactions.iter().map(move |range| Transaction { parser: self, range })
+  }
+
+  pub fn transactions_of_type<'a>(&'a self, transaction_set_code: &'a str) -> impl Iterator<Item = Transaction<'a>> {
+    self.transactions().filter(move |t| t.transaction_set_code() == Some(transaction_set_code.as_bytes()))
+  }
+
+  pub fn segments(&self) -> impl Iterator<Item = SegmentRef<'_>> {
+    self.segments_in(&(0..self.segments.len()))
+  }
+
+  fn segments_in(&self, range: &Range<usize>) -> impl Iterator<Item = SegmentRef<'_>> {
+    self.segments[range.clone()].iter().map(move |slot| SegmentRef { parser: self, slot })
+  }
+
+  fn store(&mut self, segment: &Segment) {
+    let start = self.bytes.len();
+    self.bytes.extend_from_slice(&segment.raw);
+    let raw = start..self.bytes.len();
+    let mut from = raw.start;
+    let first_field = self.field_ranges.len();
+    for field in segment.fields.iter() {
+      let range = place(&mut self.bytes, &raw, &mut from, field);
+      self.field_ranges.push(range);
+    }
+    let fields = first_field..self.field_ranges.len();
+    let tag = match self.field_ranges.get(first_field) {
+      Some(r) if self.bytes[r.clone()] == segment.tag[..] => r.clone(),
+      _ => append(&mut self.bytes, &segment.tag)
+    };
+    let stray = append(&mut self.bytes, &segment.stray);
+    self.segments.push(SegmentSlot {
+      tag,
+      fields,
+      raw,
+      stray,
+      start_offset: segment.start_offset,
+      end_offset: segment.end_offset,
+      segment_index: segment.segment_index,
+      line: segment.line,
+      column: segment.column
+    });
+  }
+}
+
+// Points at `value` where it next appears in the raw bytes of the segment,
+// from `from` on, and only copies it into the buffer when it is not there.
+fn place(bytes: &mut Vec<u8>, raw: &Range<usize>, from: &mut usize, value: &[u8]) -> Range<usize> {
+  match (*from..raw.end).find(|i| bytes[*i..raw.end].starts_with(value)) {
+    Some(i) => {
+      *from = i + value.len();
+      i..*from
+    },
+    None => append(bytes, value)
+  }
+}
+
+fn append(bytes: &mut Vec<u8>, value: &[u8]) -> Range<usize> {
+  let start = bytes.len();
+  bytes.extend_from_slice(value);
+  start..bytes.len()
 }
 
 impl StreamParser for DefaultParser {
   fn transaction_start(&mut self, _segment: &Segment) {
-    let trans = Transaction {
-      segments: Vec::new()
-    };
     self.state = ParserState::InTransaction;
-    let rc = RefCell::new(trans);
-    self.current_transaction = Some(rc);
+    let next_segment = self.segments.len();
+    self.transactions.push(TransactionRange {
+      segments: next_segment..next_segment,
+      closed: false
+    });
+    if let Some(fg) = self.functional_groups.last_mut() {
+      fg.transactions.end = self.transactions.len();
+    }
   }
 
-  fn transaction_end(&mut self, _segment: Option<&Segment>) {
+  fn transaction_end(&mut self, segment: Option<&Segment>) {
     self.state = ParserState::InFunctionalGroup;
-    match &self.current_functional_group {
-      None => (),
-      Some(fg) => {
-        match &self.current_transaction {
-          None => (),
-          Some(ct) => {
-            let ctb = ct.borrow();
-            let trans = Transaction {
-              segments: ctb.segments.clone()
-            };
-            let trc = Arc::new(trans);
-            let mut fgm = fg.borrow_mut();
-            fgm.transactions.push(trc);
-          }
-        }
-      }
+    if let Some(t) = self.transactions.last_mut() {
+      t.closed = segment.is_some();
     }
-    self.current_transaction = None;
   }
 
   fn functional_group_start(&mut self, _segment: &Segment) {
     self.state = ParserState::InFunctionalGroup;
-    let fg = FunctionalGroup {
-      transactions: Vec::new(),
-      segments: Vec::new()
-    };
-    let rc = RefCell::new(fg);
-    self.current_functional_group = Some(
-      rc
-    )
+    let next_segment = self.segments.len();
+    let next_transaction = self.transactions.len();
+    self.functional_groups.push(FunctionalGroupRange {
+      segments: next_segment..next_segment,
+      transactions: next_transaction..next_transaction,
+      closed: false
+    });
+    if let Some(i) = self.interchanges.last_mut() {
+      i.functional_groups.end = self.functional_groups.len();
+    }
   }
 
-  fn functional_group_end(&mut self, _segment: Option<&Segment>) {
+  fn functional_group_end(&mut self, segment: Option<&Segment>) {
     self.state = ParserState::InInterchange;
-    match &self.current_interchange {
-      None => (),
-      Some(ci) => {
-        match &self.current_functional_group {
-          None => (),
-          Some(fg) => {
-            let fgb = fg.borrow();
-            let new_group = FunctionalGroup {
-              transactions: fgb.transactions.clone(),
-              segments: fgb.segments.clone()
-            };
-            let tfg = Arc::new(new_group);
-            let mut cim = ci.borrow_mut();
-            cim.functional_groups.push(tfg);
-          }
-        }
-      }
+    if let Some(fg) = self.functional_groups.last_mut() {
+      fg.closed = segment.is_some();
     }
-    self.current_functional_group = None;
   }
 
   fn interchange_start(&mut self, _segment: &Segment) {
     self.state = ParserState::InInterchange;
-    let interchange = Interchange {
-      functional_groups: Vec::new(),
-      segments: Vec::new()
-    };
-    let rc = RefCell::new(interchange);
-    self.current_interchange = Some(
-      rc
-    )
+    let next_segment = self.segments.len();
+    let next_group = self.functional_groups.len();
+    self.interchanges.push(InterchangeRange {
+      segments: next_segment..next_segment,
+      functional_groups: next_group..next_group,
+      closed: false
+    });
   }
 
-  fn interchange_end(&mut self, _segment: Option<&Segment>) {
+  fn interchange_end(&mut self, segment: Option<&Segment>) {
     self.state = ParserState::Nothing;
-    match &self.current_interchange {
-      None => (),
-      Some(ci) => {
-        let cim = ci.borrow();
-        let interchange = Interchange {
-          segments: cim.segments.clone(),
-          functional_groups: cim.functional_groups.clone()
-        };
-        let interchange_rc = Arc::new(interchange);
-        self.interchanges.push(interchange_rc);
-      }
+    if let Some(i) = self.interchanges.last_mut() {
+      i.closed = segment.is_some();
     }
-    self.current_interchange = None;
   }
 
   fn error(&mut self, _error: std::io::Error) {
@@ -170,60 +440,21 @@ impl StreamParser for DefaultParser {
 }
 
 fn consume_segment(parser: &mut DefaultParser, segment: &Segment) {
-  let new_seg: Segment = Segment {
-    tag: segment.tag.clone(),
-    fields: segment.fields.clone(),
-    start_offset: segment.start_offset,
-    end_offset: segment.end_offset,
-    segment_index: segment.segment_index,
-    raw: segment.raw.clone()
-  };
-  let s_box: Arc<Segment> = Arc::new(new_seg);
-  parser.segments.push(s_box.clone());
+  parser.store(segment);
+  let end = parser.segments.len();
   if parser.in_transaction() {
-    match &mut parser.current_transaction {
-      None => (),
-      Some(ct) => {
-        let mut curr = ct.borrow_mut();
-        curr.segments.push(s_box.clone());
-      }
-    }
-    match &mut parser.current_functional_group {
-      None => (),
-      Some(fg) => {
-        let mut curr = fg.borrow_mut();
-        curr.segments.push(s_box.clone());
-      }
+    if let Some(t) = parser.transactions.last_mut() {
+      t.segments.end = end;
     }
-    match &mut parser.current_interchange {
-      None => (),
-      Some(ci) => {
-        let mut curr = ci.borrow_mut();
-        curr.segments.push(s_box);
-      }
-    }
-  } else if parser.in_functional_group() {
-    match &mut parser.current_functional_group {
-      None => (),
-      Some(fg) => {
-        let mut curr = fg.borrow_mut();
-        curr.segments.push(s_box.clone());
-      }
-    }
-    match &mut parser.current_interchange {
-      None => (),
-      Some(ci) => {
-        let mut curr = ci.borrow_mut();
-        curr.segments.push(s_box);
-      }
+  }
+  if parser.in_functional_group() {
+    if let Some(fg) = parser.functional_groups.last_mut() {
+      fg.segments.end = end;
     }
-  } else if parser.in_interchange() {
-    match &mut parser.current_interchange {
-      None => (),
-      Some(ci) => {
-        let mut curr = ci.borrow_mut();
-        curr.segments.push(s_box);
-      }
+  }
+  if parser.in_interchange() {
+    if let Some(i) = parser.interchanges.last_mut() {
+      i.segments.end = end;
     }
   }
 }
@@ -243,7 +474,7 @@ mod test {
     match edis {
       Ok(pi) => {
         execute_streaming_parser(pi, &mut dp);
-        assert_eq!("ISA", String::from_utf8_lossy(dp.interchanges[0].segments[0].tag.as_slice()));
+        assert_eq!("ISA", String::from_utf8_lossy(dp.interchanges().next().unwrap().segments().next().unwrap().tag()));
       },
       Err(_e) => panic!("FAILED TO CREATE PARSER")
     }
@@ -265,9 +496,9 @@ IEA~
     match edis {
       Ok(pi) => {
         execute_streaming_parser(pi, &mut dp);
-        assert_eq!("ISA", String::from_utf8_lossy(dp.interchanges[0].segments[0].tag.as_slice()));
-        assert_eq!(dp.interchanges[0].functional_groups.len(), 1);
-        assert_eq!(dp.interchanges[0].functional_groups[0].transactions.len(), 1);
+        assert_eq!("ISA", String::from_utf8_lossy(dp.interchanges().next().unwrap().segments().next().unwrap().tag()));
+        assert_eq!(dp.interchanges().next().unwrap().functional_groups().count(), 1);
+        assert_eq!(dp.interchanges().next().unwrap().functional_groups().next().unwrap().transactions().count(), 1);
       },
       Err(_e) => panic!("FAILED TO CREATE PARSER")
     }
@@ -303,12 +534,109 @@ IEA~
     match edis {
       Ok(pi) => {
         execute_streaming_parser(pi, &mut dp);
-        assert_eq!("ISA", String::from_utf8_lossy(dp.interchanges[0].segments[0].tag.as_slice()));
-        assert_eq!(dp.interchanges.len(), 6);
-        assert_eq!(dp.interchanges[4].functional_groups.len(), 2);
-        assert_eq!(dp.interchanges[5].functional_groups[0].transactions.len(), 2);
+        assert_eq!("ISA", String::from_utf8_lossy(dp.interchanges().next().unwrap().segments().next().unwrap().tag()));
+        assert_eq!(dp.interchanges().count(), 6);
+        assert_eq!(dp.interchanges().nth(4).unwrap().functional_groups().count(), 2);
+        assert_eq!(dp.interchanges().nth(5).unwrap().functional_groups().next().unwrap().transactions().count(), 2);
       },
       Err(_e) => panic!("FAILED TO CREATE PARSER")
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn public_model_test() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SENDER*RECEIVER*20230101*1200*1*X*005010X220A1~
+ST*834*0001~
+BGN*00*1*20230101~
+SE*3*0001~
+ST*820*0002~
+SE*2*0002~
+GE*2*1~
+IEA*1*000000001~
+";
+    let mut dp = DefaultParser::new();
+    let mut ioish = Cursor::new(raw.as_bytes());
+    let edis = &mut create_edi_streamer(&mut ioish);
+    match edis {
+      Ok(pi) => execute_streaming_parser(pi, &mut dp),
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    }
+    let interchange = dp.interchanges().next().unwrap();
+    assert_eq!(interchange.control_number(), Some("000000001".as_bytes()));
+    assert_eq!(interchange.trailer().map(|s| s.tag().to_vec()), Some(b"IEA".to_vec()));
+    assert_eq!(interchange.body().count(), 0);
+    let group = interchange.functional_groups().next().unwrap();
+    assert_eq!(group.functional_identifier(), Some("BE".as_bytes()));
+    assert_eq!(group.trailer().map(|s| s.tag().to_vec()), Some(b"GE".to_vec()));
+    assert_eq!(dp.transactions().count(), 2);
+    let enrollments : Vec<_> = dp.transactions_of_type("834").collect();
+    assert_eq!(enrollments.len(), 1);
+    assert_eq!(enrollments[0].control_number(), Some("0001".as_bytes()));
+    let body_tags : Vec<Vec<u8>> = enrollments[0].body().map(|s| s.tag().to_vec()).collect();
+    assert_eq!(body_tags, vec![b"BGN".to_vec()]);
+    assert_eq!(enrollments[0].trailer().map(|s| s.tag().to_vec()), Some(b"SE".to_vec()));
+  }
+
+  #[test]
+  fn segments_stored_once_test() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SENDER*RECEIVER*20230101*1200*1*X*005010X220A1~
+ST*834*0001~
+BGN*00*1*20230101~
+SE*3*0001~
+GE*1*1~
+IEA*1*000000001~
+";
+    let mut dp = DefaultParser::new();
+    let mut ioish = Cursor::new(raw.as_bytes());
+    match &mut create_edi_streamer(&mut ioish) {
+      Ok(pi) => execute_streaming_parser(pi, &mut dp),
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    }
+    assert_eq!(dp.segments.len(), 7);
+    assert_eq!(dp.interchanges[0].segments, 0..7);
+    assert_eq!(dp.functional_groups[0].segments, 1..6);
+    assert_eq!(dp.transactions[0].segments, 2..5);
+    assert_eq!(dp.bytes.len(), raw.len());
+    let transaction = dp.transactions().next().unwrap();
+    let bgn = transaction.body().next().unwrap();
+    let from_interchange = dp.interchanges().next().unwrap().segments().nth(3).unwrap();
+    assert!(std::ptr::eq(bgn.raw(), from_interchange.raw()));
+    assert_eq!(bgn.raw(), b"BGN*00*1*20230101~\n");
+    let fields : Vec<&[u8]> = bgn.fields().collect();
+    assert_eq!(fields, vec![b"BGN".as_slice(), b"00", b"1", b"20230101"]);
+    assert_eq!(bgn.to_segment().text(3), Some(String::from("20230101")));
+  }
+
+  #[test]
+  fn body_skips_nested_ranges_test() {
+    let raw = "\
+ISA*00*          *00*          *ZZ*SENDER         *ZZ*RECEIVER       *230101*1200*^*00501*000000001*0*P*:~
+GS*BE*SENDER*RECEIVER*20230101*1200*1*X*005010X220A1~
+NTE*A~
+ST*834*0001~
+BGN*00*1*20230101~
+SE*3*0001~
+NTE*B~
+ST*834*0002~
+SE*2*0002~
+ST*834*0003~
+SE*2*0003~
+NTE*C~
+GE*3*1~
+IEA*1*000000001~
+";
+    let mut dp = DefaultParser::new();
+    let mut ioish = Cursor::new(raw.as_bytes());
+    match &mut create_edi_streamer(&mut ioish) {
+      Ok(pi) => execute_streaming_parser(pi, &mut dp),
+      Err(_e) => panic!("FAILED TO CREATE PARSER")
+    }
+    let group = dp.interchanges().next().unwrap().functional_groups().next().unwrap();
+    let notes : Vec<Vec<u8>> = group.body().map(|s| s.field(1).unwrap().to_vec()).collect();
+    assert_eq!(notes, vec![b"A".to_vec(), b"B".to_vec(), b"C".to_vec()]);
+  }
+}